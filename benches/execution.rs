@@ -0,0 +1,215 @@
+// instructions per second of the decoded executor, compared with a straightforward
+// interpreter that matches on the opcode string of every instruction it executes.
+//
+// > cargo bench --bench execution
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use instreams::models::instruction::{Instruction};
+use instreams::services::executor::{Machine, parse_imdval, alu, is_alu, MEMORY_SIZE, REGISTER_COUNT, REG_SP, REG_LINK};
+
+const BUDGET: u64 = 10_000_000;
+
+fn ins(opcode: &str, imdval: &str, regsrc: u8, regext: u8, regdst: u8) -> Instruction {
+    Instruction { opcode: opcode.to_string(), imdval: imdval.to_string(), regsrc, regext, regdst }
+}
+
+// r1 counts down from 100000, mixing a few ALU operations into r2 and r3
+fn arithmetic_loop() -> Vec<Instruction> {
+    vec![
+        ins("add", "100000", 0, 0, 1),
+        ins("add", "0x3", 2, 0, 2),
+        ins("xor", "0x", 2, 1, 3),
+        ins("mul", "0x5", 3, 0, 3),
+        ins("shr", "0x1", 3, 0, 2),
+        ins("sub", "0x1", 1, 0, 1),
+        ins("bne", "0x1", 1, 0, 0),
+        ins("halt", "0x", 0, 0, 0),
+    ]
+}
+
+// walks a 4k buffer 20 times, storing and reloading every word
+fn memory_loop() -> Vec<Instruction> {
+    vec![
+        ins("add", "20", 0, 0, 4),
+        ins("add", "0", 0, 0, 1),
+        ins("add", "4096", 0, 0, 2),
+        ins("st", "0x", 1, 1, 0),
+        ins("ld", "0x", 1, 0, 3),
+        ins("add", "0x8", 1, 0, 1),
+        ins("blt", "0x3", 1, 2, 0),
+        ins("sub", "0x1", 4, 0, 4),
+        ins("bne", "0x1", 4, 0, 0),
+        ins("halt", "0x", 0, 0, 0),
+    ]
+}
+
+// calls a tiny function 50000 times
+fn call_loop() -> Vec<Instruction> {
+    vec![
+        ins("add", "50000", 0, 0, 1),
+        ins("call", "0x5", 0, 0, 0),
+        ins("sub", "0x1", 1, 0, 1),
+        ins("bne", "0x1", 1, 0, 0),
+        ins("halt", "0x", 0, 0, 0),
+        ins("add", "0x1", 2, 0, 2),
+        ins("ret", "0x", 0, 0, 0),
+    ]
+}
+
+// the baseline: everything is looked up and parsed again on every step
+struct NaiveMachine {
+    code: Vec<Instruction>,
+    registers: [u64; REGISTER_COUNT],
+    memory: Vec<u8>,
+    pc: usize,
+    steps: u64,
+    halted: bool,
+}
+
+impl NaiveMachine {
+
+    fn new(code: Vec<Instruction>) -> NaiveMachine {
+        let mut registers = [0; REGISTER_COUNT];
+        registers[REG_SP as usize] = MEMORY_SIZE as u64;
+        NaiveMachine { code, registers, memory: vec![0; MEMORY_SIZE], pc: 0, steps: 0, halted: false }
+    }
+
+    fn reg(&self, index: u8) -> Result<u64, String> {
+        self.registers.get(index as usize).copied().ok_or(format!("invalid register r{}", index))
+    }
+
+    fn set_reg(&mut self, index: u8, value: u64) -> Result<(), String> {
+        if index as usize >= REGISTER_COUNT {
+            return Err(format!("invalid register r{}", index));
+        }
+        if index != 0 {
+            self.registers[index as usize] = value;
+        }
+        Ok(())
+    }
+
+    fn address(&self, base: u8, offset: Option<i64>, size: usize) -> Result<usize, String> {
+        let address = self.reg(base)?.wrapping_add(offset.unwrap_or(0) as u64);
+        if address.checked_add(size as u64).is_none_or(|end| end > self.memory.len() as u64) {
+            return Err(format!("memory access out of bounds at 0x{:x}", address));
+        }
+        Ok(address as usize)
+    }
+
+    fn target(&self, imdval: Option<i64>) -> Result<usize, String> {
+        match imdval {
+            Some(target) if target >= 0 && (target as usize) <= self.code.len() => Ok(target as usize),
+            _ => Err("branch target out of range".to_string()),
+        }
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        if self.pc >= self.code.len() {
+            self.halted = true;
+            return Ok(());
+        }
+
+        let pc = self.pc;
+        let instruction = self.code[pc].clone();
+        let imdval = parse_imdval(&instruction.imdval)?;
+        let src = self.reg(instruction.regsrc)?;
+        let operand = match imdval {
+            Some(value) => value as u64,
+            None => self.reg(instruction.regext)?,
+        };
+        let mut next_pc = pc + 1;
+
+        match instruction.opcode.as_str() {
+            "nop" => {},
+            "halt" => {
+                self.halted = true;
+                next_pc = pc;
+            },
+            opcode if is_alu(opcode) => self.set_reg(instruction.regdst, alu(opcode, src, operand)?)?,
+            "ld" => {
+                let address = self.address(instruction.regsrc, imdval, 8)?;
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&self.memory[address..address + 8]);
+                self.set_reg(instruction.regdst, u64::from_le_bytes(bytes))?;
+            },
+            "st" => {
+                let address = self.address(instruction.regsrc, imdval, 8)?;
+                let value = self.reg(instruction.regext)?.to_le_bytes();
+                self.memory[address..address + 8].copy_from_slice(&value);
+            },
+            "beq" | "bne" | "blt" | "bge" => {
+                let rhs = self.reg(instruction.regext)?;
+                let taken = match instruction.opcode.as_str() {
+                    "beq" => src == rhs,
+                    "bne" => src != rhs,
+                    "blt" => (src as i64) < (rhs as i64),
+                    _ => (src as i64) >= (rhs as i64),
+                };
+                if taken {
+                    next_pc = self.target(imdval)?;
+                }
+            },
+            "jmp" => next_pc = self.target(imdval)?,
+            "call" => {
+                let target = self.target(imdval)?;
+                self.set_reg(REG_LINK, (pc + 1) as u64)?;
+                next_pc = target;
+            },
+            "ret" => next_pc = self.target(Some(self.reg(REG_LINK)? as i64))?,
+            opcode => return Err(format!("illegal opcode '{}'", opcode)),
+        }
+
+        self.steps += 1;
+        self.pc = next_pc;
+        Ok(())
+    }
+
+    fn run(&mut self, budget: u64) {
+        while !self.halted && self.steps < budget {
+            self.step().expect("benchmark programs do not fault");
+        }
+    }
+}
+
+fn bench_execution(c: &mut Criterion) {
+
+    let workloads = [
+        ("arithmetic_loop", arithmetic_loop()),
+        ("memory_loop", memory_loop()),
+        ("call_loop", call_loop()),
+    ];
+
+    for (name, program) in workloads {
+
+        // both engines have to agree before their speed is worth comparing
+        let mut reference = NaiveMachine::new(program.clone());
+        reference.run(BUDGET);
+        let mut machine = Machine::new(program.clone());
+        machine.run(BUDGET);
+        assert!(reference.halted);
+        assert_eq!(reference.registers, machine.registers);
+        assert_eq!(reference.steps, machine.steps);
+
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(machine.steps));
+        group.sample_size(20);
+
+        group.bench_function("naive", |b| b.iter(|| {
+            let mut machine = NaiveMachine::new(program.clone());
+            machine.run(BUDGET);
+            machine.steps
+        }));
+
+        group.bench_function("decoded", |b| b.iter(|| {
+            let mut machine = Machine::new(program.clone());
+            machine.run(BUDGET);
+            machine.steps
+        }));
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_execution);
+criterion_main!(benches);
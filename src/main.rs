@@ -1,20 +1,30 @@
-mod models;
-mod services;
-mod routes;
-
 use std::env;
-use std::fmt;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 
 use std::net::{TcpListener, SocketAddr};
 use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder, HttpRequest};
 
-use crate::routes::session::{hello, status, session_key};
-use crate::routes::worker::{execute, send_command, };
-use crate::services::state::{InstreamState};
-use crate::models::instruction::{ProgramSource};
-use crate::models::command::{WorkersEnum, ResponseMessage};
+use instreams::routes::session::{hello, status, session_key};
+use instreams::routes::worker::{execute, send_command, };
+use instreams::routes::run::{run_program, start_run, get_run, stop_run, run_console, run_input, resume_run, litmus};
+use instreams::routes::analysis::{analyze_program, optimize_program};
+use instreams::routes::events::{events};
+use instreams::routes::channel::{channel};
+use instreams::routes::code::{patch_instruction, insert_instructions, delete_instruction};
+use instreams::routes::programs::{program_history, program_diff, rollback_program};
+use instreams::routes::library::{list_library, get_library_program, save_library_program, load_library_program};
+use instreams::routes::assembler::{assemble_program};
+use instreams::services::state::{InstreamState};
+use instreams::services::events::{EventBus};
+use instreams::services::history::{ProgramHistory, session_of};
+use instreams::services::library::{ProgramLibrary};
+use instreams::services::persistence::{StateStore, restore, start_saver};
+use instreams::models::history::{SessionQuery, VersionOrigin};
+use instreams::models::instruction::{ProgramSource, ListQuery};
+use instreams::services::dot::{render_dot};
+use instreams::models::command::{ResponseMessage};
 
 // test usage: ( start executable with: "args": ["-s", "127.0.0.1:8082"] )
 // or use: > cargo run -- -s (selects random port)
@@ -23,25 +33,6 @@ use crate::models::command::{WorkersEnum, ResponseMessage};
 //> curl --header "Content-Type: application/json" --request POST --data '{"key": "{session_key}", "message": "StartWorker10ms"}' localhost:8082/work
 //> curl --header "Content-Type: application/json" --request POST --data '{"key": "{session_key}", "receiver": "Worker10", "command": "UpdateStatus"}' localhost:8082/command       
 
-impl fmt::Display for WorkersEnum {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        
-        // Implement how each variant should be displayed
-        match self {
-            WorkersEnum::StartWorker10ms => write!(f, "StartWorker10ms"),
-            WorkersEnum::StartWorker25ms => write!(f, "StartWorker25ms"),
-            WorkersEnum::StartWorker50ms => write!(f, "StartWorker50ms"),
-            WorkersEnum::StartWorker100ms => write!(f, "StartWorker100ms"),
-            WorkersEnum::StartWorker250ms => write!(f, "StartWorker250ms"),
-            WorkersEnum::StopWorker10ms => write!(f, "StopWorker10ms"),
-            WorkersEnum::StopWorker25ms => write!(f, "StopWorker25ms"),
-            WorkersEnum::StopWorker50ms => write!(f, "StopWorker50ms"),
-            WorkersEnum::StopWorker100ms => write!(f, "StopWorker100ms"),
-            WorkersEnum::StopWorker250ms => write!(f, "StopWorker250ms"),
-        }
-    }
-}
-
 // study:
 /*
  http://alanclements.org/power%20pc.html
@@ -82,14 +73,22 @@ impl fmt::Display for WorkersEnum {
 */
 
 // copy-paste: curl --header "Content-Type: application/json" --request POST --data '{"instructions":[{"opcode": "add","imdval": "0x","regsrc": 1,"regext": 0,"regdst": 2}, {"opcode": "sub","imdval": "0x","regsrc": 3,"regext": 0,"regdst": 4}]}' http://localhost:8082/load --verbose
+#[allow(clippy::needless_return)]
 #[post("/load")]
-async fn load_program(payload: web::Json<ProgramSource>, 
+async fn load_program(payload: web::Json<ProgramSource>, query: web::Query<SessionQuery>,
                 _req:HttpRequest, data: web::Data<Arc<InstreamState>>) -> impl Responder {
 
                     let mut instructions = data.code_segment.lock().unwrap();
 
                     // move instructions to the shared area
-                    *instructions = payload.instructions.iter().cloned().collect();
+                    *instructions = payload.instructions.to_vec();
+                    *data.last_run.lock().unwrap() = None;
+
+                    // ?key=<session key> names the session in GET /programs/history
+                    let session = session_of(query.key.as_deref(), &data.master_key.lock().unwrap());
+                    data.history.lock().unwrap().record(&instructions, VersionOrigin::Load, session);
+                    // the program is kept across restarts
+                    data.store.changed();
 
                     return HttpResponse::Ok().json(ResponseMessage {
                         message: "Program Loaded.".to_string(),
@@ -101,14 +100,35 @@ async fn load_program(payload: web::Json<ProgramSource>,
 // usage example:
 // > curl http://localhost:8081/list 
 // > {"instructions":[{"opcode":"add","imdval":"0x","regsrc":1,"regext":0,"regdst":2},{"opcode":"sub","imdval":"0x","regsrc":3,"regext":0,"regdst":4}]}
+//
+// control flow graph in graphviz format, annotated with the execution counts of the last run
+// (loading or editing the program drops them, "counts=false" leaves them out):
+// > curl "http://localhost:8081/list?format=dot" | dot -Tsvg > program.svg
+#[allow(clippy::needless_return)]
 #[get("/list")]
-async fn list_program(data: web::Data<Arc<InstreamState>>) -> impl Responder {
+async fn list_program(query: web::Query<ListQuery>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
 
                     let instructions = data.code_segment.lock().unwrap();
 
-                    return HttpResponse::Ok().json(ProgramSource {
-                        instructions: instructions.iter().cloned().collect(),
-                    })
+                    match query.format.as_deref().unwrap_or("json") {
+                        "json" => {
+                            return HttpResponse::Ok().json(ProgramSource {
+                                instructions: instructions.iter().cloned().collect(),
+                            })
+                        },
+                        "dot" => {
+                            let last_run = data.last_run.lock().unwrap();
+                            let counts = last_run.as_ref().map(|run| run.counts.as_slice());
+                            return HttpResponse::Ok()
+                                .content_type("text/vnd.graphviz")
+                                .body(render_dot(&instructions, counts, query.counts.unwrap_or(true)))
+                        },
+                        format => {
+                            return HttpResponse::BadRequest().json(ResponseMessage {
+                                message: format!("::: unknown format '{}'", format),
+                            })
+                        }
+                    }
 }
 
 // run server with 
@@ -117,6 +137,7 @@ async fn list_program(data: web::Data<Arc<InstreamState>>) -> impl Responder {
 // or 
 // > ./instreams -s 127.0.0.1:8081 
 // (specify ip address and port from command line)
+#[allow(clippy::never_loop)]
 #[actix_rt::main]
 async fn main() -> std::io::Result<()>{
 
@@ -151,12 +172,28 @@ async fn main() -> std::io::Result<()>{
                     let sender100_clone = sender100.clone();
                     let sender250_clone = sender250.clone();
 
+                    // programs saved by earlier runs of the server
+                    let library = match ProgramLibrary::from_environment() {
+                        Ok(library) => library,
+                        Err(error) => {
+                            eprintln!("::: Failed to open the program library: {}", error);
+                            return Ok(())
+                        }
+                    };
+
                     let stream_state = web::Data::new(Arc::new(InstreamState {
                         
                         
                         // these could be replaced by ..Default::default()
                         master_key: Mutex::new(0.to_string()),
                         code_segment: Vec::new().into(),
+                        last_run: Mutex::new(None),
+                        runs: Mutex::new(HashMap::new()),
+                        events: EventBus::new(),
+                        history: Mutex::new(ProgramHistory::new()),
+                        library: Mutex::new(library),
+                        pending_commands: Mutex::new(HashMap::new()),
+                        store: StateStore::from_environment(),
 
                         worker10running: Mutex::new(false),
                         worker25running: Mutex::new(false),
@@ -180,6 +217,10 @@ async fn main() -> std::io::Result<()>{
                     
                     }));
 
+                    // session key, program, workers and pending commands of the last run of the server
+                    restore(stream_state.clone());
+                    start_saver(stream_state.get_ref().clone());
+
                     let tcp_listener = match TcpListener::bind(socket_address) {
                             Ok(listener) => listener,
                             Err(error) => {
@@ -207,7 +248,30 @@ async fn main() -> std::io::Result<()>{
                                                         .service(send_command)
                                                         .service(session_key)
                                                         .service(load_program)
-                                                        .service(list_program))
+                                                        .service(list_program)
+                                                        .service(patch_instruction)
+                                                        .service(insert_instructions)
+                                                        .service(delete_instruction)
+                                                        .service(program_history)
+                                                        .service(program_diff)
+                                                        .service(rollback_program)
+                                                        .service(list_library)
+                                                        .service(get_library_program)
+                                                        .service(save_library_program)
+                                                        .service(load_library_program)
+                                                        .service(assemble_program)
+                                                        .service(run_program)
+                                                        .service(start_run)
+                                                        .service(get_run)
+                                                        .service(stop_run)
+                                                        .service(run_console)
+                                                        .service(run_input)
+                                                        .service(resume_run)
+                                                        .service(litmus)
+                                                        .service(events)
+                                                        .service(channel)
+                                                        .service(analyze_program)
+                                                        .service(optimize_program))
                                                         .listen(tcp_listener)?;
                     let _ = server.run()
                     .await;
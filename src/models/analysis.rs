@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Taken,
+    FallThrough,
+    Jump,
+    Call,
+    Return,
+    // ivec to the handler it installs
+    Interrupt,
+    // xvec to the handler it installs
+    Exception,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Edge {
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+// instructions 'start' up to and including 'end'
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BasicBlock {
+    pub id: usize,
+    pub start: usize,
+    pub end: usize,
+    pub reachable: bool,
+    pub successors: Vec<Edge>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum DiagnosticKind {
+    InvalidInstruction,
+    UnreachableInstruction,
+    ReadBeforeWrite,
+    DeadStore,
+    ReservedRegisterWrite,
+    BranchOutOfRange,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Diagnostic {
+    pub index: usize,
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AnalysisReport {
+    pub blocks: Vec<BasicBlock>,
+    pub diagnostics: Vec<Diagnostic>,
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::models::instruction::{Instruction};
+
+// body of POST /assemble
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssembleRequest {
+    pub source: String,
+    // replace the loaded program with the assembled one, like /load
+    pub load: Option<bool>,
+}
+
+// where an instruction (or an error) comes from. 'line' and 'text' are the line of the
+// source as the user wrote it, 'via' the macros, pseudo-instructions and included programs
+// it went through, outermost first, e.g. ["macro 'inc' at line 3", "pseudo-instruction 'li'"]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SourceRef {
+    pub line: usize,
+    pub text: String,
+    pub via: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssemblyError {
+    pub source: SourceRef,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssembleResult {
+    pub instructions: Vec<Instruction>,
+    // one entry per instruction
+    pub source_map: Vec<SourceRef>,
+    pub labels: BTreeMap<String, usize>,
+    pub loaded: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssembleErrors {
+    pub message: String,
+    pub errors: Vec<AssemblyError>,
+}
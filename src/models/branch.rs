@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+fn default_table_bits() -> u32 {
+    10
+}
+
+// 'table_bits' sets the number of counters (2^table_bits) indexed by the branch pc.
+//
+// "StaticTaken", {"TwoBit": {"table_bits": 8}}, {"Gshare": {"history_bits": 6, "table_bits": 10}}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum PredictorConfig {
+    StaticTaken,
+    StaticNotTaken,
+    OneBit {
+        #[serde(default = "default_table_bits")]
+        table_bits: u32,
+    },
+    TwoBit {
+        #[serde(default = "default_table_bits")]
+        table_bits: u32,
+    },
+    Gshare {
+        history_bits: u32,
+        #[serde(default = "default_table_bits")]
+        table_bits: u32,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct BranchStats {
+    pub executed: u64,
+    pub taken: u64,
+    pub correct: u64,
+    pub accuracy: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BranchSiteStats {
+    pub index: usize,
+    pub stats: BranchStats,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BranchReport {
+    pub predictor: PredictorConfig,
+    pub total: BranchStats,
+    pub per_site: Vec<BranchSiteStats>,
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ReplacementPolicy {
+    Lru,
+    Fifo,
+    Random,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum WritePolicy {
+    WriteBack,
+    WriteThrough,
+}
+
+fn default_write_allocate() -> bool {
+    true
+}
+
+// one cache level. sizes are in bytes, 'associativity' is the number of ways per set
+// (use size / line_size for a fully associative cache).
+//
+// {"size": 1024, "associativity": 2, "line_size": 32, "replacement": "Lru", "write_policy": "WriteBack"}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CacheConfig {
+    pub size: usize,
+    pub associativity: usize,
+    pub line_size: usize,
+    pub replacement: ReplacementPolicy,
+    pub write_policy: WritePolicy,
+    #[serde(default = "default_write_allocate")]
+    pub write_allocate: bool,
+    // only used by ReplacementPolicy::Random, same seed => same evictions
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct AddressRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+// the cache hierarchy selected for a run: split L1 instruction / data caches backed by
+// an optional unified L2. any level may be left out.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CacheSetup {
+    #[serde(default)]
+    pub icache: Option<CacheConfig>,
+    #[serde(default)]
+    pub dcache: Option<CacheConfig>,
+    #[serde(default)]
+    pub l2: Option<CacheConfig>,
+    // address ranges (end exclusive) to report statistics for
+    #[serde(default)]
+    pub ranges: Vec<AddressRange>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub writebacks: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InstructionCacheStats {
+    pub index: usize,
+    pub stats: CacheStats,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RangeCacheStats {
+    pub range: AddressRange,
+    pub stats: CacheStats,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CacheLevelReport {
+    pub config: CacheConfig,
+    pub total: CacheStats,
+    pub per_instruction: Vec<InstructionCacheStats>,
+    pub per_range: Vec<RangeCacheStats>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CacheReport {
+    pub icache: Option<CacheLevelReport>,
+    pub dcache: Option<CacheLevelReport>,
+    pub l2: Option<CacheLevelReport>,
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::command::{CommandMessage, RequestMessage};
+use crate::models::event::{Event};
+
+// a message sent to the websocket channel, the payload of /command or of /work
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ControlMessage {
+    Command(CommandMessage),
+    Work(RequestMessage),
+}
+
+// a message pushed by the websocket channel. 'ok' is false for a rejected key or a message
+// that could not be read.
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum ChannelMessage {
+    Response { ok: bool, message: String },
+    Event(Event),
+}
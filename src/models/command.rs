@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Debug, Deserialize)]
@@ -88,6 +89,25 @@ impl FromStr for WorkersEnum {
     }
 }
 
+impl fmt::Display for WorkersEnum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        
+        // Implement how each variant should be displayed
+        match self {
+            WorkersEnum::StartWorker10ms => write!(f, "StartWorker10ms"),
+            WorkersEnum::StartWorker25ms => write!(f, "StartWorker25ms"),
+            WorkersEnum::StartWorker50ms => write!(f, "StartWorker50ms"),
+            WorkersEnum::StartWorker100ms => write!(f, "StartWorker100ms"),
+            WorkersEnum::StartWorker250ms => write!(f, "StartWorker250ms"),
+            WorkersEnum::StopWorker10ms => write!(f, "StopWorker10ms"),
+            WorkersEnum::StopWorker25ms => write!(f, "StopWorker25ms"),
+            WorkersEnum::StopWorker50ms => write!(f, "StopWorker50ms"),
+            WorkersEnum::StopWorker100ms => write!(f, "StopWorker100ms"),
+            WorkersEnum::StopWorker250ms => write!(f, "StopWorker250ms"),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct CommandMessage {
     pub key: String,
@@ -0,0 +1,55 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+// the memory model of a run, see services/consistency.rs
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum MemoryModel {
+    // every store is visible to all cores at once
+    #[default]
+    Sequential,
+    // total store order (x86): stores wait in a FIFO store buffer per core
+    Tso,
+    // PowerPC / ARM like: stores to different addresses leave the buffer in any order
+    Relaxed,
+}
+
+// POST /litmus explores every interleaving of the loaded program on 'cores' cores.
+// 'observe' names what makes up an outcome: "1:r3" is r3 of core 1, "[0x100]" the 64 bit
+// word at 0x100 once all stores are visible. 'memory' holds initial words by address,
+// 'exists' is a condition on the observed values, like the exists clause of a litmus test.
+#[derive(Debug, Deserialize)]
+pub struct LitmusRequest {
+    pub cores: usize,
+    #[serde(default)]
+    pub memory_model: MemoryModel,
+    pub observe: Vec<String>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub exists: Option<BTreeMap<String, u64>>,
+    // bounds of the search, states beyond them are not explored. they default to
+    // DEFAULT_MAX_STATES and DEFAULT_MAX_STEPS and can not be raised past MAX_STATES and MAX_STEPS.
+    #[serde(default)]
+    pub max_states: Option<usize>,
+    #[serde(default)]
+    pub max_steps: Option<u64>,
+}
+
+// 'states' is the number of distinct final states with these values
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LitmusOutcome {
+    pub values: BTreeMap<String, u64>,
+    pub fault: Option<String>,
+    pub states: u64,
+}
+
+// 'complete' is false when a bound cut the search short, the outcomes may then be missing some
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LitmusResult {
+    pub memory_model: MemoryModel,
+    pub outcomes: Vec<LitmusOutcome>,
+    pub states: usize,
+    pub complete: bool,
+    // whether an outcome satisfies 'exists'
+    pub exists: Option<bool>,
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GpioReport {
+    pub output: u64,
+    pub input: u64,
+    // bit n set <=> pin n is an output
+    pub direction: u64,
+}
+
+// body of POST /runs/{id}/input. 'text' is queued as utf-8, every number of 'numbers' as
+// 8 little endian bytes (one ld worth), 'close' ends the stream after the data is queued.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct InputRequest {
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+    #[serde(default)]
+    pub numbers: Option<Vec<i64>>,
+    #[serde(default)]
+    pub close: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InputStatus {
+    // bytes queued that the program has not read yet
+    pub pending: usize,
+    pub closed: bool,
+}
+
+// query of GET /runs/{id}/console, 'follow=true' keeps the response open and streams
+// the output while the run is live
+#[derive(Debug, Deserialize)]
+pub struct ConsoleQuery {
+    pub follow: Option<bool>,
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    WorkerStarted,
+    WorkerStopped,
+    // published once a second by a running worker, the message is its number of ticks
+    Tick,
+    // a command received by a worker's msg_loop
+    Command,
+    Halt,
+    Fault,
+    Breakpoint,
+    Console,
+}
+
+impl EventKind {
+    pub fn from_name(name: &str) -> Option<EventKind> {
+        match name {
+            "WorkerStarted" => Some(EventKind::WorkerStarted),
+            "WorkerStopped" => Some(EventKind::WorkerStopped),
+            "Tick" => Some(EventKind::Tick),
+            "Command" => Some(EventKind::Command),
+            "Halt" => Some(EventKind::Halt),
+            "Fault" => Some(EventKind::Fault),
+            "Breakpoint" => Some(EventKind::Breakpoint),
+            "Console" => Some(EventKind::Console),
+            _ => None,
+        }
+    }
+}
+
+// 'worker' is set for worker events ("Worker10" ...), 'run' for run events
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Event {
+    pub id: u64,
+    pub kind: EventKind,
+    pub worker: Option<String>,
+    pub run: Option<String>,
+    pub message: String,
+}
+
+// query of GET /events. 'worker' and 'kinds' (comma separated) narrow down the stream.
+#[derive(Debug, Deserialize)]
+pub struct EventQuery {
+    pub key: String,
+    pub worker: Option<String>,
+    pub kinds: Option<String>,
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::instruction::{Instruction};
+
+// what went wrong, the number in brackets is the cause a handler reads with xcause
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ExceptionKind {
+    // an opcode, register or target the decoder rejected (0)
+    IllegalInstruction,
+    // div or rem by zero (1)
+    DivisionByZero,
+    // a ld, st, atomic or device access that is not naturally aligned (2)
+    MisalignedAccess,
+    // outside of the data memory, or a device register that does not take the access (3)
+    AccessFault,
+    // an access through sp below the stack of the core (4)
+    StackOverflow,
+    // everything else an instruction can refuse to do: iret / xret outside of a handler,
+    // a bad interrupt line or rounding mode, ret out of range (5)
+    InvalidOperation,
+    // user mode accessed memory or code the mpu does not give it (6)
+    ProtectionFault,
+    // user mode executed a supervisor opcode (7)
+    PrivilegedInstruction,
+    // an ecall in user mode, when a handler is installed for it (8)
+    Syscall,
+    // a virtual address without a mapping that permits the access (9)
+    PageFault,
+    // the run used up its budget, no handler can catch it
+    BudgetExhausted,
+}
+
+impl ExceptionKind {
+    pub fn cause(self) -> u64 {
+        self as u64
+    }
+
+    pub fn from_cause(cause: u64) -> Option<ExceptionKind> {
+        match cause {
+            0 => Some(ExceptionKind::IllegalInstruction),
+            1 => Some(ExceptionKind::DivisionByZero),
+            2 => Some(ExceptionKind::MisalignedAccess),
+            3 => Some(ExceptionKind::AccessFault),
+            4 => Some(ExceptionKind::StackOverflow),
+            5 => Some(ExceptionKind::InvalidOperation),
+            6 => Some(ExceptionKind::ProtectionFault),
+            7 => Some(ExceptionKind::PrivilegedInstruction),
+            8 => Some(ExceptionKind::Syscall),
+            9 => Some(ExceptionKind::PageFault),
+            _ => None,
+        }
+    }
+}
+
+// the exception that ended a run
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExceptionReport {
+    pub kind: ExceptionKind,
+    pub cause: u64,
+    pub core: usize,
+    pub pc: usize,
+    // the instruction that raised it, None when the run stopped past the last instruction
+    pub instruction: Option<Instruction>,
+    // the data address of a memory exception
+    pub address: Option<u64>,
+    // the mpu region a protection fault violated
+    pub region: Option<usize>,
+    pub message: String,
+    // raised inside an exception handler, which is never caught
+    pub nested: bool,
+}
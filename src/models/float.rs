@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::instruction::{Operand};
+
+// rounding mode of the FP unit, set with fsrm and read with frrm (0..=3 in this order)
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum RoundingMode {
+    // round to nearest, ties to even
+    #[default]
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+}
+
+impl RoundingMode {
+    pub fn from_bits(bits: u64) -> Option<RoundingMode> {
+        match bits {
+            0 => Some(RoundingMode::NearestEven),
+            1 => Some(RoundingMode::TowardZero),
+            2 => Some(RoundingMode::Down),
+            3 => Some(RoundingMode::Up),
+            _ => None,
+        }
+    }
+
+    pub fn bits(self) -> u64 {
+        self as u64
+    }
+}
+
+use Operand::{Float as F, Integer as R, Unused as U};
+
+// (opcode, regdst, regsrc, regext)
+const FLOAT_OPCODES: &[(&str, Operand, Operand, Operand)] = &[
+    ("fadd.s", F, F, F), ("fadd.d", F, F, F),
+    ("fsub.s", F, F, F), ("fsub.d", F, F, F),
+    ("fmul.s", F, F, F), ("fmul.d", F, F, F),
+    ("fdiv.s", F, F, F), ("fdiv.d", F, F, F),
+    ("fmin.s", F, F, F), ("fmin.d", F, F, F),
+    ("fmax.s", F, F, F), ("fmax.d", F, F, F),
+    ("fmadd.s", F, F, F), ("fmadd.d", F, F, F),
+    ("fsqrt.s", F, F, U), ("fsqrt.d", F, F, U),
+    ("fneg.s", F, F, U), ("fneg.d", F, F, U),
+    ("fabs.s", F, F, U), ("fabs.d", F, F, U),
+    ("fli.s", F, U, U), ("fli.d", F, U, U),
+    ("fcvt.s.l", F, R, U), ("fcvt.d.l", F, R, U),
+    ("fcvt.l.s", R, F, U), ("fcvt.l.d", R, F, U),
+    ("fcvt.s.d", F, F, U), ("fcvt.d.s", F, F, U),
+    ("fmv.x.s", R, F, U), ("fmv.x.d", R, F, U),
+    ("fmv.s.x", F, R, U), ("fmv.d.x", F, R, U),
+    ("flw", F, R, U), ("fld", F, R, U),
+    ("fsw", U, R, F), ("fsd", U, R, F),
+    ("fcmp.s", U, F, F), ("fcmp.d", U, F, F),
+    ("frflags", R, U, U), ("fsflags", U, R, U),
+    ("frrm", R, U, U), ("fsrm", U, R, U),
+];
+
+pub fn float_operands(opcode: &str) -> Option<(Operand, Operand, Operand)> {
+    FLOAT_OPCODES.iter()
+        .find(|(name, _, _, _)| *name == opcode)
+        .map(|(_, dst, src, ext)| (*dst, *src, *ext))
+}
+
+pub fn is_float(opcode: &str) -> bool {
+    float_operands(opcode).is_some()
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::instruction::{Instruction};
+
+// what replaced the program before this version
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum VersionOrigin {
+    Load,
+    // POST /library/{name}/load, with the name
+    Library(String),
+    // POST /assemble with "load": true
+    Assemble,
+    // PATCH / DELETE /code/{index}, POST /code/{index}/insert
+    Edit,
+    // POST /programs/rollback/{version}, holding that version
+    Rollback(u64),
+    // the program loaded when the server stopped, put back when it starts again
+    Restore,
+}
+
+// one entry of GET /programs/history
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VersionSummary {
+    pub version: u64,
+    // seconds since the unix epoch
+    pub loaded_at: u64,
+    // first characters of the session key the change was made with, if it sent one
+    pub session: Option<String>,
+    pub origin: VersionOrigin,
+    pub length: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryReport {
+    pub current: Option<u64>,
+    // oldest first, at most MAX_VERSIONS
+    pub versions: Vec<VersionSummary>,
+}
+
+// query of the requests that change the program, 'key' is the session key
+#[derive(Debug, Deserialize)]
+pub struct SessionQuery {
+    pub key: Option<String>,
+}
+
+// query of GET /programs/diff, both default to the current version and the one before it
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Change {
+    Deleted,
+    Inserted,
+}
+
+// 'from_index' is set for deleted instructions, 'to_index' for inserted ones. both are
+// positions in their version, so a replaced instruction shows up as a deletion and an insertion.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiffEntry {
+    pub change: Change,
+    pub from_index: Option<usize>,
+    pub to_index: Option<usize>,
+    pub instruction: Instruction,
+    // the instruction as assembly text
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProgramDiff {
+    pub from: u64,
+    pub to: u64,
+    pub unchanged: usize,
+    // false when the changed part was too long to compare instruction by instruction, it is
+    // then listed as deleted and inserted as a whole (see MAX_DIFF_CELLS)
+    pub minimal: bool,
+    pub changes: Vec<DiffEntry>,
+}
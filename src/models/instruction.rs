@@ -1,12 +1,16 @@
+use std::fmt;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+use crate::models::float::{float_operands};
+use crate::models::vector::{vector_operands};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Instruction {
-    opcode: String,
-    imdval: String,
-    regsrc: u8,
-    regext: u8, 
-    regdst: u8,
+    pub opcode: String,
+    pub imdval: String,
+    pub regsrc: u8,
+    pub regext: u8, 
+    pub regdst: u8,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -14,3 +18,87 @@ pub struct ProgramSource {
     pub instructions: Vec<Instruction>,
 }
 
+// query of GET /list. format is "json" (default) or "dot", 'counts=false' leaves the
+// execution counts of the last run out of the graph.
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub format: Option<String>,
+    pub counts: Option<bool>,
+}
+
+// which register file an operand of a float or vector opcode refers to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Integer,
+    Float,
+    Vector,
+    Unused,
+}
+
+// (regdst, regsrc, regext) of the opcodes that use the FP or vector register files.
+// the integer opcodes read and write integer registers only and return None.
+pub fn operands(opcode: &str) -> Option<(Operand, Operand, Operand)> {
+    float_operands(opcode).or_else(|| vector_operands(opcode))
+}
+
+// assembly-like rendering, e.g. "add r2, r1, r0", "ld r3, [r1 + 0x10]" or "beq r1, r2, 7"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let imdval = match self.imdval.trim() {
+            "" | "0x" => None,
+            imdval => Some(imdval),
+        };
+        let operand = match imdval {
+            Some(imdval) => imdval.to_string(),
+            None => format!("r{}", self.regext),
+        };
+        let offset = imdval.unwrap_or("0");
+
+        // fp and vector opcodes name their registers f0..f31 and v0..v31
+        if let Some((dst, src, ext)) = operands(&self.opcode) {
+            let name = |operand: Operand, register: u8| match operand {
+                Operand::Integer => Some(format!("r{}", register)),
+                Operand::Float => Some(format!("f{}", register)),
+                Operand::Vector => Some(format!("v{}", register)),
+                Operand::Unused => None,
+            };
+            let operands: Vec<String> = match self.opcode.as_str() {
+                "flw" | "fld" | "vld" => vec![name(dst, self.regdst).unwrap_or_default(), format!("[r{} + {}]", self.regsrc, offset)],
+                "fsw" | "fsd" | "vst" => vec![name(ext, self.regext).unwrap_or_default(), format!("[r{} + {}]", self.regsrc, offset)],
+                "fli.s" | "fli.d" => vec![format!("f{}", self.regdst), offset.to_string()],
+                "fsflags" | "fsrm" => vec![imdval.map(str::to_string).unwrap_or(format!("r{}", self.regsrc))],
+                _ => {
+                    // an immediate replaces regext, or is an extra operand (vext/vins lane)
+                    let ext = match imdval {
+                        Some(imdval) => Some(imdval.to_string()),
+                        None => name(ext, self.regext),
+                    };
+                    [name(dst, self.regdst), name(src, self.regsrc), ext].into_iter().flatten().collect()
+                }
+            };
+            return write!(f, "{} {}", self.opcode, operands.join(", "));
+        }
+
+        match self.opcode.as_str() {
+            "nop" | "halt" | "ret" | "ei" | "di" | "iret" | "xret" | "wfi" | "brk" | "fence" | "tlbflush" => write!(f, "{}", self.opcode),
+            "ien" | "idis" | "iack" | "iraise" => write!(f, "{} {}", self.opcode, imdval.map(str::to_string).unwrap_or(format!("r{}", self.regsrc))),
+            "ecall" | "syscall" => match imdval {
+                Some(number) => write!(f, "{} {}", self.opcode, number),
+                None => write!(f, "{}", self.opcode),
+            },
+            "ipend" | "coreid" | "ncores" | "xcause" | "xepc" | "xaddr" | "priv" => write!(f, "{} r{}", self.opcode, self.regdst),
+            "xsetepc" | "ptroot" => write!(f, "{} r{}", self.opcode, self.regsrc),
+            "ivec" | "xvec" => write!(f, "{} r{}, {}", self.opcode, self.regsrc, offset),
+            "mpubase" => write!(f, "{} r{}, r{}, {}", self.opcode, self.regsrc, self.regext, offset),
+            "mpuperm" => write!(f, "{} r{}, {}", self.opcode, self.regsrc, offset),
+            "jmp" | "call" | "ujmp" => write!(f, "{} {}", self.opcode, offset),
+            "amoadd" | "amoswap" | "cas" | "stwcx." | "strex" =>
+                write!(f, "{} r{}, r{}, [r{} + {}]", self.opcode, self.regdst, self.regext, self.regsrc, offset),
+            "ld" | "ldb" | "lwarx" | "ldrex" => write!(f, "{} r{}, [r{} + {}]", self.opcode, self.regdst, self.regsrc, offset),
+            "st" | "stb" => write!(f, "{} r{}, [r{} + {}]", self.opcode, self.regext, self.regsrc, offset),
+            "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" =>
+                write!(f, "{} r{}, r{}, {}", self.opcode, self.regsrc, self.regext, offset),
+            _ => write!(f, "{} r{}, r{}, {}", self.opcode, self.regdst, self.regsrc, operand),
+        }
+    }
+}
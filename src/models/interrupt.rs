@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+// state of the interrupt controller at the end of a run (or right now, for a live run)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InterruptReport {
+    // global enable, set by ei and cleared by di
+    pub enabled: bool,
+    // bit n <=> line n
+    pub mask: u8,
+    pub pending: u8,
+    // handler installed for every line
+    pub vectors: Vec<Option<usize>>,
+    // lines whose handlers are running, outermost first
+    pub in_service: Vec<u8>,
+    // number of times every line was delivered
+    pub delivered: Vec<u64>,
+}
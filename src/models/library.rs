@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// one program of the library, as kept in its manifest
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LibraryEntry {
+    // file of the program in the library directory, a ProgramSource in json
+    pub file: String,
+    pub length: usize,
+    // seconds since the unix epoch
+    pub saved_at: u64,
+}
+
+// manifest.json of the library directory, by program name
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Manifest {
+    pub programs: BTreeMap<String, LibraryEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LibraryReport {
+    pub directory: String,
+    pub programs: BTreeMap<String, LibraryEntry>,
+}
@@ -1,2 +1,22 @@
 pub mod instruction;
-pub mod command;
\ No newline at end of file
+pub mod command;
+pub mod cache;
+pub mod run;
+pub mod branch;
+pub mod analysis;
+pub mod optimizer;
+pub mod float;
+pub mod vector;
+pub mod interrupt;
+pub mod device;
+pub mod event;
+pub mod channel;
+pub mod consistency;
+pub mod exception;
+pub mod protection;
+pub mod paging;
+pub mod patch;
+pub mod history;
+pub mod library;
+pub mod persistence;
+pub mod assembler;
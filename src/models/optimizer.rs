@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::instruction::{Instruction};
+use crate::models::run::{RunStatus};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Pass {
+    ConstantFolding,
+    StrengthReduction,
+    NoOpRemoval,
+    DeadCodeRemoval,
+    BranchCollapsing,
+}
+
+// body of POST /optimize, a ProgramSource with an optional budget for the verification runs
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OptimizeRequest {
+    pub instructions: Vec<Instruction>,
+    #[serde(default)]
+    pub budget: Option<u64>,
+}
+
+// one rewrite applied by the optimizer. 'index' is the position of the instruction in the
+// program that was submitted, 'after' is empty when the instruction was removed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Rewrite {
+    pub pass: Pass,
+    pub index: usize,
+    pub before: Instruction,
+    pub after: Option<Instruction>,
+}
+
+// outcome of running the submitted and the optimized program side by side
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Verification {
+    pub equivalent: bool,
+    pub original_status: RunStatus,
+    pub optimized_status: RunStatus,
+    pub original_steps: u64,
+    pub optimized_steps: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OptimizeResult {
+    pub instructions: Vec<Instruction>,
+    pub rewrites: Vec<Rewrite>,
+    pub verification: Verification,
+}
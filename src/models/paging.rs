@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+// virtual memory of a run, see services/paging.rs. translation stays off until the program
+// sets a page table root with ptroot.
+//
+// {"page_size": 1024, "tlb_entries": 8}
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PagingConfig {
+    // bytes, a power of two from 256 to 4096 (4096 by default)
+    #[serde(default)]
+    pub page_size: Option<u64>,
+    // fully associative, least recently used entry replaced (16 by default)
+    #[serde(default)]
+    pub tlb_entries: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TlbReport {
+    pub page_size: u64,
+    pub entries: usize,
+    // physical address of the root page table, None while translation is off
+    pub root: Option<u64>,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    // page table walks, a write to a clean page walks again to set its dirty bit
+    pub walks: u64,
+    pub page_faults: u64,
+    pub flushes: u64,
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+// result of an edit of the loaded code_segment (PATCH / DELETE /code/{index},
+// POST /code/{index}/insert)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PatchResult {
+    pub message: String,
+    // instructions in the program after the edit
+    pub length: usize,
+    // indexes (after the edit) of the branches, jumps and calls whose target was moved
+    pub retargeted: Vec<usize>,
+}
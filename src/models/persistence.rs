@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::models::instruction::{Instruction};
+
+// what survives a restart of the server, see services/persistence.rs
+// the file holds the session key in plain text, it is only readable by the user running the
+// server
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ServerSnapshot {
+    // "0" while no session key was handed out
+    pub master_key: String,
+    // the program in code_segment, snapshots written before it was kept have none
+    #[serde(default)]
+    pub code: Vec<Instruction>,
+    // the /work messages that started the workers running when the snapshot was taken,
+    // e.g. "StartWorker100ms"
+    pub workers: Vec<String>,
+    // commands sent to a worker but not received by it yet, oldest first, by worker
+    // ("Worker100")
+    pub pending: BTreeMap<String, Vec<String>>,
+    // seconds since the unix epoch
+    pub saved_at: u64,
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default, Hash)]
+pub enum Privilege {
+    #[default]
+    Supervisor,
+    User,
+}
+
+// a memory protection region that is switched on
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegionReport {
+    pub index: usize,
+    pub base: u64,
+    pub size: u64,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
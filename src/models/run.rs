@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::cache::{CacheSetup, CacheReport};
+use crate::models::branch::{PredictorConfig, BranchReport};
+use crate::models::float::{RoundingMode};
+use crate::models::interrupt::{InterruptReport};
+use crate::models::device::{GpioReport};
+use crate::models::consistency::{MemoryModel};
+use crate::models::exception::{ExceptionReport};
+use crate::models::protection::{Privilege, RegionReport};
+use crate::models::paging::{PagingConfig, TlbReport};
+
+// options for a single run of the loaded code_segment. every field is optional,
+// '{}' runs the program with the default budget and no simulators attached.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RunRequest {
+    // instructions to execute, DEFAULT_BUDGET when unset and at most MAX_BUDGET
+    #[serde(default)]
+    pub budget: Option<u64>,
+    #[serde(default)]
+    pub cache: Option<CacheSetup>,
+    #[serde(default)]
+    pub branch_predictor: Option<PredictorConfig>,
+    // initial contents of the input stream
+    #[serde(default)]
+    pub input: Option<String>,
+    // number of cores sharing the data memory (1 by default), see services/core.rs
+    #[serde(default)]
+    pub cores: Option<usize>,
+    // instructions a core executes before the next one takes its turn (1, lockstep)
+    #[serde(default)]
+    pub quantum: Option<u64>,
+    #[serde(default)]
+    pub memory_model: Option<MemoryModel>,
+    // page size and tlb of the virtual memory, see services/paging.rs
+    #[serde(default)]
+    pub paging: Option<PagingConfig>,
+}
+
+// a live run driven by the worker ticks (POST /runs). 'clock' is the period in ms of the
+// worker that advances it (one of the worker periods), every tick executes up to
+// 'steps_per_tick' instructions, at most MAX_STEPS_PER_TICK.
+// live runs have no budget unless one is given.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ContextRequest {
+    #[serde(flatten)]
+    pub run: RunRequest,
+    #[serde(default)]
+    pub clock: Option<u64>,
+    #[serde(default)]
+    pub steps_per_tick: Option<u64>,
+    // a clock per core: core n is advanced by the ticks of the worker core_clocks[n] on
+    // its own. without it all cores share 'clock' and take turns.
+    #[serde(default)]
+    pub core_clocks: Option<Vec<u64>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum RunStatus {
+    Halted,
+    Faulted,
+    BudgetExhausted,
+    // stopped at a wfi with no interrupt to deliver
+    Waiting,
+    // blocked in a read syscall until input arrives
+    WaitingForInput,
+    // stopped at a brk, POST /runs/{id}/resume continues a live run
+    Breakpoint,
+    // a live run that has not stopped yet
+    Running,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CoreReport {
+    pub id: usize,
+    pub status: RunStatus,
+    pub pc: usize,
+    // instructions executed by this core
+    pub steps: u64,
+    pub registers: Vec<u64>,
+    pub fregisters: Vec<f64>,
+    pub fault: Option<String>,
+    pub privilege: Privilege,
+    // stores in the store buffer of the core, see services/consistency.rs
+    pub pending_stores: usize,
+}
+
+// pc, the register files and interrupts are those of core 'core', which is core 0 unless
+// another core faulted or stopped at a brk. 'steps' counts the instructions of all cores.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunResult {
+    pub id: String,
+    pub status: RunStatus,
+    pub steps: u64,
+    pub core: usize,
+    pub cores: Vec<CoreReport>,
+    pub pc: usize,
+    pub registers: Vec<u64>,
+    // NaN is not valid json and shows up as null
+    pub fregisters: Vec<f64>,
+    // the last fcmp and the accrued exceptions, see services/float.rs
+    pub fflags: u64,
+    pub rounding: RoundingMode,
+    // 128 bit vector registers as hex strings, lane 0 in the lowest digits
+    pub vregisters: Vec<String>,
+    // execution count of every instruction
+    pub counts: Vec<u64>,
+    pub fault: Option<String>,
+    // the exception behind the fault (or the budget exhaustion), see services/exception.rs
+    pub exception: Option<ExceptionReport>,
+    // exceptions the program handled itself
+    pub exceptions_handled: u64,
+    // privilege level and switched on mpu regions of core 'core', see services/protection.rs
+    pub privilege: Privilege,
+    pub regions: Vec<RegionReport>,
+    pub tlb: TlbReport,
+    pub cache: Option<CacheReport>,
+    pub branch: Option<BranchReport>,
+    pub interrupts: InterruptReport,
+    // everything the program sent to the uart
+    pub console: String,
+    // bytes queued for the program that it has not read yet
+    pub input_pending: usize,
+    pub input_closed: bool,
+    // written with the write syscall on stream 2
+    pub error_console: String,
+    pub gpio: GpioReport,
+    pub exit_code: Option<i64>,
+    pub syscall_errors: Vec<String>,
+}
@@ -0,0 +1,82 @@
+use crate::models::instruction::{Operand};
+
+// element type of a vector opcode, taken from its suffix ("vadd.i32", "vmul.f64")
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lanes {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl Lanes {
+
+    pub fn from_suffix(suffix: &str) -> Option<Lanes> {
+        match suffix {
+            "i8" => Some(Lanes::I8),
+            "i16" => Some(Lanes::I16),
+            "i32" => Some(Lanes::I32),
+            "i64" => Some(Lanes::I64),
+            "f32" => Some(Lanes::F32),
+            "f64" => Some(Lanes::F64),
+            _ => None,
+        }
+    }
+
+    // lane width in bits
+    pub fn width(self) -> u32 {
+        match self {
+            Lanes::I8 => 8,
+            Lanes::I16 => 16,
+            Lanes::I32 | Lanes::F32 => 32,
+            Lanes::I64 | Lanes::F64 => 64,
+        }
+    }
+
+    pub fn count(self) -> usize {
+        128 / self.width() as usize
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Lanes::F32 | Lanes::F64)
+    }
+
+    // the scalar register file this lane type is moved to and from
+    pub fn scalar(self) -> Operand {
+        if self.is_float() { Operand::Float } else { Operand::Integer }
+    }
+}
+
+// splits "vadd.i32" into ("vadd", Some(I32)), vld and vst have no suffix
+pub fn split_vector_opcode(opcode: &str) -> Option<(&str, Option<Lanes>)> {
+    if !opcode.starts_with('v') {
+        return None;
+    }
+    match opcode.split_once('.') {
+        Some((base, suffix)) => Lanes::from_suffix(suffix).map(|lanes| (base, Some(lanes))),
+        None => Some((opcode, None)),
+    }
+}
+
+// (regdst, regsrc, regext) of a vector opcode, see services/vector.rs
+pub fn vector_operands(opcode: &str) -> Option<(Operand, Operand, Operand)> {
+    use Operand::{Integer as R, Unused as U, Vector as V};
+
+    let (base, lanes) = split_vector_opcode(opcode)?;
+    match (base, lanes) {
+        ("vld", None) => Some((V, R, U)),
+        ("vst", None) => Some((U, R, V)),
+        ("vadd" | "vsub" | "vmul" | "vmin" | "vmax" | "vand" | "vor" | "vxor" | "vshuf", Some(_)) => Some((V, V, V)),
+        ("vdiv", Some(lanes)) if lanes.is_float() => Some((V, V, V)),
+        ("vshl" | "vshr" | "vsar", Some(lanes)) if !lanes.is_float() => Some((V, V, V)),
+        ("vsplat" | "vins", Some(lanes)) => Some((V, lanes.scalar(), U)),
+        ("vext" | "vredsum" | "vredmin" | "vredmax", Some(lanes)) => Some((lanes.scalar(), V, U)),
+        _ => None,
+    }
+}
+
+pub fn is_vector(opcode: &str) -> bool {
+    vector_operands(opcode).is_some()
+}
@@ -0,0 +1,29 @@
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::models::instruction::{ProgramSource};
+use crate::models::optimizer::{OptimizeRequest};
+use crate::services::analysis::{analyze};
+use crate::services::optimizer::{optimize};
+use crate::services::executor::{DEFAULT_BUDGET};
+
+// builds the control flow graph of a program and reports problems found in it.
+// diagnostics point at instruction indexes.
+//
+// usage example:
+// > curl --header "Content-Type: application/json" --request POST --data '{"instructions":[{"opcode": "add","imdval": "0x","regsrc": 1,"regext": 0,"regdst": 2}, {"opcode": "jmp","imdval": "0x7","regsrc": 0,"regext": 0,"regdst": 0}]}' http://localhost:8082/analyze
+// > {"blocks":[...],"diagnostics":[{"index":0,"kind":"ReadBeforeWrite","message":"r1 may be read before it is written"}, ...]}
+#[post("/analyze")]
+async fn analyze_program(payload: web::Json<ProgramSource>) -> impl Responder {
+    HttpResponse::Ok().json(analyze(&payload.instructions))
+}
+
+// runs the peephole optimizer over a program. the response holds the optimized program, every
+// rewrite that was applied and the result of running both versions side by side. if they do not
+// end up in the same state the submitted program is returned unchanged.
+//
+// usage example:
+// > curl --header "Content-Type: application/json" --request POST --data '{"instructions":[{"opcode": "add","imdval": "0x4","regsrc": 0,"regext": 0,"regdst": 1}, {"opcode": "mul","imdval": "0x8","regsrc": 2,"regext": 0,"regdst": 3}]}' http://localhost:8082/optimize
+#[post("/optimize")]
+async fn optimize_program(payload: web::Json<OptimizeRequest>) -> impl Responder {
+    HttpResponse::Ok().json(optimize(&payload.instructions, payload.budget.unwrap_or(DEFAULT_BUDGET)))
+}
@@ -0,0 +1,58 @@
+use std::sync::{Arc};
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::services::state::{InstreamState};
+use crate::services::assembler::{assemble};
+use crate::services::history::{session_of};
+use crate::models::assembler::{AssembleErrors, AssembleRequest, AssembleResult};
+use crate::models::history::{SessionQuery, VersionOrigin};
+use crate::models::command::{ResponseMessage};
+
+// assembles program text (see services/assembler.rs), and loads it with "load": true
+//
+// usage example:
+// > curl --header "Content-Type: application/json" --request POST --data '{"source":".equ STEP, 8\nloop: add r1, r1, STEP\nblt r1, r2, loop\nhalt","load":true}' http://localhost:8082/assemble
+// > {"instructions":[{"opcode":"add","imdval":"8","regsrc":1,"regext":0,"regdst":1}, ...],
+//    "source_map":[{"line":2,"text":"loop: add r1, r1, STEP","via":[]}, ...],"labels":{"loop":0},"loaded":true}
+//
+// errors come with the line they are on:
+// > {"message":"1 error","errors":[{"source":{"line":3,"text":"blt r1, r2, lop","via":[]},"message":"unknown label or constant 'lop'"}]}
+#[post("/assemble")]
+async fn assemble_program(payload: web::Json<AssembleRequest>, query: web::Query<SessionQuery>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    // the assembly runs on the blocking thread pool with no lock held, the library is only
+    // locked to copy out each program an .include names
+    let state = data.get_ref().clone();
+    let source = payload.source.clone();
+    let assembled = web::block(move || {
+        assemble(&source, &|name: &str| state.library.lock().unwrap().get(name).map(|instructions| instructions.to_vec()))
+    }).await;
+
+    let assembly = match assembled {
+        Ok(Ok(assembly)) => assembly,
+        Ok(Err(errors)) => return HttpResponse::BadRequest().json(AssembleErrors {
+            message: if errors.len() == 1 { "1 error".to_string() } else { format!("{} errors", errors.len()) },
+            errors,
+        }),
+        Err(e) => return HttpResponse::InternalServerError().json(ResponseMessage {
+            message: format!("::: assembly failed: {}", e),
+        }),
+    };
+
+    let loaded = payload.load.unwrap_or(false);
+    if loaded {
+        let mut code = data.code_segment.lock().unwrap();
+        *code = assembly.instructions.clone();
+        *data.last_run.lock().unwrap() = None;
+        let session = session_of(query.key.as_deref(), &data.master_key.lock().unwrap());
+        data.history.lock().unwrap().record(&code, VersionOrigin::Assemble, session);
+        data.store.changed();
+    }
+
+    HttpResponse::Ok().json(AssembleResult {
+        instructions: assembly.instructions,
+        source_map: assembly.source_map,
+        labels: assembly.labels,
+        loaded,
+    })
+}
@@ -0,0 +1,108 @@
+use std::sync::{Arc};
+use std::time::Duration;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use actix_ws::{Message, Session};
+
+use crate::services::state::{InstreamState};
+use crate::routes::events::{EventFilter, EVENT_POLL_MS, KEEP_ALIVE_POLLS};
+use crate::routes::worker::{dispatch_command, dispatch_work};
+use crate::models::command::{ResponseMessage};
+use crate::models::event::{EventQuery};
+use crate::models::channel::{ControlMessage, ChannelMessage};
+
+async fn send(session: &mut Session, message: &ChannelMessage) -> Result<(), actix_ws::Closed> {
+    session.text(serde_json::to_string(message).unwrap_or_default()).await
+}
+
+fn control(text: &str, data: web::Data<Arc<InstreamState>>) -> ChannelMessage {
+    let result = match serde_json::from_str::<ControlMessage>(text) {
+        Ok(ControlMessage::Command(command)) => dispatch_command(&command, &data),
+        Ok(ControlMessage::Work(work)) => dispatch_work(&work, data),
+        Err(error) => Err(format!("::: invalid message: {}", error)),
+    };
+    match result {
+        Ok(message) => ChannelMessage::Response { ok: true, message },
+        Err(message) => ChannelMessage::Response { ok: false, message },
+    }
+}
+
+// a websocket for dashboards: accepts the payloads of /work and /command, every message is
+// answered with a Response, and the events of /events (narrowed down by 'worker' and
+// 'kinds' like there) are pushed as they are published.
+//
+// {"key": "{session_key}", "message": "StartWorker10ms"}
+// > {"type":"Response","ok":true,"message":"::: executing: StartWorker10ms"}
+// > {"type":"Event","id":1,"kind":"WorkerStarted","worker":"Worker10","run":null,"message":"StartWorker10ms"}
+// {"key": "{session_key}", "receiver": "Worker10", "command": "Stop"}
+//
+// usage example:
+// > websocat "ws://localhost:8082/channel?key={session_key}&kinds=WorkerStarted,WorkerStopped,Halt"
+#[get("/channel")]
+async fn channel(req: HttpRequest, body: web::Payload, query: web::Query<EventQuery>,
+                data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    if !data.key_matches(&query.key) {
+        return HttpResponse::Forbidden().json(ResponseMessage {
+            message: "::. Key? ..".to_owned(),
+        });
+    }
+
+    let filter = match EventFilter::from_query(&query) {
+        Ok(filter) => filter,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(ResponseMessage { message });
+        }
+    };
+
+    let (response, mut session, mut messages) = match actix_ws::handle(&req, body) {
+        Ok(handshake) => handshake,
+        Err(error) => return HttpResponse::from_error(error),
+    };
+
+    // pushes the events, ends once the connection is closed
+    let mut event_session = session.clone();
+    let state = data.get_ref().clone();
+    actix_rt::spawn(async move {
+        let mut next = state.events.next_id();
+        let mut idle = 0;
+        loop {
+            let (events, following) = state.events.since(next);
+            next = following;
+            for event in events.into_iter().filter(|event| filter.accepts(event)) {
+                idle = 0;
+                if send(&mut event_session, &ChannelMessage::Event(event)).await.is_err() {
+                    return;
+                }
+            }
+            idle += 1;
+            if idle == KEEP_ALIVE_POLLS {
+                idle = 0;
+                // also notices a connection that went away
+                if event_session.ping(b"").await.is_err() {
+                    return;
+                }
+            }
+            actix_rt::time::sleep(Duration::from_millis(EVENT_POLL_MS)).await;
+        }
+    });
+
+    actix_rt::spawn(async move {
+        while let Some(Ok(message)) = messages.recv().await {
+            let sent = match message {
+                Message::Text(text) => send(&mut session, &control(&text, data.clone())).await,
+                Message::Ping(bytes) => session.pong(&bytes).await,
+                Message::Close(reason) => {
+                    let _ = session.close(reason).await;
+                    return;
+                },
+                _ => Ok(()),
+            };
+            if sent.is_err() {
+                return;
+            }
+        }
+        let _ = session.close(None).await;
+    });
+
+    response
+}
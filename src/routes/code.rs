@@ -0,0 +1,53 @@
+use std::sync::{Arc};
+use actix_web::{delete, patch, post, web, HttpResponse, Responder};
+
+use crate::services::state::{InstreamState};
+use crate::services::patch::{apply, Edit};
+use crate::models::instruction::{Instruction, ProgramSource};
+use crate::services::history::{session_of};
+use crate::models::command::{ResponseMessage};
+use crate::models::history::{SessionQuery, VersionOrigin};
+
+// edits the loaded program without reloading it, branch targets that point behind the edit
+// are moved along (see services/patch.rs). live runs keep the program they were started with.
+//
+// usage example:
+// > curl --header "Content-Type: application/json" --request PATCH --data '{"opcode": "add","imdval": "0x1","regsrc": 1,"regext": 0,"regdst": 1}' http://localhost:8082/code/3
+// > curl --header "Content-Type: application/json" --request POST --data '{"instructions":[{"opcode": "nop","imdval": "0x","regsrc": 0,"regext": 0,"regdst": 0}]}' http://localhost:8082/code/3/insert
+// > curl --request DELETE http://localhost:8082/code/3
+// > {"message":"instruction 3 deleted","length":9,"retargeted":[6]}
+fn edit_code(data: &InstreamState, index: usize, edit: Edit, key: Option<&str>) -> HttpResponse {
+
+    // the lock is held for the whole edit, nobody sees half of it
+    let mut code = data.code_segment.lock().unwrap();
+
+    match apply(&code, index, edit) {
+        Ok((patched, result)) => {
+            *code = patched;
+            // its execution counts belong to the old program
+            *data.last_run.lock().unwrap() = None;
+            let session = session_of(key, &data.master_key.lock().unwrap());
+            data.history.lock().unwrap().record(&code, VersionOrigin::Edit, session);
+            data.store.changed();
+            HttpResponse::Ok().json(result)
+        },
+        Err(e) => HttpResponse::BadRequest().json(ResponseMessage {
+            message: e,
+        }),
+    }
+}
+
+#[patch("/code/{index}")]
+async fn patch_instruction(path: web::Path<usize>, payload: web::Json<Instruction>, query: web::Query<SessionQuery>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+    edit_code(&data, path.into_inner(), Edit::Replace(payload.into_inner()), query.key.as_deref())
+}
+
+#[post("/code/{index}/insert")]
+async fn insert_instructions(path: web::Path<usize>, payload: web::Json<ProgramSource>, query: web::Query<SessionQuery>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+    edit_code(&data, path.into_inner(), Edit::Insert(payload.into_inner().instructions), query.key.as_deref())
+}
+
+#[delete("/code/{index}")]
+async fn delete_instruction(path: web::Path<usize>, query: web::Query<SessionQuery>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+    edit_code(&data, path.into_inner(), Edit::Delete, query.key.as_deref())
+}
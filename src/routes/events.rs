@@ -0,0 +1,108 @@
+use std::sync::{Arc};
+use std::time::Duration;
+use futures_util::stream;
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::services::state::{InstreamState};
+use crate::models::command::{ResponseMessage};
+use crate::models::event::{Event, EventKind, EventQuery};
+
+// how often a subscriber looks for new events
+pub(crate) const EVENT_POLL_MS: u64 = 50;
+// a comment is sent after this many idle polls so proxies keep the connection open
+pub(crate) const KEEP_ALIVE_POLLS: u32 = 200;
+
+pub(crate) struct EventFilter {
+    worker: Option<String>,
+    kinds: Option<Vec<EventKind>>,
+}
+
+impl EventFilter {
+
+    // 'kinds' is a comma separated list of event kinds
+    pub(crate) fn from_query(query: &EventQuery) -> Result<EventFilter, String> {
+        let kinds = match &query.kinds {
+            Some(kinds) => {
+                let mut parsed = Vec::new();
+                for name in kinds.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                    match EventKind::from_name(name) {
+                        Some(kind) => parsed.push(kind),
+                        None => return Err(format!("::: unknown event kind '{}'", name)),
+                    }
+                }
+                Some(parsed)
+            },
+            None => None,
+        };
+        Ok(EventFilter { worker: query.worker.clone(), kinds })
+    }
+
+    pub(crate) fn accepts(&self, event: &Event) -> bool {
+        let worker = match &self.worker {
+            Some(worker) => event.worker.as_deref() == Some(worker.as_str()),
+            None => true,
+        };
+        let kind = match &self.kinds {
+            Some(kinds) => kinds.contains(&event.kind),
+            None => true,
+        };
+        worker && kind
+    }
+}
+
+fn format_event(event: &Event) -> String {
+    let data = serde_json::to_string(event).unwrap_or_default();
+    format!("id: {}\nevent: {:?}\ndata: {}\n\n", event.id, event.kind, data)
+}
+
+// server-sent events of the workers (WorkerStarted, WorkerStopped, Tick, Command) and the
+// runs (Halt, Fault, Breakpoint, Console). events published before the connection was
+// opened are not replayed. subscribing needs the key of GET /session_key, "0" is refused.
+//
+// usage example:
+// > curl --no-buffer "http://localhost:8082/events?key={session_key}"
+// > curl --no-buffer "http://localhost:8082/events?key={session_key}&worker=Worker100&kinds=Tick,Command"
+// > curl --no-buffer "http://localhost:8082/events?key={session_key}&kinds=Console,Halt,Fault"
+#[get("/events")]
+async fn events(query: web::Query<EventQuery>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    if !data.key_matches(&query.key) {
+        return HttpResponse::Forbidden().json(ResponseMessage {
+            message: "::. Key? ..".to_owned(),
+        });
+    }
+
+    let filter = match EventFilter::from_query(&query) {
+        Ok(filter) => filter,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(ResponseMessage { message });
+        }
+    };
+
+    let state = data.get_ref().clone();
+    let next = state.events.next_id();
+    let chunks = stream::unfold((state, filter, next), |(state, filter, mut next)| async move {
+        let mut idle = 0;
+        loop {
+            let (events, following) = state.events.since(next);
+            next = following;
+            let text: String = events.iter()
+                .filter(|event| filter.accepts(event))
+                .map(format_event)
+                .collect();
+            if !text.is_empty() {
+                return Some((Ok::<_, actix_web::Error>(web::Bytes::from(text)), (state, filter, next)));
+            }
+            idle += 1;
+            if idle == KEEP_ALIVE_POLLS {
+                return Some((Ok(web::Bytes::from_static(b":\n\n")), (state, filter, next)));
+            }
+            actix_rt::time::sleep(Duration::from_millis(EVENT_POLL_MS)).await;
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(chunks)
+}
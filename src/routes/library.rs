@@ -0,0 +1,90 @@
+use std::sync::{Arc};
+use actix_web::{get, post, web, HttpResponse, Responder};
+
+use crate::services::state::{InstreamState};
+use crate::services::history::{session_of};
+use crate::models::instruction::{ProgramSource};
+use crate::models::command::{ResponseMessage};
+use crate::models::history::{SessionQuery, VersionOrigin};
+
+// named programs kept on disk across restarts (see services/library.rs). the directory is
+// taken from INSTREAMS_LIBRARY, ./library when it is not set.
+//
+// usage example:
+// > curl http://localhost:8082/library
+// > {"directory":"library","programs":{"loop":{"file":"loop.json","length":7,"saved_at":1760860800}}}
+#[get("/library")]
+async fn list_library(data: web::Data<Arc<InstreamState>>) -> impl Responder {
+    HttpResponse::Ok().json(data.library.lock().unwrap().report())
+}
+
+// a program of the library, without loading it
+//
+// usage example:
+// > curl http://localhost:8082/library/loop
+// > {"instructions":[{"opcode":"add","imdval":"0","regsrc":0,"regext":0,"regdst":1}, ...]}
+#[get("/library/{name}")]
+async fn get_library_program(path: web::Path<String>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+    match data.library.lock().unwrap().get(&path) {
+        Ok(instructions) => HttpResponse::Ok().json(ProgramSource {
+            instructions: instructions.to_vec(),
+        }),
+        Err(e) => HttpResponse::NotFound().json(ResponseMessage {
+            message: e,
+        }),
+    }
+}
+
+// saves the loaded code_segment under a name, replacing a program saved under it before
+//
+// usage example:
+// > curl --request POST http://localhost:8082/library/loop
+// > {"file":"loop.json","length":7,"saved_at":1760860800}
+#[post("/library/{name}")]
+async fn save_library_program(path: web::Path<String>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    // a copy, runs and edits do not wait for the disk
+    let code = data.code_segment.lock().unwrap().to_vec();
+    let state = data.get_ref().clone();
+    let name = path.into_inner();
+    let saved = web::block(move || state.library.lock().unwrap().save(&name, &code)).await;
+
+    match saved {
+        Ok(Ok(entry)) => HttpResponse::Ok().json(entry),
+        Ok(Err(e)) => HttpResponse::BadRequest().json(ResponseMessage {
+            message: e,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(ResponseMessage {
+            message: format!("::: save failed: {}", e),
+        }),
+    }
+}
+
+// loads a program of the library into code_segment, like /load
+//
+// usage example:
+// > curl --request POST http://localhost:8082/library/loop/load
+// > {"message":"Program 'loop' Loaded."}
+#[post("/library/{name}/load")]
+async fn load_library_program(path: web::Path<String>, query: web::Query<SessionQuery>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    let name = path.into_inner();
+    let mut code = data.code_segment.lock().unwrap();
+
+    let instructions = match data.library.lock().unwrap().get(&name) {
+        Ok(instructions) => instructions.to_vec(),
+        Err(e) => return HttpResponse::NotFound().json(ResponseMessage {
+            message: e,
+        }),
+    };
+
+    *code = instructions;
+    *data.last_run.lock().unwrap() = None;
+    let session = session_of(query.key.as_deref(), &data.master_key.lock().unwrap());
+    data.history.lock().unwrap().record(&code, VersionOrigin::Library(name.clone()), session);
+    data.store.changed();
+
+    HttpResponse::Ok().json(ResponseMessage {
+        message: format!("Program '{}' Loaded.", name),
+    })
+}
@@ -1,2 +1,10 @@
 pub mod session;
-pub mod worker;
\ No newline at end of file
+pub mod worker;
+pub mod run;
+pub mod analysis;
+pub mod events;
+pub mod channel;
+pub mod code;
+pub mod programs;
+pub mod library;
+pub mod assembler;
@@ -0,0 +1,65 @@
+use std::sync::{Arc};
+use actix_web::{get, post, web, HttpResponse, Responder};
+
+use crate::services::state::{InstreamState};
+use crate::services::history::{session_of};
+use crate::models::command::{ResponseMessage};
+use crate::models::history::{DiffQuery, SessionQuery};
+
+// versions of the loaded program, recorded by /load, the /code edits and rollbacks
+//
+// usage example:
+// > curl http://localhost:8082/programs/history
+// > {"current":2,"versions":[{"version":1,"loaded_at":1760860800,"session":"3f2a9c1e","origin":"Load","length":2},
+//    {"version":2,"loaded_at":1760860815,"session":null,"origin":"Edit","length":3}]}
+#[get("/programs/history")]
+async fn program_history(data: web::Data<Arc<InstreamState>>) -> impl Responder {
+    HttpResponse::Ok().json(data.history.lock().unwrap().report())
+}
+
+// instructions deleted from 'from' and inserted in 'to'. without parameters the current
+// version is compared with the one before it.
+//
+// usage example:
+// > curl "http://localhost:8082/programs/diff?from=1&to=2"
+// > {"from":1,"to":2,"unchanged":2,"minimal":true,"changes":[{"change":"Inserted","from_index":null,"to_index":1,
+//    "instruction":{"opcode":"nop","imdval":"0x","regsrc":0,"regext":0,"regdst":0},"text":"nop"}]}
+#[get("/programs/diff")]
+async fn program_diff(query: web::Query<DiffQuery>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+    match data.history.lock().unwrap().diff(query.from, query.to) {
+        Ok(diff) => HttpResponse::Ok().json(diff),
+        Err(e) => HttpResponse::BadRequest().json(ResponseMessage {
+            message: e,
+        }),
+    }
+}
+
+// loads an earlier version again, as a new version so the rollback can be undone as well
+//
+// usage example:
+// > curl --request POST http://localhost:8082/programs/rollback/1
+// > {"message":"version 1 loaded as version 3"}
+#[post("/programs/rollback/{version}")]
+async fn rollback_program(path: web::Path<u64>, query: web::Query<SessionQuery>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    let version = path.into_inner();
+    let mut code = data.code_segment.lock().unwrap();
+    let mut history = data.history.lock().unwrap();
+
+    let session = session_of(query.key.as_deref(), &data.master_key.lock().unwrap());
+    let current = match history.rollback(version, session) {
+        Ok((current, instructions)) => {
+            *code = instructions;
+            current
+        },
+        Err(e) => return HttpResponse::NotFound().json(ResponseMessage {
+            message: e,
+        }),
+    };
+    *data.last_run.lock().unwrap() = None;
+    data.store.changed();
+
+    HttpResponse::Ok().json(ResponseMessage {
+        message: format!("version {} loaded as version {}", version, current),
+    })
+}
@@ -0,0 +1,259 @@
+use uuid::Uuid;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures_util::stream;
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+
+use crate::services::state::{InstreamState};
+use crate::services::executor::{Machine, DEFAULT_BUDGET, MAX_BUDGET};
+use crate::services::context::{find_run, publish_run, ExecutionContext};
+use crate::services::litmus::{explore};
+use crate::models::command::{ResponseMessage};
+use crate::models::run::{RunRequest, ContextRequest};
+use crate::models::device::{ConsoleQuery, InputRequest, InputStatus};
+use crate::models::consistency::{LitmusRequest};
+
+// runs the program currently loaded in memory. the same code_segment can be run
+// repeatedly with different simulator settings and the results compared.
+//
+// usage example:
+// > curl --header "Content-Type: application/json" --request POST --data '{}' http://localhost:8082/run
+// > curl --header "Content-Type: application/json" --request POST --data '{"branch_predictor": {"Gshare": {"history_bits": 4}}}' http://localhost:8082/run
+// > curl --header "Content-Type: application/json" --request POST --data '{"cores": 4, "quantum": 10}' http://localhost:8082/run
+// > curl --header "Content-Type: application/json" --request POST --data '{"budget": 1000, "cache": {"dcache": {"size": 256, "associativity": 2, "line_size": 16, "replacement": "Lru", "write_policy": "WriteBack"}, "ranges": [{"start": 0, "end": 128}]}}' http://localhost:8082/run
+#[post("/run")]
+async fn run_program(payload: web::Json<RunRequest>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    let code = data.code_segment.lock().unwrap().to_vec();
+    let mut machine = Machine::new(code);
+
+    if let Err(e) = machine.attach(&payload) {
+        return HttpResponse::BadRequest().json(ResponseMessage {
+            message: e,
+        });
+    }
+    // nobody can send more input to this run
+    machine.devices.input_closed = true;
+
+    // the run can take a while, it runs on the blocking thread pool
+    let budget = payload.budget.unwrap_or(DEFAULT_BUDGET).min(MAX_BUDGET);
+    let (machine, status) = match web::block(move || {
+        let status = machine.run(budget);
+        (machine, status)
+    }).await {
+        Ok(ran) => ran,
+        Err(e) => return HttpResponse::InternalServerError().json(ResponseMessage {
+            message: format!("::: run failed: {}", e),
+        }),
+    };
+    let result = machine.result(&Uuid::new_v4().to_string(), status);
+    publish_run(&data.events, &result.id, status, &machine, 0);
+
+    *data.last_run.lock().unwrap() = Some(result.clone());
+
+    HttpResponse::Ok().json(result)
+}
+
+// starts a live run of the loaded program. it is advanced by the ticks of its clock worker,
+// which has to be started with /work, and every running worker raises its timer interrupt.
+//
+// usage example:
+// > curl --header "Content-Type: application/json" --request POST --data '{"clock": 10, "steps_per_tick": 500}' http://localhost:8082/runs
+// > curl --header "Content-Type: application/json" --request POST --data '{"cores": 2, "core_clocks": [10, 100]}' http://localhost:8082/runs
+// > curl http://localhost:8082/runs/{id}
+// > curl --request DELETE http://localhost:8082/runs/{id}
+#[post("/runs")]
+async fn start_run(payload: web::Json<ContextRequest>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    let code = data.code_segment.lock().unwrap().to_vec();
+    let id = Uuid::new_v4().to_string();
+
+    match ExecutionContext::new(id.clone(), Machine::new(code), &payload) {
+        Ok(context) => {
+            let result = context.result();
+            data.runs.lock().unwrap().insert(id, Arc::new(Mutex::new(context)));
+            HttpResponse::Ok().json(result)
+        },
+        Err(e) => {
+            HttpResponse::BadRequest().json(ResponseMessage {
+                message: e,
+            })
+        }
+    }
+}
+
+#[get("/runs/{id}")]
+async fn get_run(path: web::Path<String>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    match find_run(&data, path.as_str()) {
+        Some(context) => HttpResponse::Ok().json(context.lock().unwrap().result()),
+        None => HttpResponse::NotFound().json(ResponseMessage {
+            message: format!("::: no run '{}'", path),
+        }),
+    }
+}
+
+// stops a live run and forgets it, the response is its final state
+#[delete("/runs/{id}")]
+async fn stop_run(path: web::Path<String>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    let removed = data.runs.lock().unwrap().remove(path.as_str());
+    match removed {
+        Some(context) => HttpResponse::Ok().json(context.lock().unwrap().result()),
+        None => HttpResponse::NotFound().json(ResponseMessage {
+            message: format!("::: no run '{}'", path),
+        }),
+    }
+}
+
+// how often a followed console looks for new output
+const CONSOLE_POLL_MS: u64 = 50;
+
+// console output of a live run, or of the last run of /run. with 'follow=true' the response
+// stays open and new output of a live run is streamed as it is written.
+//
+// usage example:
+// > curl http://localhost:8082/runs/{id}/console
+// > curl --no-buffer "http://localhost:8082/runs/{id}/console?follow=true"
+#[get("/runs/{id}/console")]
+async fn run_console(path: web::Path<String>, query: web::Query<ConsoleQuery>,
+                data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    let id = path.into_inner();
+    let live = data.runs.lock().unwrap().contains_key(&id);
+
+    if !live {
+        return match &*data.last_run.lock().unwrap() {
+            Some(run) if run.id == id => HttpResponse::Ok()
+                .content_type("text/plain; charset=utf-8")
+                .body(run.console.clone()),
+            _ => HttpResponse::NotFound().json(ResponseMessage {
+                message: format!("::: no run '{}'", id),
+            }),
+        };
+    }
+
+    if !query.follow.unwrap_or(false) {
+        let console = match find_run(&data, &id) {
+            Some(context) => context.lock().unwrap().machine.devices.console_text(),
+            None => String::new(),
+        };
+        return HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(console);
+    }
+
+    // sends whatever was written since the last chunk, until the run stops or is removed
+    let state = data.get_ref().clone();
+    let chunks = stream::unfold((state, id, 0usize), |(state, id, sent)| async move {
+        loop {
+            let (chunk, live) = match find_run(&state, &id) {
+                Some(context) => {
+                    let context = context.lock().unwrap();
+                    (context.machine.devices.console[sent..].to_vec(), context.is_live())
+                },
+                None => (Vec::new(), false),
+            };
+            if !chunk.is_empty() {
+                let sent = sent + chunk.len();
+                return Some((Ok::<_, actix_web::Error>(web::Bytes::from(chunk)), (state, id, sent)));
+            }
+            if !live {
+                return None;
+            }
+            actix_rt::time::sleep(Duration::from_millis(CONSOLE_POLL_MS)).await;
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .streaming(chunks)
+}
+
+// queues input for a live run. a run blocked in a read continues on the next tick of its clock.
+//
+// usage example:
+// > curl --header "Content-Type: application/json" --request POST --data '{"text": "hello\n"}' http://localhost:8082/runs/{id}/input
+// > curl --header "Content-Type: application/json" --request POST --data '{"numbers": [1, -2, 3], "close": true}' http://localhost:8082/runs/{id}/input
+#[post("/runs/{id}/input")]
+async fn run_input(path: web::Path<String>, payload: web::Json<InputRequest>,
+                data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    let Some(context) = find_run(&data, path.as_str()) else {
+        return HttpResponse::NotFound().json(ResponseMessage {
+            message: format!("::: no run '{}'", path),
+        });
+    };
+    let mut context = context.lock().unwrap();
+
+    let mut bytes = Vec::new();
+    if let Some(text) = &payload.text {
+        bytes.extend_from_slice(text.as_bytes());
+    }
+    if let Some(raw) = &payload.bytes {
+        bytes.extend_from_slice(raw);
+    }
+    for number in payload.numbers.iter().flatten() {
+        bytes.extend_from_slice(&number.to_le_bytes());
+    }
+
+    let devices = &mut context.machine.devices;
+    if !bytes.is_empty() {
+        if let Err(e) = devices.push_input(&bytes) {
+            return HttpResponse::Conflict().json(ResponseMessage {
+                message: e,
+            });
+        }
+    }
+    if payload.close.unwrap_or(false) {
+        devices.input_closed = true;
+    }
+
+    HttpResponse::Ok().json(InputStatus {
+        pending: devices.input.len(),
+        closed: devices.input_closed,
+    })
+}
+
+// continues a live run that stopped at a brk
+#[post("/runs/{id}/resume")]
+async fn resume_run(path: web::Path<String>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    match find_run(&data, path.as_str()) {
+        Some(context) => {
+            let mut context = context.lock().unwrap();
+            match context.resume() {
+                Ok(()) => HttpResponse::Ok().json(context.result()),
+                Err(e) => HttpResponse::Conflict().json(ResponseMessage {
+                    message: e,
+                }),
+            }
+        },
+        None => HttpResponse::NotFound().json(ResponseMessage {
+            message: format!("::: no run '{}'", path),
+        }),
+    }
+}
+
+// explores every interleaving of the loaded program on several cores under a memory model
+// and reports the outcomes, see services/litmus.rs
+//
+// usage example:
+// > curl --header "Content-Type: application/json" --request POST --data '{"cores": 2, "memory_model": "Tso", "observe": ["0:r1", "1:r1"], "exists": {"0:r1": 0, "1:r1": 0}}' http://localhost:8082/litmus
+#[post("/litmus")]
+async fn litmus(payload: web::Json<LitmusRequest>, data: web::Data<Arc<InstreamState>>) -> impl Responder {
+
+    let code = data.code_segment.lock().unwrap().to_vec();
+
+    // the search can take a while, it runs on the blocking thread pool
+    let request = payload.into_inner();
+    match web::block(move || explore(code, &request)).await {
+        Ok(Ok(result)) => HttpResponse::Ok().json(result),
+        Ok(Err(e)) => HttpResponse::BadRequest().json(ResponseMessage {
+            message: e,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(ResponseMessage {
+            message: format!("::: litmus test failed: {}", e),
+        }),
+    }
+}
@@ -19,6 +19,7 @@ async fn status() -> impl Responder {
     })
 }
 
+#[allow(clippy::cmp_owned)]
 #[get("/session_key")]
 async fn session_key(data: web::Data<Arc<InstreamState>>) -> impl Responder {
 
@@ -26,6 +27,13 @@ async fn session_key(data: web::Data<Arc<InstreamState>>) -> impl Responder {
 
     if *master_key == "0".to_string() {
         *master_key = Uuid::new_v4().to_string();
+        let key = master_key.to_owned();
+        drop(master_key);
+        // kept across restarts
+        data.store.changed();
+        return HttpResponse::Ok().json(ResponseMessage {
+            message: key,
+        });
     }
 
     HttpResponse::Ok().json(ResponseMessage {
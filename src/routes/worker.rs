@@ -1,3 +1,7 @@
+// the worker code keeps its explicit returns, comparisons and borrows
+#![allow(clippy::needless_return, clippy::bool_comparison, clippy::needless_borrow,
+        clippy::to_string_in_format_args)]
+
 //use uuid::Uuid;
 use std::thread;
 use std::sync::mpsc;
@@ -7,6 +11,9 @@ use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
 use std::str::FromStr;
 
 use crate::services::state::{InstreamState};
+use crate::services::context::{tick};
+use crate::services::persistence::{queue_command, command_received};
+use crate::models::event::{EventKind};
 use crate::models::command::{CommandEnum, WorkersEnum, DestinationEnum, 
     CommandMessage, RequestMessage, ResponseMessage};
 
@@ -16,6 +23,20 @@ use crate::models::command::{CommandEnum, WorkersEnum, DestinationEnum,
 async fn send_command(payload: web::Json<CommandMessage>, 
                 _req:HttpRequest, data: web::Data<Arc<InstreamState>>) -> impl Responder {
 
+    match dispatch_command(&payload, &data) {
+        Ok(message) => {
+            return HttpResponse::Ok().json(ResponseMessage { message });
+        },
+        Err(message) => {
+            return HttpResponse::Forbidden().json(ResponseMessage { message });
+        }
+    }
+}
+
+// sends a command to a worker, shared by /command and the websocket channel.
+// Err is a rejected key.
+pub fn dispatch_command(payload: &CommandMessage, data: &InstreamState) -> Result<String, String> {
+
     let key = &payload.key;
     let destination = &payload.receiver;
     let command = &payload.command;
@@ -59,19 +80,19 @@ async fn send_command(payload: web::Json<CommandMessage>,
                 match destination {
 
                     DestinationEnum::Worker10 => {
-                        data.sender10.lock().unwrap().send(actual_command).expect("Send failed");
+                        queue_command(data, "Worker10", &data.sender10, actual_command);
                     }
                     DestinationEnum::Worker25 => {
-                        data.sender25.lock().unwrap().send(actual_command).expect("Send failed");
+                        queue_command(data, "Worker25", &data.sender25, actual_command);
                     }
                     DestinationEnum::Worker50 => {
-                        data.sender50.lock().unwrap().send(actual_command).expect("Send failed");
+                        queue_command(data, "Worker50", &data.sender50, actual_command);
                     }
                     DestinationEnum::Worker100 => {
-                        data.sender100.lock().unwrap().send(actual_command).expect("Send failed");
+                        queue_command(data, "Worker100", &data.sender100, actual_command);
                     }
                     DestinationEnum::Worker250 => {
-                        data.sender250.lock().unwrap().send(actual_command).expect("Send failed");
+                        queue_command(data, "Worker250", &data.sender250, actual_command);
                     }
                 }
             }
@@ -82,13 +103,9 @@ async fn send_command(payload: web::Json<CommandMessage>,
             }
         }
 
-        return HttpResponse::Ok().json(ResponseMessage {
-            message: ret_value,
-        })
+        return Ok(ret_value);
     } else {
-        return HttpResponse::Forbidden().json(ResponseMessage {
-            message: "::. Key? ..".to_owned(),
-        });
+        return Err("::. Key? ..".to_owned());
     }
 }
 
@@ -118,6 +135,20 @@ async fn send_command(payload: web::Json<CommandMessage>,
 async fn execute(payload: web::Json<RequestMessage>, 
                 _req:HttpRequest, data: web::Data<Arc<InstreamState>>) -> impl Responder {
 
+    match dispatch_work(&payload, data) {
+        Ok(message) => {
+            return HttpResponse::Ok().json(ResponseMessage { message });
+        },
+        Err(message) => {
+            return HttpResponse::Forbidden().json(ResponseMessage { message });
+        }
+    }
+}
+
+// starts or stops a worker, shared by /work and the websocket channel.
+// Err is a rejected key.
+pub fn dispatch_work(payload: &RequestMessage, data: web::Data<Arc<InstreamState>>) -> Result<String, String> {
+
     let key = &payload.key;
     let work: &String = &payload.message;
 
@@ -140,10 +171,14 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StartWorker10ms => { 
                         if false == *data.worker10running.lock().unwrap() {
                             println!("{} starting !!", work_enum.to_string()); 
+                            // running before the thread is, for the state snapshot
+                            *data.worker10running.lock().unwrap() = true;
+                            data.store.changed();
                             let _handle10ms = thread::spawn(move || 
                                 worker10ms(work_enum.to_string(), 
                                 &data.receiver10,
-                                &data.worker10running));
+                                &data.worker10running,
+                                &data));
                         } else {
                             println!("{} already running.", work_enum.to_string()); 
                         }
@@ -151,7 +186,7 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StopWorker10ms => {
                         if true == *data.worker10running.lock().unwrap() {
                             println!("{} stopping ...", work_enum.to_string());
-                            data.sender10.lock().unwrap().send("Stop").expect("Send failed");
+                            queue_command(&data, "Worker10", &data.sender10, "Stop");
                         } else {
                             println!("{} is NOT even running. ", work_enum.to_string());
                         }          
@@ -159,10 +194,14 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StartWorker25ms => {
                         if false == *data.worker25running.lock().unwrap() {
                             println!("{} starting !!", work_enum.to_string()); 
+                            // running before the thread is, for the state snapshot
+                            *data.worker25running.lock().unwrap() = true;
+                            data.store.changed();
                             let _handle25ms = thread::spawn(move || 
                                 worker25ms(work_enum.to_string(), 
                                 &data.receiver25,
-                                &data.worker25running));
+                                &data.worker25running,
+                                &data));
                         } else {
                             println!("{} already running.", work_enum.to_string()); 
                         }
@@ -170,7 +209,7 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StopWorker25ms => {
                         if true == *data.worker25running.lock().unwrap() {
                             println!("{} stopping ...", work_enum.to_string());
-                            data.sender25.lock().unwrap().send("Stop").expect("Send failed");
+                            queue_command(&data, "Worker25", &data.sender25, "Stop");
                         } else {
                             println!("{} is NOT even running. ", work_enum.to_string());
                         }   
@@ -178,10 +217,14 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StartWorker50ms => {
                         if false == *data.worker50running.lock().unwrap() {
                             println!("{} starting !!", work_enum.to_string()); 
+                            // running before the thread is, for the state snapshot
+                            *data.worker50running.lock().unwrap() = true;
+                            data.store.changed();
                             let _handle50ms = thread::spawn(move || 
                                 worker50ms(work_enum.to_string(), 
                                 &data.receiver50,
-                                &data.worker50running));
+                                &data.worker50running,
+                                &data));
                         } else {
                             println!("{} already running.", work_enum.to_string()); 
                         }
@@ -189,7 +232,7 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StopWorker50ms => {
                         if true == *data.worker50running.lock().unwrap() {
                             println!("{} stopping ...", work_enum.to_string());
-                            data.sender50.lock().unwrap().send("Stop").expect("Send failed");
+                            queue_command(&data, "Worker50", &data.sender50, "Stop");
                         } else {
                             println!("{} is NOT even running. ", work_enum.to_string());
                         }   
@@ -197,10 +240,14 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StartWorker100ms => {
                         if false == *data.worker100running.lock().unwrap() {
                             println!("{} starting !!", work_enum.to_string()); 
+                            // running before the thread is, for the state snapshot
+                            *data.worker100running.lock().unwrap() = true;
+                            data.store.changed();
                             let _handle100ms = thread::spawn(move || 
                                 worker100ms(work_enum.to_string(), 
                                 &data.receiver100,
-                                &data.worker100running));
+                                &data.worker100running,
+                                &data));
                         } else {
                             println!("{} already running.", work_enum.to_string()); 
                         }
@@ -208,7 +255,7 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StopWorker100ms => {
                         if true == *data.worker100running.lock().unwrap() {
                             println!("{} stopping ...", work_enum.to_string());
-                            data.sender100.lock().unwrap().send("Stop").expect("Send failed");
+                            queue_command(&data, "Worker100", &data.sender100, "Stop");
                         } else {
                             println!("{} is NOT even running. ", work_enum.to_string());
                         }   
@@ -216,10 +263,14 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StartWorker250ms => {
                         if false == *data.worker250running.lock().unwrap() {
                             println!("{} starting !!", work_enum.to_string()); 
+                            // running before the thread is, for the state snapshot
+                            *data.worker250running.lock().unwrap() = true;
+                            data.store.changed();
                             let _handle250ms = thread::spawn(move || 
                                 worker250ms(work_enum.to_string(), 
                                 &data.receiver250,
-                                &data.worker250running));
+                                &data.worker250running,
+                                &data));
                         } else {
                             println!("{} already running.", work_enum.to_string()); 
                         }
@@ -227,7 +278,7 @@ async fn execute(payload: web::Json<RequestMessage>,
                     WorkersEnum::StopWorker250ms => {
                         if true == *data.worker250running.lock().unwrap() {
                             println!("{} stopping ...", work_enum.to_string());
-                            data.sender250.lock().unwrap().send("Stop").expect("Send failed");
+                            queue_command(&data, "Worker250", &data.sender250, "Stop");
                         } else {
                             println!("{} is NOT even running. ", work_enum.to_string());
                         }   
@@ -241,15 +292,11 @@ async fn execute(payload: web::Json<RequestMessage>,
             }
         }
 
-        return HttpResponse::Ok().json(ResponseMessage {
-            message: ret_value,
-        })
+        return Ok(ret_value);
 
     
     } else {
-        return HttpResponse::Forbidden().json(ResponseMessage {
-            message: "::. Key? ..".to_owned(),
-        });
+        return Err("::. Key? ..".to_owned());
     }
     
 }
@@ -257,11 +304,14 @@ async fn execute(payload: web::Json<RequestMessage>,
 /////////////////////
 ////// workers //////
 /////////////////////
+// how often a worker publishes a Tick event
+const TICK_EVENT_MS: u64 = 1_000;
+
 fn sleep_ms(ms: u64) {
     thread::sleep(Duration::from_millis(ms))
 }
 
-fn msg_loop(identifier: String, receiver: &Mutex<mpsc::Receiver<&str>>) {
+fn msg_loop(identifier: String, receiver: &Mutex<mpsc::Receiver<&str>>, state: &InstreamState) {
 
     // set doze interval
     let doze: u64 = match identifier.as_str() {
@@ -273,11 +323,21 @@ fn msg_loop(identifier: String, receiver: &Mutex<mpsc::Receiver<&str>>) {
         _ => identifier.parse::<u64>().unwrap_or_default()
     };
 
+    // the name commands use to address this worker
+    let worker = format!("Worker{}", doze);
+    let events = &state.events;
+    events.worker_event(EventKind::WorkerStarted, &worker, identifier.to_string());
+    let mut ticks: u64 = 0;
+    // one Tick event a second, with the number of ticks so far, instead of one per tick
+    let ticks_per_event = (TICK_EVENT_MS / doze.max(1)).max(1);
+
     // common processing & message loop
     loop { 
         match receiver.lock().unwrap().try_recv() {
             Ok(msg) => {
                 println!("Received by {} => {}", identifier, msg);
+                command_received(state, &worker);
+                events.worker_event(EventKind::Command, &worker, msg.to_string());
                 let command_to_execute = CommandEnum::from_str(&msg);
                 match command_to_execute { 
                     Ok(command) => {
@@ -308,6 +368,12 @@ fn msg_loop(identifier: String, receiver: &Mutex<mpsc::Receiver<&str>>) {
 
             Err(mpsc::TryRecvError::Empty) => {
                 // Channel is empty, do other work or sleep
+                // every tick is a timer interrupt for the live runs
+                ticks += 1;
+                if ticks.is_multiple_of(ticks_per_event) {
+                    events.worker_event(EventKind::Tick, &worker, ticks.to_string());
+                }
+                tick(state, doze);
                 sleep_ms(doze);
             }
 
@@ -317,63 +383,70 @@ fn msg_loop(identifier: String, receiver: &Mutex<mpsc::Receiver<&str>>) {
             }
         }
     }
+
+    events.worker_event(EventKind::WorkerStopped, &worker, format!("after {} ticks", ticks));
 }
 
 
-fn worker10ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>) {
+fn worker10ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>, state: &InstreamState) {
     
     *running.lock().unwrap() = true;
     println!("Spawning thread: {}", name);
 
     // when this returns, the worker will exit
-    msg_loop(name, receiver);
+    msg_loop(name, receiver, state);
 
     println!("Thread exiting ..");
     *running.lock().unwrap() = false;
+    state.store.changed();
 }
 
-fn worker25ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>) {
+fn worker25ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>, state: &InstreamState) {
     *running.lock().unwrap() = true;
     println!("Spawning thread: {}", name);
 
     // when this returns, the worker will exit
-    msg_loop(name, receiver);
+    msg_loop(name, receiver, state);
 
     println!("Thread exiting ..");
     *running.lock().unwrap() = false;
+    state.store.changed();
 }
 
-fn worker50ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>) {
+fn worker50ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>, state: &InstreamState) {
     *running.lock().unwrap() = true;
     println!("Spawning thread: {}", name);
 
     // when this returns, the worker will exit
-    msg_loop(name, receiver);
+    msg_loop(name, receiver, state);
 
     println!("Thread exiting ..");
     *running.lock().unwrap() = false;
+    state.store.changed();
 }
 
-fn worker100ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>) {
+fn worker100ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>, state: &InstreamState) {
     *running.lock().unwrap() = true;
     println!("Spawning thread: {}", name);
 
     // when this returns, the worker will exit
-    msg_loop(name, receiver);
+    msg_loop(name, receiver, state);
 
     println!("Thread exiting ..");
     *running.lock().unwrap() = false;
+    state.store.changed();
 }
 
-fn worker250ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>) {
+fn worker250ms(name: String, receiver: &Mutex<mpsc::Receiver<&str>>, running: &Mutex<bool>, state: &InstreamState) {
     *running.lock().unwrap() = true;
     println!("Spawning thread: {}", name);
 
     // when this returns, the worker will exit
-    msg_loop(name, receiver);
+    msg_loop(name, receiver, state);
 
     println!("Thread exiting ..");
     *running.lock().unwrap() = false;
+    state.store.changed();
 }
 /////////////////////
 ////// /workers /////
@@ -0,0 +1,388 @@
+use std::collections::VecDeque;
+
+use crate::models::instruction::{Instruction};
+use crate::models::analysis::{AnalysisReport, BasicBlock, Diagnostic, DiagnosticKind, Edge, EdgeKind};
+use crate::services::executor::{REGISTER_COUNT, REG_ZERO, REG_SP};
+use crate::services::decoder::{self, is_target_error, Op};
+use crate::services::syscall::{SYS_EXIT};
+
+// registers a program should never write to
+pub const RESERVED_REGISTERS: &[u8] = &[REG_ZERO];
+
+// register sets are bit masks, bit n <=> rn
+type RegisterSet = u32;
+const ALL_REGISTERS: RegisterSet = u32::MAX;
+
+fn bit(register: u8) -> RegisterSet {
+    1 << register
+}
+
+// what the analysis needs to know about a single instruction
+struct Node {
+    op: Op,
+    uses: RegisterSet,
+    def: Option<u8>,
+    // successors inside the program, an index equal to the program length means 'falls off the end'
+    successors: Vec<Edge>,
+}
+
+// the registers come from the decoded op (see Op::reads / Op::writes), only the control
+// flow is worked out here. an instruction that does not decode faults when it is executed.
+fn decode(index: usize, instruction: &Instruction, length: usize, diagnostics: &mut Vec<Diagnostic>) -> Node {
+
+    let op = match decoder::decode(instruction, length) {
+        Ok(op) => op,
+        Err(e) => {
+            let kind = if is_target_error(&e) { DiagnosticKind::BranchOutOfRange } else { DiagnosticKind::InvalidInstruction };
+            diagnostics.push(Diagnostic { index, kind, message: e });
+            Op::Illegal
+        }
+    };
+
+    let next = Edge { to: index + 1, kind: EdgeKind::FallThrough };
+    let successors = match op {
+        Op::Halt | Op::Illegal => Vec::new(),
+        Op::Branch { target, .. } => vec![Edge { to: target, kind: EdgeKind::Taken }, next],
+        Op::Jump { target } | Op::UserJump { target } => vec![Edge { to: target, kind: EdgeKind::Jump }],
+        Op::Call { target } => vec![Edge { to: target, kind: EdgeKind::Call }, next],
+        // return edges are filled in once all call sites are known
+        Op::Ret => Vec::new(),
+        // the handler may run at any point after it is installed
+        Op::InterruptVector { target, .. } => vec![Edge { to: target, kind: EdgeKind::Interrupt }, next],
+        Op::ExceptionVector { target, .. } => vec![Edge { to: target, kind: EdgeKind::Exception }, next],
+        // returns to whatever was interrupted
+        Op::InterruptReturn | Op::ExceptionReturn => Vec::new(),
+        Op::SyscallImm { number: SYS_EXIT } => Vec::new(),
+        _ => vec![next],
+    };
+
+    Node { op, uses: op.reads(), def: op.writes().map(|register| register as u8), successors }
+}
+
+fn build_blocks(length: usize, nodes: &[Node], reachable: &[bool]) -> Vec<BasicBlock> {
+
+    let mut leader = vec![false; length];
+    if length > 0 {
+        leader[0] = true;
+    }
+    for (index, node) in nodes.iter().enumerate() {
+        let ends_block = node.successors.len() != 1 || node.successors[0].kind != EdgeKind::FallThrough;
+        for edge in &node.successors {
+            if edge.to < length && edge.kind != EdgeKind::FallThrough {
+                leader[edge.to] = true;
+            }
+        }
+        if ends_block && index + 1 < length {
+            leader[index + 1] = true;
+        }
+    }
+
+    // block id of every instruction
+    let mut block_of = vec![0; length];
+    let mut starts = Vec::new();
+    for index in 0..length {
+        if leader[index] {
+            starts.push(index);
+        }
+        block_of[index] = starts.len() - 1;
+    }
+
+    starts.iter().enumerate().map(|(id, start)| {
+        let end = starts.get(id + 1).map(|next| next - 1).unwrap_or(length - 1);
+        BasicBlock {
+            id,
+            start: *start,
+            end,
+            reachable: reachable[*start],
+            successors: nodes[end].successors.iter()
+                .filter(|edge| edge.to < length)
+                .map(|edge| Edge { to: block_of[edge.to], kind: edge.kind })
+                .collect(),
+        }
+    }).collect()
+}
+
+// builds the control flow graph of a program and lints it.
+// every diagnostic references the instruction index it was found at.
+pub fn analyze(program: &[Instruction]) -> AnalysisReport {
+
+    let length = program.len();
+    let mut diagnostics = Vec::new();
+
+    let mut nodes: Vec<Node> = program.iter()
+        .enumerate()
+        .map(|(index, instruction)| decode(index, instruction, length, &mut diagnostics))
+        .collect();
+
+    // 'ret' may return to any call site
+    let return_points: Vec<usize> = nodes.iter()
+        .enumerate()
+        .filter(|(_, node)| matches!(node.op, Op::Call { .. }))
+        .map(|(index, _)| index + 1)
+        .collect();
+    for node in nodes.iter_mut().filter(|node| node.op == Op::Ret) {
+        node.successors = return_points.iter()
+            .map(|to| Edge { to: *to, kind: EdgeKind::Return })
+            .collect();
+    }
+
+    // reachability from the entry point
+    let mut reachable = vec![false; length];
+    let mut queue = VecDeque::from([0]);
+    while let Some(index) = queue.pop_front() {
+        if index >= length || reachable[index] {
+            continue;
+        }
+        reachable[index] = true;
+        queue.extend(nodes[index].successors.iter().map(|edge| edge.to));
+    }
+
+    // registers that are written on every path reaching an instruction (r0 and sp start out defined)
+    let mut defined_in = vec![ALL_REGISTERS; length];
+    if length > 0 {
+        defined_in[0] = bit(REG_ZERO) | bit(REG_SP);
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in (0..length).filter(|index| reachable[*index]) {
+            let out = defined_in[index] | nodes[index].def.map(bit).unwrap_or(0);
+            for edge in &nodes[index].successors {
+                if edge.to < length && defined_in[edge.to] & out != defined_in[edge.to] {
+                    defined_in[edge.to] &= out;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    // registers whose current value may still be read. all registers are visible in the
+    // run result, so everything is live when the program ends.
+    let mut live_out = vec![0 as RegisterSet; length];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in (0..length).rev() {
+            let node = &nodes[index];
+            let out = if node.successors.is_empty() {
+                ALL_REGISTERS
+            } else {
+                node.successors.iter().fold(0, |live, edge| {
+                    if edge.to >= length {
+                        return ALL_REGISTERS;
+                    }
+                    let next = &nodes[edge.to];
+                    live | next.uses | (live_out[edge.to] & !next.def.map(bit).unwrap_or(0))
+                })
+            };
+            if out != live_out[index] {
+                live_out[index] = out;
+                changed = true;
+            }
+        }
+    }
+
+    for index in 0..length {
+        let node = &nodes[index];
+
+        if !reachable[index] {
+            diagnostics.push(Diagnostic {
+                index,
+                kind: DiagnosticKind::UnreachableInstruction,
+                message: "instruction can never be executed".to_string(),
+            });
+            continue;
+        }
+
+        for register in 0..REGISTER_COUNT as u8 {
+            if node.uses & !defined_in[index] & bit(register) != 0 {
+                diagnostics.push(Diagnostic {
+                    index,
+                    kind: DiagnosticKind::ReadBeforeWrite,
+                    message: format!("r{} may be read before it is written", register),
+                });
+            }
+        }
+
+        if let Some(register) = node.def {
+            if RESERVED_REGISTERS.contains(&register) {
+                diagnostics.push(Diagnostic {
+                    index,
+                    kind: DiagnosticKind::ReservedRegisterWrite,
+                    message: format!("write to reserved register r{}", register),
+                });
+            } else if live_out[index] & bit(register) == 0 && !matches!(node.op, Op::Syscall | Op::SyscallImm { .. }) {
+                // a syscall is executed for its side effects, ignoring its result is fine
+                diagnostics.push(Diagnostic {
+                    index,
+                    kind: DiagnosticKind::DeadStore,
+                    message: format!("value written to r{} is never read", register),
+                });
+            }
+        }
+    }
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.index);
+
+    AnalysisReport {
+        blocks: build_blocks(length, &nodes, &reachable),
+        diagnostics,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(opcode: &str, imdval: &str, regsrc: u8, regext: u8, regdst: u8) -> Instruction {
+        Instruction { opcode: opcode.to_string(), imdval: imdval.to_string(), regsrc, regext, regdst }
+    }
+
+    // (index, kind) of every diagnostic
+    fn found(program: &[Instruction]) -> Vec<(usize, DiagnosticKind)> {
+        analyze(program).diagnostics.iter().map(|diagnostic| (diagnostic.index, diagnostic.kind)).collect()
+    }
+
+    fn messages(program: &[Instruction], kind: DiagnosticKind) -> Vec<String> {
+        analyze(program).diagnostics.into_iter()
+            .filter(|diagnostic| diagnostic.kind == kind)
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    #[test]
+    fn code_after_a_jump_or_halt_is_unreachable() {
+        let program = [
+            instruction("add", "1", 0, 0, 1),
+            instruction("jmp", "3", 0, 0, 0),
+            instruction("add", "2", 0, 0, 1),
+            instruction("st", "", 0, 1, 0),
+            instruction("halt", "", 0, 0, 0),
+            instruction("nop", "", 0, 0, 0),
+        ];
+        let report = analyze(&program);
+        assert_eq!(found(&program), vec![(2, DiagnosticKind::UnreachableInstruction), (5, DiagnosticKind::UnreachableInstruction)]);
+        let reachable: Vec<(usize, bool)> = report.blocks.iter().map(|block| (block.start, block.reachable)).collect();
+        assert_eq!(reachable, vec![(0, true), (2, false), (3, true), (5, false)]);
+    }
+
+    #[test]
+    fn both_sides_of_a_branch_are_reachable() {
+        let program = [
+            instruction("add", "1", 0, 0, 1),
+            instruction("beq", "3", 1, 0, 0),
+            instruction("add", "1", 1, 0, 1),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        assert!(found(&program).is_empty());
+        let report = analyze(&program);
+        let edges: Vec<(usize, EdgeKind)> = report.blocks[0].successors.iter().map(|edge| (edge.to, edge.kind)).collect();
+        assert_eq!(edges, vec![(2, EdgeKind::Taken), (1, EdgeKind::FallThrough)]);
+    }
+
+    #[test]
+    fn ret_returns_after_every_call() {
+        let program = [
+            instruction("call", "3", 0, 0, 0),
+            instruction("call", "3", 0, 0, 0),
+            instruction("halt", "", 0, 0, 0),
+            instruction("ret", "", 0, 0, 0),
+        ];
+        assert!(found(&program).is_empty());
+        let report = analyze(&program);
+        let returns: Vec<usize> = report.blocks.last().unwrap().successors.iter()
+            .filter(|edge| edge.kind == EdgeKind::Return)
+            .map(|edge| report.blocks[edge.to].start)
+            .collect();
+        assert_eq!(returns, vec![1, 2]);
+    }
+
+    #[test]
+    fn overwritten_value_is_a_dead_store() {
+        let program = [
+            instruction("add", "1", 0, 0, 1),
+            instruction("add", "2", 0, 0, 1),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        assert_eq!(found(&program), vec![(0, DiagnosticKind::DeadStore)]);
+    }
+
+    #[test]
+    fn value_read_on_one_path_is_live() {
+        let program = [
+            instruction("add", "1", 0, 0, 1),
+            instruction("beq", "4", 2, 0, 0),
+            instruction("add", "1", 1, 0, 3),
+            instruction("halt", "", 0, 0, 0),
+            instruction("add", "5", 0, 0, 1),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        // r1 is read when the branch is not taken, r2 is never written
+        assert_eq!(found(&program), vec![(1, DiagnosticKind::ReadBeforeWrite)]);
+    }
+
+    #[test]
+    fn register_defined_on_one_path_only_may_be_undefined() {
+        let program = [
+            instruction("beq", "2", 0, 0, 0),
+            instruction("add", "1", 0, 0, 1),
+            instruction("add", "1", 1, 0, 2),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        assert_eq!(messages(&program, DiagnosticKind::ReadBeforeWrite), vec!["r1 may be read before it is written"]);
+
+        // defined on both paths
+        let program = [
+            instruction("add", "1", 0, 0, 1),
+            instruction("beq", "3", 0, 0, 0),
+            instruction("add", "2", 0, 0, 1),
+            instruction("add", "1", 1, 0, 2),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        assert!(messages(&program, DiagnosticKind::ReadBeforeWrite).is_empty());
+    }
+
+    #[test]
+    fn reads_come_from_the_decoded_op() {
+        // cas reads the expected value from its destination
+        let program = [instruction("cas", "", 1, 2, 3), instruction("halt", "", 0, 0, 0)];
+        assert_eq!(messages(&program, DiagnosticKind::ReadBeforeWrite), vec![
+            "r1 may be read before it is written",
+            "r2 may be read before it is written",
+            "r3 may be read before it is written",
+        ]);
+
+        // an immediate replaces regext, fsflags with an immediate reads no register
+        let program = [
+            instruction("add", "4", 1, 9, 2),
+            instruction("fsflags", "1", 7, 0, 0),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        assert_eq!(messages(&program, DiagnosticKind::ReadBeforeWrite), vec!["r1 may be read before it is written"]);
+
+        // exit reads its status only and ends the program
+        let program = [instruction("ecall", "0", 0, 0, 0), instruction("nop", "", 0, 0, 0)];
+        assert_eq!(found(&program), vec![(0, DiagnosticKind::ReadBeforeWrite), (1, DiagnosticKind::UnreachableInstruction)]);
+        assert_eq!(messages(&program, DiagnosticKind::ReadBeforeWrite), vec!["r2 may be read before it is written"]);
+    }
+
+    #[test]
+    fn reports_invalid_instructions() {
+        // none of them decodes, so execution stops at the first one
+        let program = [
+            instruction("add", "1", 0, 0, 0),
+            instruction("jmp", "9", 0, 0, 0),
+            instruction("add", "", 40, 0, 1),
+            instruction("frobnicate", "", 0, 0, 0),
+        ];
+        assert_eq!(found(&program), vec![
+            (0, DiagnosticKind::ReservedRegisterWrite),
+            (1, DiagnosticKind::BranchOutOfRange),
+            (2, DiagnosticKind::InvalidInstruction),
+            (2, DiagnosticKind::UnreachableInstruction),
+            (3, DiagnosticKind::InvalidInstruction),
+            (3, DiagnosticKind::UnreachableInstruction),
+        ]);
+        assert_eq!(messages(&program, DiagnosticKind::InvalidInstruction), vec!["invalid register r40", "illegal opcode 'frobnicate'"]);
+    }
+}
@@ -0,0 +1,849 @@
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::{Entry};
+
+use crate::models::instruction::{Instruction, Operand};
+use crate::models::assembler::{AssemblyError, SourceRef};
+use crate::services::executor::{has_target, is_alu, is_branch, parse_imdval, REG_LINK, REG_SP, REG_ZERO};
+use crate::services::decoder::{decode};
+use crate::services::atomic::{AtomicOp};
+use crate::services::patch::{target_of, with_target};
+
+// text assembler, the syntax is what Instruction's Display renders:
+//
+//     loop:   add r1, r1, 8          ; comments start with ';' or '#'
+//             ld r3, [r1 + 0x10]
+//             blt r1, r2, loop       ; targets are labels or indexes
+//
+// r0..r31 are also zero, sp (r30) and lr (r31), f0..f31 and v0..v31 name the fp and vector
+// registers. on top of the instructions:
+//
+// - pseudo-instructions, expanded to instructions:
+//     mov rd, rs        add rd, rs, r0
+//     li rd, value      add rd, zero, value, or a sequence of shl / or when the value does
+//                       not fit in IMMEDIATE_BITS
+//     push rs, ...      per register: sub sp, sp, 8 and st rs, [sp + 0]
+//     pop rd, ...       per register, the last one first: ld rd, [sp + 0] and add sp, sp, 8,
+//                       so pop takes the registers of the push it undoes
+// - .equ NAME, value    a constant, usable wherever an immediate is, once it is defined
+// - .macro name a, b    a macro with parameters, up to .endm. its parameters are replaced
+//                       word by word, labels defined in it are local to each expansion.
+//                       a macro cannot use itself, not even through other macros.
+// - .include name       the program saved under 'name' in the library, its targets moved
+//                       to where it is included
+//
+// every instruction keeps the source line it comes from, errors are reported on the line
+// the user wrote, with the macros and pseudo-instructions in between. the assembly stops at
+// the first error it cannot go on from: more than MAX_INSTRUCTIONS instructions, or more than
+// MAX_STATEMENTS statements once the macros are expanded (macros using macros grow
+// exponentially, a few lines can stand for billions of them).
+
+// width of the immediates the assembler writes, li splits wider values. the decoder takes
+// any 64 bit value, this keeps programs within what a fixed width encoding could hold.
+pub const IMMEDIATE_BITS: u32 = 32;
+const MAX_DEPTH: usize = 16;
+const MAX_INSTRUCTIONS: usize = 1 << 16;
+const MAX_STATEMENTS: usize = 1 << 18;
+
+// instructions as (opcode, operands), what a pseudo-instruction stands for
+type Expansion = Vec<(String, Vec<String>)>;
+
+struct Macro {
+    params: Vec<String>,
+    // (source line, text)
+    body: Vec<(usize, String)>,
+    line: usize,
+}
+
+pub struct Assembly {
+    pub instructions: Vec<Instruction>,
+    pub source_map: Vec<SourceRef>,
+    pub labels: BTreeMap<String, usize>,
+}
+
+struct Assembler<'a> {
+    lines: Vec<&'a str>,
+    include: &'a dyn Fn(&str) -> Result<Vec<Instruction>, String>,
+    macros: HashMap<String, Macro>,
+    constants: HashMap<String, i64>,
+    labels: BTreeMap<String, usize>,
+    instructions: Vec<Instruction>,
+    source_map: Vec<SourceRef>,
+    errors: Vec<AssemblyError>,
+    expansions: usize,
+    // statements assembled so far, macro bodies included, against MAX_STATEMENTS
+    statements: usize,
+    // the macros being expanded, innermost last
+    expanding: Vec<String>,
+    // set by an error the assembly cannot go on from
+    stopped: bool,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && text.chars().all(is_word)
+}
+
+fn strip_comment(text: &str) -> &str {
+    match text.find([';', '#']) {
+        Some(at) => &text[..at],
+        None => text,
+    }
+}
+
+// replaces whole words of 'text' found in 'words'
+fn replace_words(text: &str, words: &HashMap<String, String>) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once('\n')) {
+        if is_word(c) {
+            word.push(c);
+            continue;
+        }
+        replaced.push_str(words.get(&word).map(String::as_str).unwrap_or(&word));
+        word.clear();
+        if c != '\n' {
+            replaced.push(c);
+        }
+    }
+    replaced
+}
+
+// splits at the commas outside of [ ]
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in text.chars() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            },
+            _ => {},
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+fn register(token: &str, kind: Operand) -> Result<u8, String> {
+    let token = token.trim();
+    let named = match (kind, token) {
+        (Operand::Integer, "zero") => Some(REG_ZERO),
+        (Operand::Integer, "sp") => Some(REG_SP),
+        (Operand::Integer, "lr") => Some(REG_LINK),
+        _ => None,
+    };
+    if let Some(index) = named {
+        return Ok(index);
+    }
+    let prefix = match kind {
+        Operand::Float => 'f',
+        Operand::Vector => 'v',
+        _ => 'r',
+    };
+    token.strip_prefix(prefix)
+        .filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|digits| digits.parse::<u8>().ok())
+        .ok_or_else(|| format!("'{}' is not a{} register", token, match kind {
+            Operand::Float => "n fp",
+            Operand::Vector => " vector",
+            _ => "n integer",
+        }))
+}
+
+fn is_register(token: &str) -> bool {
+    [Operand::Integer, Operand::Float, Operand::Vector].iter().any(|kind| register(token, *kind).is_ok())
+}
+
+// expects 'count' operands
+fn operand_count(opcode: &str, operands: &[String], count: usize) -> Result<(), String> {
+    if operands.len() != count {
+        return Err(format!("'{}' takes {} operand{}, not {}", opcode, count, if count == 1 { "" } else { "s" }, operands.len()));
+    }
+    Ok(())
+}
+
+impl<'a> Assembler<'a> {
+
+    fn source(&self, line: usize, via: &[String]) -> SourceRef {
+        SourceRef {
+            line,
+            text: self.lines.get(line - 1).map(|text| text.trim().to_string()).unwrap_or_default(),
+            via: via.to_vec(),
+        }
+    }
+
+    fn error(&mut self, line: usize, via: &[String], message: String) {
+        let source = self.source(line, via);
+        self.errors.push(AssemblyError { source, message });
+    }
+
+    // a literal is kept as written, a constant becomes its value. anything else is left for
+    // the labels (or the decoder) to make sense of.
+    fn immediate(&self, token: &str) -> String {
+        let token = token.trim();
+        match self.constants.get(token) {
+            Some(value) => value.to_string(),
+            None => match token.strip_prefix('-').and_then(|name| self.constants.get(name.trim())) {
+                Some(value) => value.wrapping_neg().to_string(),
+                None => token.to_string(),
+            },
+        }
+    }
+
+    // an immediate needed while expanding (.equ, li), labels are not known yet
+    fn value(&self, token: &str) -> Result<i64, String> {
+        match parse_imdval(&self.immediate(token)) {
+            Ok(Some(value)) => Ok(value),
+            _ if is_identifier(token.trim()) => Err(format!("'{}' is not a constant defined above", token.trim())),
+            _ => Err(format!("'{}' is not a value", token.trim())),
+        }
+    }
+
+    // "[rs]", "[rs + offset]" or "[rs - offset]" => (rs, imdval)
+    fn memory(&self, token: &str) -> Result<(u8, String), String> {
+        let inner = token.trim()
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+            .ok_or_else(|| format!("'{}' is not a memory operand like [r1 + 8]", token))?;
+        let (base, offset) = match inner.find(['+', '-']) {
+            Some(at) => {
+                let offset = inner[at + 1..].trim();
+                let offset = if &inner[at..at + 1] == "-" { format!("-{}", offset) } else { offset.to_string() };
+                (&inner[..at], self.immediate(&offset))
+            },
+            None => (inner, "0x".to_string()),
+        };
+        Ok((register(base, Operand::Integer)?, offset))
+    }
+
+    fn encode(&self, opcode: &str, operands: &[String]) -> Result<Instruction, String> {
+
+        let mut instruction = Instruction {
+            opcode: opcode.to_string(),
+            imdval: "0x".to_string(),
+            regsrc: 0,
+            regext: 0,
+            regdst: 0,
+        };
+        let integer = |token: &String| register(token, Operand::Integer);
+
+        // fp and vector opcodes, their operands follow the register files they use
+        if let Some((dst, src, ext)) = crate::models::instruction::operands(opcode) {
+            match opcode {
+                "flw" | "fld" | "vld" => {
+                    operand_count(opcode, operands, 2)?;
+                    instruction.regdst = register(&operands[0], dst)?;
+                    (instruction.regsrc, instruction.imdval) = self.memory(&operands[1])?;
+                },
+                "fsw" | "fsd" | "vst" => {
+                    operand_count(opcode, operands, 2)?;
+                    instruction.regext = register(&operands[0], ext)?;
+                    (instruction.regsrc, instruction.imdval) = self.memory(&operands[1])?;
+                },
+                "fli.s" | "fli.d" => {
+                    operand_count(opcode, operands, 2)?;
+                    instruction.regdst = register(&operands[0], Operand::Float)?;
+                    instruction.imdval = self.immediate(&operands[1]);
+                },
+                "fsflags" | "fsrm" => {
+                    operand_count(opcode, operands, 1)?;
+                    match integer(&operands[0]) {
+                        Ok(src) => instruction.regsrc = src,
+                        Err(_) => instruction.imdval = self.immediate(&operands[0]),
+                    }
+                },
+                _ => {
+                    // an immediate takes the place of the last register, or follows them
+                    // (the lane of vext / vins)
+                    let slots: Vec<(Operand, usize)> = [(dst, 0), (src, 1), (ext, 2)].into_iter()
+                        .filter(|(operand, _)| *operand != Operand::Unused)
+                        .collect();
+                    if operands.len() < slots.len() || operands.len() > slots.len() + 1 {
+                        return Err(format!("'{}' takes {} operands, not {}", opcode, slots.len(), operands.len()));
+                    }
+                    for (at, token) in operands.iter().enumerate() {
+                        let last = at + 1 == operands.len();
+                        match slots.get(at) {
+                            Some((kind, field)) if !last || is_register(token) => {
+                                let index = register(token, *kind)?;
+                                match field {
+                                    0 => instruction.regdst = index,
+                                    1 => instruction.regsrc = index,
+                                    _ => instruction.regext = index,
+                                }
+                            },
+                            _ if last => instruction.imdval = self.immediate(token),
+                            _ => return Err(format!("operand {} of '{}' is a register", at + 1, opcode)),
+                        }
+                    }
+                },
+            }
+            return Ok(instruction);
+        }
+
+        match opcode {
+            "nop" | "halt" | "ret" | "ei" | "di" | "iret" | "xret" | "wfi" | "brk" | "fence" | "tlbflush" => {
+                operand_count(opcode, operands, 0)?;
+            },
+            "ien" | "idis" | "iack" | "iraise" => {
+                operand_count(opcode, operands, 1)?;
+                match integer(&operands[0]) {
+                    Ok(src) => instruction.regsrc = src,
+                    Err(_) => instruction.imdval = self.immediate(&operands[0]),
+                }
+            },
+            "ecall" | "syscall" => {
+                if !operands.is_empty() {
+                    operand_count(opcode, operands, 1)?;
+                    instruction.imdval = self.immediate(&operands[0]);
+                }
+            },
+            "ipend" | "coreid" | "ncores" | "xcause" | "xepc" | "xaddr" | "priv" => {
+                operand_count(opcode, operands, 1)?;
+                instruction.regdst = integer(&operands[0])?;
+            },
+            "xsetepc" | "ptroot" => {
+                operand_count(opcode, operands, 1)?;
+                instruction.regsrc = integer(&operands[0])?;
+            },
+            "ivec" | "xvec" | "mpuperm" => {
+                operand_count(opcode, operands, 2)?;
+                instruction.regsrc = integer(&operands[0])?;
+                instruction.imdval = self.immediate(&operands[1]);
+            },
+            "mpubase" => {
+                operand_count(opcode, operands, 3)?;
+                instruction.regsrc = integer(&operands[0])?;
+                instruction.regext = integer(&operands[1])?;
+                instruction.imdval = self.immediate(&operands[2]);
+            },
+            "jmp" | "call" | "ujmp" => {
+                operand_count(opcode, operands, 1)?;
+                instruction.imdval = self.immediate(&operands[0]);
+            },
+            "stwcx." | "strex" => {
+                operand_count(opcode, operands, 3)?;
+                instruction.regdst = integer(&operands[0])?;
+                instruction.regext = integer(&operands[1])?;
+                (instruction.regsrc, instruction.imdval) = self.memory(&operands[2])?;
+            },
+            "ld" | "ldb" | "lwarx" | "ldrex" => {
+                operand_count(opcode, operands, 2)?;
+                instruction.regdst = integer(&operands[0])?;
+                (instruction.regsrc, instruction.imdval) = self.memory(&operands[1])?;
+            },
+            "st" | "stb" => {
+                operand_count(opcode, operands, 2)?;
+                instruction.regext = integer(&operands[0])?;
+                (instruction.regsrc, instruction.imdval) = self.memory(&operands[1])?;
+            },
+            _ if AtomicOp::from_opcode(opcode).is_some() => {
+                operand_count(opcode, operands, 3)?;
+                instruction.regdst = integer(&operands[0])?;
+                instruction.regext = integer(&operands[1])?;
+                (instruction.regsrc, instruction.imdval) = self.memory(&operands[2])?;
+            },
+            _ if is_branch(opcode) => {
+                operand_count(opcode, operands, 3)?;
+                instruction.regsrc = integer(&operands[0])?;
+                instruction.regext = integer(&operands[1])?;
+                instruction.imdval = self.immediate(&operands[2]);
+            },
+            _ if is_alu(opcode) => {
+                operand_count(opcode, operands, 3)?;
+                instruction.regdst = integer(&operands[0])?;
+                instruction.regsrc = integer(&operands[1])?;
+                match integer(&operands[2]) {
+                    Ok(ext) => instruction.regext = ext,
+                    Err(_) => instruction.imdval = self.immediate(&operands[2]),
+                }
+            },
+            _ => return Err(format!("unknown opcode or macro '{}'", opcode)),
+        }
+        Ok(instruction)
+    }
+
+    // the instructions of a pseudo-instruction, as (opcode, operands). None for opcodes.
+    fn pseudo(&self, opcode: &str, operands: &[String]) -> Option<Result<Expansion, String>> {
+        let line = |opcode: &str, operands: &[&str]| (opcode.to_string(), operands.iter().map(|operand| operand.to_string()).collect());
+        let expanded = match opcode {
+            "mov" => operand_count(opcode, operands, 2).and_then(|_| {
+                match register(&operands[1], Operand::Integer) {
+                    Ok(_) => Ok(vec![line("add", &[operands[0].as_str(), operands[1].as_str(), "r0"])]),
+                    Err(_) => self.load_immediate(&operands[0], &operands[1]),
+                }
+            }),
+            "li" => operand_count(opcode, operands, 2).and_then(|_| self.load_immediate(&operands[0], &operands[1])),
+            "push" | "pop" if operands.is_empty() => Err(format!("'{}' takes at least one register", opcode)),
+            "push" => Ok(operands.iter()
+                .flat_map(|register| [line("sub", &["sp", "sp", "8"]), line("st", &[register.as_str(), "[sp + 0]"])])
+                .collect()),
+            "pop" => Ok(operands.iter()
+                .rev()
+                .flat_map(|register| [line("ld", &[register.as_str(), "[sp + 0]"]), line("add", &["sp", "sp", "8"])])
+                .collect()),
+            _ => return None,
+        };
+        Some(expanded)
+    }
+
+    fn load_immediate(&self, destination: &str, token: &str) -> Result<Expansion, String> {
+        register(destination, Operand::Integer)?;
+        let text = |opcode: &str, operand: String| (opcode.to_string(), vec![destination.to_string(), destination.to_string(), operand]);
+
+        // a label is an index, which always fits
+        if is_identifier(token.trim()) && !self.constants.contains_key(token.trim()) {
+            return Ok(vec![("add".to_string(), vec![destination.to_string(), "zero".to_string(), token.trim().to_string()])]);
+        }
+        let value = self.value(token)?;
+        let half = IMMEDIATE_BITS / 2;
+        let fits = |value: i64| value >> (IMMEDIATE_BITS - 1) == 0 || value >> (IMMEDIATE_BITS - 1) == -1;
+        if fits(value) {
+            return Ok(vec![("add".to_string(), vec![destination.to_string(), "zero".to_string(), self.immediate(token)])]);
+        }
+
+        // the upper bits first, then 'half' bits at a time
+        let mut shift = 0;
+        while !fits(value >> shift) {
+            shift += half;
+        }
+        let mut expanded = vec![("add".to_string(), vec![destination.to_string(), "zero".to_string(), (value >> shift).to_string()])];
+        let mask = (1i64 << half) - 1;
+        while shift > 0 {
+            shift -= half;
+            expanded.push(text("shl", half.to_string()));
+            let chunk = (value >> shift) & mask;
+            if chunk != 0 {
+                expanded.push(text("or", format!("0x{:x}", chunk)));
+            }
+        }
+        Ok(expanded)
+    }
+
+    fn emit(&mut self, instruction: Instruction, source: SourceRef) -> Result<(), String> {
+        if self.instructions.len() >= MAX_INSTRUCTIONS {
+            self.stopped = true;
+            return Err(format!("more than {} instructions", MAX_INSTRUCTIONS));
+        }
+        self.instructions.push(instruction);
+        self.source_map.push(source);
+        Ok(())
+    }
+
+    fn label(&mut self, name: &str) -> Result<(), String> {
+        if !is_identifier(name) || is_register(name) {
+            return Err(format!("'{}' cannot be a label", name));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(format!("'{}' is defined twice", name));
+        }
+        self.labels.insert(name.to_string(), self.instructions.len());
+        Ok(())
+    }
+
+    fn include(&mut self, name: &str, line: usize, via: &[String]) -> Result<(), String> {
+        let included = (self.include)(name)?;
+        let offset = self.instructions.len();
+        for (at, mut instruction) in included.into_iter().enumerate() {
+            if has_target(&instruction.opcode) {
+                if let Some(target) = target_of(&instruction) {
+                    instruction.imdval = with_target(&instruction.imdval, target + offset);
+                }
+            }
+            let mut via = via.to_vec();
+            via.push(format!("instruction {} of library program '{}'", at, name));
+            let source = self.source(line, &via);
+            self.emit(instruction, source)?;
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, directive: &str, rest: &str, line: usize, via: &[String]) -> Result<(), String> {
+        match directive {
+            ".equ" => {
+                let operands = split_operands(rest);
+                operand_count(".equ", &operands, 2)?;
+                let name = operands[0].as_str();
+                if !is_identifier(name) || is_register(name) {
+                    return Err(format!("'{}' cannot be a constant", name));
+                }
+                if self.constants.contains_key(name) || self.labels.contains_key(name) {
+                    return Err(format!("'{}' is defined twice", name));
+                }
+                let value = self.value(&operands[1])?;
+                self.constants.insert(name.to_string(), value);
+                Ok(())
+            },
+            ".include" => {
+                let name = rest.trim();
+                if name.is_empty() {
+                    return Err(".include takes the name of a library program".to_string());
+                }
+                self.include(name, line, via)
+            },
+            ".endm" => Err(".endm without .macro".to_string()),
+            _ => Err(format!("unknown directive '{}'", directive)),
+        }
+    }
+
+    fn expand(&mut self, name: &str, operands: &[String], line: usize, via: &[String]) -> Result<(), String> {
+        if self.expanding.iter().any(|expanding| expanding == name) {
+            return Err(format!("macro '{}' uses itself", name));
+        }
+        if via.len() >= MAX_DEPTH {
+            return Err(format!("macros nested deeper than {}", MAX_DEPTH));
+        }
+        let (words, body, defined) = {
+            let definition = &self.macros[name];
+            if operands.len() != definition.params.len() {
+                return Err(format!("macro '{}' takes {} arguments, not {}", name, definition.params.len(), operands.len()));
+            }
+            let mut words: HashMap<String, String> = definition.params.iter().cloned().zip(operands.iter().cloned()).collect();
+            // labels of the macro are renamed per expansion
+            self.expansions += 1;
+            for (_, text) in &definition.body {
+                let text = strip_comment(text).trim();
+                if let Some((label, _)) = text.split_once(':') {
+                    if is_identifier(label.trim()) {
+                        words.insert(label.trim().to_string(), format!("{}.{}", label.trim(), self.expansions));
+                    }
+                }
+            }
+            (words, definition.body.clone(), definition.line)
+        };
+        self.expanding.push(name.to_string());
+        for (body_line, text) in body {
+            if self.stopped {
+                break;
+            }
+            let text = replace_words(strip_comment(&text), &words);
+            let mut via = via.to_vec();
+            via.push(format!("macro '{}' (line {} of the macro at line {}): {}", name, body_line, defined, text.trim()));
+            self.statement(&text, line, &via);
+        }
+        self.expanding.pop();
+        Ok(())
+    }
+
+    // one line without its comment, 'line' is where it ends up in the user's source
+    fn statement(&mut self, text: &str, line: usize, via: &[String]) {
+        if self.stopped {
+            return;
+        }
+        self.statements += 1;
+        if self.statements > MAX_STATEMENTS {
+            self.stopped = true;
+            self.error(line, via, format!("more than {} statements once the macros are expanded", MAX_STATEMENTS));
+            return;
+        }
+        let mut text = text.trim();
+
+        // labels, any number of them
+        while let Some((label, rest)) = text.split_once(':') {
+            if !is_identifier(label.trim()) {
+                break;
+            }
+            if let Err(e) = self.label(label.trim()) {
+                self.error(line, via, e);
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            return;
+        }
+
+        let (opcode, rest) = match text.split_once(char::is_whitespace) {
+            Some((opcode, rest)) => (opcode.to_lowercase(), rest.trim()),
+            None => (text.to_lowercase(), ""),
+        };
+        let result = if opcode.starts_with('.') {
+            self.directive(&opcode, rest, line, via)
+        } else if self.macros.contains_key(&opcode) {
+            self.expand(&opcode, &split_operands(rest), line, via)
+        } else if let Some(expanded) = self.pseudo(&opcode, &split_operands(rest)) {
+            expanded.and_then(|expanded| {
+                let mut via = via.to_vec();
+                via.push(format!("pseudo-instruction '{}'", opcode));
+                for (opcode, operands) in expanded {
+                    let instruction = self.encode(&opcode, &operands)?;
+                    let source = self.source(line, &via);
+                    self.emit(instruction, source)?;
+                }
+                Ok(())
+            })
+        } else {
+            self.encode(&opcode, &split_operands(rest))
+                .and_then(|instruction| {
+                    let source = self.source(line, via);
+                    self.emit(instruction, source)
+                })
+        };
+        if let Err(e) = result {
+            self.error(line, via, e);
+        }
+    }
+
+    fn run(&mut self) {
+        let mut at = 0;
+        while at < self.lines.len() && !self.stopped {
+            let line = at + 1;
+            let text = strip_comment(self.lines[at]).trim().to_string();
+            at += 1;
+
+            let definition = text.strip_prefix(".macro").filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace));
+            let Some(definition) = definition else {
+                self.statement(&text, line, &[]);
+                continue;
+            };
+
+            // the body up to .endm, kept as text until the macro is used
+            let (name, params) = match definition.trim().split_once(char::is_whitespace) {
+                Some((name, params)) => (name.to_lowercase(), split_operands(params)),
+                None => (definition.trim().to_lowercase(), Vec::new()),
+            };
+            let mut body = Vec::new();
+            let mut closed = false;
+            while at < self.lines.len() {
+                let text = strip_comment(self.lines[at]).trim();
+                at += 1;
+                if text == ".endm" {
+                    closed = true;
+                    break;
+                }
+                if text.starts_with(".macro") {
+                    self.error(at, &[], "macros cannot be defined inside a macro".to_string());
+                    continue;
+                }
+                body.push((at, self.lines[at - 1].to_string()));
+            }
+            if !closed {
+                self.error(line, &[], format!("macro '{}' has no .endm", name));
+            } else if !is_identifier(&name) {
+                self.error(line, &[], format!("'{}' cannot be a macro", name));
+            } else if let Some(param) = params.iter().find(|param| !is_identifier(param)) {
+                self.error(line, &[], format!("'{}' cannot be a parameter", param));
+            } else if let Entry::Vacant(entry) = self.macros.entry(name.clone()) {
+                entry.insert(Macro { params, body, line });
+            } else {
+                self.error(line, &[], format!("macro '{}' is defined twice", name));
+            }
+        }
+    }
+
+    // labels into their indexes, then every instruction through the decoder
+    fn resolve(&mut self) {
+        let length = self.instructions.len();
+        for at in 0..length {
+            let imdval = self.instructions[at].imdval.trim().to_string();
+            if let Some(index) = self.labels.get(&imdval) {
+                self.instructions[at].imdval = index.to_string();
+            }
+            if let Err(e) = decode(&self.instructions[at], length) {
+                let message = if is_identifier(&imdval) && !self.labels.contains_key(&imdval) && parse_imdval(&imdval).is_err() {
+                    format!("unknown label or constant '{}'", imdval)
+                } else {
+                    e
+                };
+                let source = self.source_map[at].clone();
+                self.errors.push(AssemblyError { source, message });
+            }
+        }
+    }
+}
+
+// 'include' returns the program saved under a name in the library
+pub fn assemble(source: &str, include: &dyn Fn(&str) -> Result<Vec<Instruction>, String>) -> Result<Assembly, Vec<AssemblyError>> {
+    let mut assembler = Assembler {
+        lines: source.lines().collect(),
+        include,
+        macros: HashMap::new(),
+        constants: HashMap::new(),
+        labels: BTreeMap::new(),
+        instructions: Vec::new(),
+        source_map: Vec::new(),
+        errors: Vec::new(),
+        expansions: 0,
+        statements: 0,
+        expanding: Vec::new(),
+        stopped: false,
+    };
+    assembler.run();
+    // labels past the point the assembly stopped at are missing, their errors would only
+    // hide the one that stopped it
+    if !assembler.stopped {
+        assembler.resolve();
+    }
+    if !assembler.errors.is_empty() {
+        assembler.errors.sort_by_key(|error| error.source.line);
+        return Err(assembler.errors);
+    }
+    Ok(Assembly {
+        instructions: assembler.instructions,
+        source_map: assembler.source_map,
+        labels: assembler.labels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::run::{RunStatus};
+    use crate::services::executor::{Machine};
+
+    fn no_library(name: &str) -> Result<Vec<Instruction>, String> {
+        Err(format!("no program '{}'", name))
+    }
+
+    fn program(source: &str) -> Vec<Instruction> {
+        assemble(source, &no_library)
+            .map(|assembly| assembly.instructions)
+            .unwrap_or_else(|errors| panic!("{:?}", errors))
+    }
+
+    fn errors(source: &str) -> Vec<AssemblyError> {
+        assemble(source, &no_library).err().expect("assembled")
+    }
+
+    fn run(source: &str) -> Machine {
+        let mut machine = Machine::new(program(source));
+        assert_eq!(machine.run(10_000), RunStatus::Halted);
+        machine
+    }
+
+    #[test]
+    fn li_splits_wide_values() {
+        for value in [0x7fff_ffffi64, -0x8000_0000, 0x1_0000_0000, 0x1234_5678_9abc_def0, -0x1234_5678_9abc_def0, i64::MAX, i64::MIN + 1, -2] {
+            let machine = run(&format!("li r1, {}\nhalt", value));
+            assert_eq!(machine.registers[1], value as u64, "li r1, {}", value);
+        }
+        // values that fit take one instruction
+        assert_eq!(program("li r1, -0x80000000").len(), 1);
+        assert!(program("li r1, 0x123456789abcdef0").len() > 1);
+        // no immediate is wider than IMMEDIATE_BITS
+        for instruction in program("li r1, -0x123456789abcdef0") {
+            let value = parse_imdval(&instruction.imdval).unwrap().unwrap();
+            assert!(value >> (IMMEDIATE_BITS - 1) == 0 || value >> (IMMEDIATE_BITS - 1) == -1);
+        }
+    }
+
+    #[test]
+    fn pop_undoes_push() {
+        let machine = run("
+            li r1, 1
+            li r2, 2
+            li r3, 3
+            push r1, r2, r3
+            li r1, 0
+            li r2, 0
+            li r3, 0
+            pop r1, r2, r3
+            halt");
+        assert_eq!(&machine.registers[1..4], &[1, 2, 3]);
+        assert_eq!(machine.registers[REG_SP as usize], Machine::new(Vec::new()).registers[REG_SP as usize]);
+    }
+
+    #[test]
+    fn macro_labels_are_local() {
+        let source = "
+            .macro countdown reg
+            again: sub reg, reg, 1
+                   bne reg, zero, again
+            .endm
+            li r1, 3
+            countdown r1
+            li r2, 5
+            countdown r2
+            halt";
+        let assembly = assemble(source, &no_library).unwrap();
+        assert_eq!(assembly.labels.len(), 2);
+        // each expansion branches to its own label
+        assert_eq!(assembly.instructions[2].imdval, "1");
+        assert_eq!(assembly.instructions[5].imdval, "4");
+        let machine = run(source);
+        assert_eq!(&machine.registers[1..3], &[0, 0]);
+    }
+
+    #[test]
+    fn constants_are_defined_before_they_are_used() {
+        let errors = errors("add r1, r1, STEP\n.equ STEP, 8\n.equ TWICE, DOUBLE\n.equ DOUBLE, 16");
+        let messages: Vec<(usize, &str)> = errors.iter().map(|error| (error.source.line, error.message.as_str())).collect();
+        assert_eq!(messages, vec![
+            (1, "unknown label or constant 'STEP'"),
+            (3, "'DOUBLE' is not a constant defined above"),
+        ]);
+        assert_eq!(program(".equ STEP, 8\nadd r1, r1, STEP")[0].imdval, "8");
+    }
+
+    #[test]
+    fn included_targets_are_moved() {
+        let library = |name: &str| match name {
+            "spin" => Ok(program("add r1, r1, 1\nblt r1, r2, 0\nret")),
+            _ => no_library(name),
+        };
+        let assembly = assemble("li r2, 4\ncall spin\nhalt\nspin:\n.include spin", &library).unwrap();
+        assert_eq!(assembly.labels["spin"], 3);
+        assert_eq!(assembly.instructions[4].imdval, "3");
+        assert_eq!(assembly.source_map[4].line, 5);
+        assert_eq!(assembly.source_map[4].via, vec!["instruction 1 of library program 'spin'".to_string()]);
+
+        let errors = assemble(".include missing", &library).err().unwrap();
+        assert_eq!(errors[0].message, "no program 'missing'");
+    }
+
+    #[test]
+    fn errors_in_macros_are_on_the_line_using_them() {
+        let errors = errors("
+            .macro load reg, value
+                li reg, value
+                add reg, reg, nowhere
+            .endm
+            nop
+            load r1, 5");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].source.line, 7);
+        assert_eq!(errors[0].source.text, "load r1, 5");
+        assert_eq!(errors[0].message, "unknown label or constant 'nowhere'");
+        assert_eq!(errors[0].source.via, vec!["macro 'load' (line 4 of the macro at line 2): add r1, r1, nowhere".to_string()]);
+    }
+
+    #[test]
+    fn macros_cannot_grow_without_bound() {
+        // 8^16 statements, stopped at MAX_STATEMENTS
+        let mut source = ".macro m0\nnop\n.endm\n".to_string();
+        for level in 1..16 {
+            source.push_str(&format!(".macro m{}\n{}.endm\n", level, format!("m{}\n", level - 1).repeat(8)));
+        }
+        source.push_str("m15\nnop\nbad r1\n");
+        let errors = errors(&source);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, format!("more than {} instructions", MAX_INSTRUCTIONS));
+
+        let source = source.replace("nop\n.endm", ".endm");
+        let errors = self::errors(&source);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, format!("more than {} statements once the macros are expanded", MAX_STATEMENTS));
+    }
+
+    #[test]
+    fn macros_cannot_use_themselves() {
+        let errors = errors(&format!(".macro a\n{}.endm\na\nhalt", "a\n".repeat(8)));
+        assert_eq!(errors.len(), 8);
+        assert!(errors.iter().all(|error| error.message == "macro 'a' uses itself"));
+
+        let errors = self::errors(".macro a\nb\n.endm\n.macro b\na\n.endm\na");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "macro 'a' uses itself");
+        assert_eq!(errors[0].source.via.len(), 2);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::services::executor::{Machine};
+use crate::services::device::{is_mmio};
+use crate::services::decoder::{Op};
+use crate::services::exception::{check_alignment, Exception};
+use crate::models::exception::{ExceptionKind};
+use crate::services::protection::{PERMISSION_READ, PERMISSION_WRITE};
+
+// atomic memory operations for multi-core programs, see services/core.rs.
+//
+//  amoadd      rd = memory[rs + imdval], memory[rs + imdval] += rx          (64 bit)
+//  amoswap     rd = memory[rs + imdval], memory[rs + imdval] = rx           (64 bit)
+//  cas         if memory[rs + imdval] == rd then memory[rs + imdval] = rx;
+//              rd = the old value, so the swap happened when rd is unchanged (64 bit)
+//  lwarx       rd = memory[rs + imdval], reserves the address               (32 bit, zero extended)
+//  stwcx.      memory[rs + imdval] = rx if the reservation still holds,
+//              rd = 1 when stored and 0 otherwise                           (32 bit)
+//  ldrex       like lwarx                                                   (64 bit)
+//  strex       like stwcx., but rd = 0 when stored and 1 otherwise          (64 bit)
+//
+// every core holds at most one reservation, for the RESERVATION_GRANULE aligned bytes
+// around the reserved address. a store of any core into the granule (st, stb, fsd, vst,
+// an atomic, a successful store conditional or a read syscall) ends the reservations of
+// all cores, and so does entering an interrupt handler for the core that enters it.
+// a store conditional always ends the reservation of its core, also when it fails.
+//
+// atomics are naturally aligned and do not reach the memory mapped devices, anything else
+// faults. the reads and writes are passed to the cache simulator like those of ld and st.
+
+pub const RESERVATION_GRANULE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AtomicOp {
+    Add,
+    Swap,
+    CompareSwap,
+}
+
+impl AtomicOp {
+    pub fn from_opcode(opcode: &str) -> Option<AtomicOp> {
+        match opcode {
+            "amoadd" => Some(AtomicOp::Add),
+            "amoswap" => Some(AtomicOp::Swap),
+            "cas" => Some(AtomicOp::CompareSwap),
+            _ => None,
+        }
+    }
+}
+
+fn granule(address: usize) -> usize {
+    address & !(RESERVATION_GRANULE - 1)
+}
+
+impl Machine {
+
+    // the data memory address of an atomic access
+    fn atomic_address(&mut self, base: usize, offset: u64, size: usize, access: u8) -> Result<usize, Exception> {
+        let address = self.registers[base].wrapping_add(offset);
+        if is_mmio(address) {
+            let message = format!("atomic access to device memory at 0x{:x}", address);
+            return Err(Exception::at(ExceptionKind::AccessFault, address, message));
+        }
+        check_alignment(address, size)?;
+        self.address(base, offset, size, access)
+    }
+
+    // a store to memory[address..address + size] ends every reservation it touches
+    #[inline(always)]
+    pub(crate) fn invalidate_reservations(&mut self, address: usize, size: usize) {
+        let (first, last) = (granule(address), granule(address + size.max(1) - 1));
+        let touches = |reservation: Option<usize>| reservation.is_some_and(|reserved| reserved >= first && reserved <= last);
+        if touches(self.reservation) {
+            self.reservation = None;
+        }
+        for core in self.cores.iter_mut() {
+            if touches(core.reservation) {
+                core.reservation = None;
+            }
+        }
+    }
+
+    // atomics go around the store buffer, they wait for the stores before them
+    pub(crate) fn step_atomic(&mut self, pc: usize, op: Op) -> Result<(), Exception> {
+        self.drain_stores();
+        match op {
+            Op::Atomic { op, dst, base, value, offset } => {
+                let address = self.atomic_address(base, offset, 8, PERMISSION_READ | PERMISSION_WRITE)?;
+                let old = self.read(pc, address, 8);
+                let new = match op {
+                    AtomicOp::Add => Some(old.wrapping_add(self.registers[value])),
+                    AtomicOp::Swap => Some(self.registers[value]),
+                    AtomicOp::CompareSwap => (old == self.registers[dst]).then_some(self.registers[value]),
+                };
+                if let Some(new) = new {
+                    self.write(pc, address, 8, new);
+                }
+                self.set(dst, old);
+            },
+            Op::LoadReserved { size, dst, base, offset } => {
+                let address = self.atomic_address(base, offset, size, PERMISSION_READ)?;
+                let value = self.read(pc, address, size);
+                self.reservation = Some(granule(address));
+                self.set(dst, value);
+            },
+            Op::StoreConditional { size, success, dst, value, base, offset } => {
+                let address = self.atomic_address(base, offset, size, PERMISSION_WRITE)?;
+                let reserved = self.reservation.take() == Some(granule(address));
+                if reserved {
+                    self.write(pc, address, size, self.registers[value]);
+                }
+                self.set(dst, if reserved { success } else { success ^ 1 });
+            },
+            _ => {},
+        }
+        self.drain_stores();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::models::instruction::{Instruction};
+    use crate::models::consistency::{LitmusRequest, MemoryModel};
+    use crate::models::run::{RunStatus};
+    use crate::services::assembler::{assemble};
+    use crate::services::executor::{Machine};
+    use crate::services::litmus::{explore};
+
+    fn program(source: &str) -> Vec<Instruction> {
+        assemble(source, &|name: &str| Err(format!("no program '{}'", name)))
+            .map(|assembly| assembly.instructions)
+            .unwrap_or_else(|errors| panic!("{:?}", errors))
+    }
+
+    // every outcome of every interleaving, as the observed values in the order of 'observe'
+    fn outcomes(source: &str, cores: usize, memory_model: MemoryModel, observe: &[&str]) -> BTreeSet<Vec<u64>> {
+        let request = LitmusRequest {
+            cores,
+            memory_model,
+            observe: observe.iter().map(|name| name.to_string()).collect(),
+            memory: BTreeMap::new(),
+            exists: None,
+            max_states: None,
+            max_steps: None,
+        };
+        let result = explore(program(source), &request).unwrap();
+        assert!(result.complete);
+        result.outcomes.iter().map(|outcome| {
+            assert_eq!(outcome.fault, None);
+            observe.iter().map(|name| outcome.values[*name]).collect()
+        }).collect()
+    }
+
+    fn word(machine: &Machine, address: usize) -> u64 {
+        u64::from_le_bytes(machine.memory[address..address + 8].try_into().unwrap())
+    }
+
+    const MODELS: [MemoryModel; 3] = [MemoryModel::Sequential, MemoryModel::Tso, MemoryModel::Relaxed];
+
+    const AMOADD_COUNTER: &str = "
+        add r3, r0, 1
+        amoadd r1, r3, [r0 + 0x100]
+        amoadd r1, r3, [r0 + 0x100]
+        halt";
+
+    const CAS_COUNTER: &str = "
+        add r5, r0, 2
+    retry:
+        ld r1, [r0 + 0x100]
+        add r4, r1, 0
+        add r2, r1, 1
+        cas r1, r2, [r0 + 0x100]
+        bne r1, r4, retry
+        sub r5, r5, 1
+        bne r5, r0, retry
+        halt";
+
+    #[test]
+    fn atomic_counters_lose_no_increments() {
+        for model in MODELS {
+            for counter in [AMOADD_COUNTER, CAS_COUNTER] {
+                assert_eq!(outcomes(counter, 2, model, &["[0x100]"]), BTreeSet::from([vec![4]]), "{:?}", model);
+            }
+        }
+
+        // the same counter with a plain load and store can lose one
+        let racy = "
+            ld r1, [r0 + 0x100]
+            add r1, r1, 1
+            st r1, [r0 + 0x100]
+            halt";
+        assert_eq!(outcomes(racy, 2, MemoryModel::Sequential, &["[0x100]"]), BTreeSet::from([vec![1], vec![2]]));
+    }
+
+    #[test]
+    fn atomic_counters_hold_for_any_quantum() {
+        let counter = "
+            add r3, r0, 1
+            add r5, r0, 10
+        again:
+            amoadd r1, r3, [r0 + 0x100]
+            sub r5, r5, 1
+            bne r5, r0, again
+            halt";
+        for source in [counter, CAS_COUNTER] {
+            for model in MODELS {
+                for quantum in 1..=7 {
+                    let mut machine = Machine::new(program(source));
+                    machine.memory_model = model;
+                    machine.set_cores(3, quantum).unwrap();
+                    assert_eq!(machine.run(100_000), RunStatus::Halted);
+                    machine.drain_stores();
+                    let expected = if source == counter { 30 } else { 6 };
+                    assert_eq!(word(&machine, 0x100), expected, "{:?}, quantum {}", model, quantum);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn store_conditional_fails_after_a_store_to_the_granule() {
+        // core 1 writes a byte in the upper half of the granule core 0 reserved
+        let stwcx = "
+            coreid r10
+            bne r10, r0, other
+            lwarx r1, [r0 + 0x100]
+            add r2, r1, 1
+            stwcx. r3, r2, [r0 + 0x100]
+            halt
+        other:
+            add r5, r0, 9
+            stb r5, [r0 + 0x104]
+            halt";
+        let high = 9 << 32;
+        for model in MODELS {
+            assert_eq!(outcomes(stwcx, 2, model, &["0:r3", "[0x100]"]),
+                BTreeSet::from([vec![0, high], vec![1, high | 1]]), "{:?}", model);
+        }
+
+        // strex reports success with 0, a byte store is enough to break the reservation
+        let strex = "
+            coreid r10
+            bne r10, r0, other
+            ldrex r1, [r0 + 0x100]
+            add r2, r1, 1
+            strex r3, r2, [r0 + 0x100]
+            halt
+        other:
+            add r5, r0, 9
+            stb r5, [r0 + 0x107]
+            halt";
+        let high = 9 << 56;
+        for model in MODELS {
+            assert_eq!(outcomes(strex, 2, model, &["0:r3", "[0x100]"]),
+                BTreeSet::from([vec![0, high | 1], vec![1, high]]), "{:?}", model);
+        }
+
+        // a store next to the granule leaves the reservation alone
+        let outside = stwcx.replace("[r0 + 0x104]", "[r0 + 0x108]");
+        for model in MODELS {
+            assert_eq!(outcomes(&outside, 2, model, &["0:r3"]), BTreeSet::from([vec![1]]), "{:?}", model);
+        }
+    }
+
+    const MESSAGE_PASSING: &str = "
+        coreid r10
+        bne r10, r0, reader
+        add r3, r0, 42
+        st r3, [r0 + 0x100]
+        FENCE
+        add r4, r0, 1
+        st r4, [r0 + 0x108]
+        halt
+    reader:
+        ld r1, [r0 + 0x108]
+        ld r2, [r0 + 0x100]
+        halt";
+
+    #[test]
+    fn message_passing() {
+        let observe = ["1:r1", "1:r2"];
+        let allowed = BTreeSet::from([vec![0, 0], vec![0, 42], vec![1, 42]]);
+        let unfenced = MESSAGE_PASSING.replace("FENCE", "nop");
+        let fenced = MESSAGE_PASSING.replace("FENCE", "fence");
+
+        // stores leave a TSO store buffer in order, the flag is never seen before the data
+        for model in [MemoryModel::Sequential, MemoryModel::Tso] {
+            assert_eq!(outcomes(&unfenced, 2, model, &observe), allowed, "{:?}", model);
+        }
+
+        // relaxed stores to different addresses may become visible out of order
+        let mut relaxed = allowed.clone();
+        relaxed.insert(vec![1, 0]);
+        assert_eq!(outcomes(&unfenced, 2, MemoryModel::Relaxed, &observe), relaxed);
+
+        // unless a fence keeps them apart
+        for model in MODELS {
+            assert_eq!(outcomes(&fenced, 2, model, &observe), allowed, "{:?}", model);
+        }
+    }
+}
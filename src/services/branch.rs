@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+
+use crate::models::branch::{PredictorConfig, BranchStats, BranchSiteStats, BranchReport};
+
+const MAX_TABLE_BITS: u32 = 20;
+
+pub trait BranchPredictor: Send {
+    fn predict(&self, pc: usize) -> bool;
+    fn update(&mut self, pc: usize, taken: bool);
+}
+
+pub struct StaticPredictor {
+    taken: bool,
+}
+
+impl BranchPredictor for StaticPredictor {
+    fn predict(&self, _pc: usize) -> bool {
+        self.taken
+    }
+
+    fn update(&mut self, _pc: usize, _taken: bool) {}
+}
+
+// remembers the last outcome of each branch
+pub struct OneBitPredictor {
+    table: Vec<bool>,
+}
+
+impl BranchPredictor for OneBitPredictor {
+    fn predict(&self, pc: usize) -> bool {
+        self.table[pc % self.table.len()]
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        let slot = pc % self.table.len();
+        self.table[slot] = taken;
+    }
+}
+
+// 0, 1 => predict not taken, 2, 3 => predict taken
+fn saturate(counter: u8, taken: bool) -> u8 {
+    if taken { (counter + 1).min(3) } else { counter.saturating_sub(1) }
+}
+
+pub struct TwoBitPredictor {
+    table: Vec<u8>,
+}
+
+impl BranchPredictor for TwoBitPredictor {
+    fn predict(&self, pc: usize) -> bool {
+        self.table[pc % self.table.len()] >= 2
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        let slot = pc % self.table.len();
+        self.table[slot] = saturate(self.table[slot], taken);
+    }
+}
+
+// two bit counters indexed by 'pc xor global history'
+pub struct GsharePredictor {
+    table: Vec<u8>,
+    history: usize,
+    history_mask: usize,
+}
+
+impl GsharePredictor {
+    fn slot(&self, pc: usize) -> usize {
+        (pc ^ self.history) % self.table.len()
+    }
+}
+
+impl BranchPredictor for GsharePredictor {
+    fn predict(&self, pc: usize) -> bool {
+        self.table[self.slot(pc)] >= 2
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        let slot = self.slot(pc);
+        self.table[slot] = saturate(self.table[slot], taken);
+        self.history = ((self.history << 1) | taken as usize) & self.history_mask;
+    }
+}
+
+pub fn build_predictor(config: &PredictorConfig) -> Result<Box<dyn BranchPredictor>, String> {
+
+    let table_size = |table_bits: u32| -> Result<usize, String> {
+        if table_bits > MAX_TABLE_BITS {
+            return Err(format!("table_bits must not exceed {}", MAX_TABLE_BITS));
+        }
+        Ok(1 << table_bits)
+    };
+
+    match config {
+        PredictorConfig::StaticTaken => Ok(Box::new(StaticPredictor { taken: true })),
+        PredictorConfig::StaticNotTaken => Ok(Box::new(StaticPredictor { taken: false })),
+        PredictorConfig::OneBit { table_bits } => Ok(Box::new(OneBitPredictor {
+            table: vec![false; table_size(*table_bits)?],
+        })),
+        PredictorConfig::TwoBit { table_bits } => Ok(Box::new(TwoBitPredictor {
+            // start weakly not taken
+            table: vec![1; table_size(*table_bits)?],
+        })),
+        PredictorConfig::Gshare { history_bits, table_bits } => {
+            if *history_bits > MAX_TABLE_BITS {
+                return Err(format!("history_bits must not exceed {}", MAX_TABLE_BITS));
+            }
+            Ok(Box::new(GsharePredictor {
+                table: vec![1; table_size(*table_bits)?],
+                history: 0,
+                history_mask: (1 << history_bits) - 1,
+            }))
+        }
+    }
+}
+
+// a predictor plus the accuracy statistics gathered while the program runs
+pub struct BranchObserver {
+    config: PredictorConfig,
+    predictor: Box<dyn BranchPredictor>,
+    total: BranchStats,
+    per_site: BTreeMap<usize, BranchStats>,
+}
+
+impl BranchObserver {
+
+    pub fn new(config: &PredictorConfig) -> Result<BranchObserver, String> {
+        Ok(BranchObserver {
+            config: config.clone(),
+            predictor: build_predictor(config)?,
+            total: BranchStats::default(),
+            per_site: BTreeMap::new(),
+        })
+    }
+
+    // called for every executed conditional branch, with its actual outcome
+    pub fn observe(&mut self, pc: usize, taken: bool) {
+
+        let correct = self.predictor.predict(pc) == taken;
+        self.predictor.update(pc, taken);
+
+        for stats in [&mut self.total, self.per_site.entry(pc).or_default()] {
+            stats.executed += 1;
+            stats.taken += taken as u64;
+            stats.correct += correct as u64;
+            stats.accuracy = stats.correct as f64 / stats.executed as f64;
+        }
+    }
+
+    pub fn report(&self) -> BranchReport {
+        BranchReport {
+            predictor: self.config.clone(),
+            total: self.total,
+            per_site: self.per_site.iter()
+                .map(|(index, stats)| BranchSiteStats { index: *index, stats: *stats })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // correct predictions of 'config' on a loop branch taken three times, then not taken
+    fn correct(config: PredictorConfig) -> u64 {
+        let mut observer = BranchObserver::new(&config).unwrap();
+        for iteration in 0..100 {
+            observer.observe(5, iteration % 4 != 3);
+        }
+        let report = observer.report();
+        assert_eq!((report.total.executed, report.total.taken), (100, 75));
+        assert_eq!(report.per_site.len(), 1);
+        report.total.correct
+    }
+
+    #[test]
+    fn accuracy_on_a_loop_pattern() {
+        assert_eq!(correct(PredictorConfig::StaticTaken), 75);
+        assert_eq!(correct(PredictorConfig::StaticNotTaken), 25);
+        // misses the first and the last iteration of every round
+        assert_eq!(correct(PredictorConfig::OneBit { table_bits: 4 }), 50);
+        // only the exit once warmed up
+        assert_eq!(correct(PredictorConfig::TwoBit { table_bits: 4 }), 74);
+        // the history tells the exit apart once every counter is trained
+        assert!(correct(PredictorConfig::Gshare { history_bits: 4, table_bits: 6 }) >= 90);
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+
+use crate::models::cache::{CacheConfig, CacheSetup, CacheStats, CacheReport, CacheLevelReport,
+    AddressRange, InstructionCacheStats, RangeCacheStats, ReplacementPolicy, WritePolicy};
+
+// code_segment[pc] is fetched from 'CODE_BASE + pc * INSTRUCTION_SIZE', above the data memory,
+// so instructions and data do not alias in a unified L2
+pub const CODE_BASE: u64 = 0x1_0000_0000;
+pub const INSTRUCTION_SIZE: u64 = 4;
+
+// bounds of a cache config, the sets are allocated up front
+const MAX_CACHE_SIZE: usize = 16 * 1024 * 1024;
+const MAX_ASSOCIATIVITY: usize = 64;
+const MAX_CACHE_LINES: usize = 1 << 16;
+
+#[derive(Clone)]
+struct Line {
+    tag: u64,
+    dirty: bool,
+    inserted: u64,
+    used: u64,
+}
+
+// result of a single access, reported back so the next level can be fed
+struct Access {
+    hit: bool,
+    // the line is now held by this level
+    allocated: bool,
+    // address of a dirty line that was evicted and must be written to the next level
+    writeback: Option<u64>,
+}
+
+pub struct Cache {
+    config: CacheConfig,
+    sets: Vec<Vec<Line>>,
+    clock: u64,
+    rng: u64,
+    total: CacheStats,
+    per_instruction: BTreeMap<usize, CacheStats>,
+    per_range: Vec<(AddressRange, CacheStats)>,
+}
+
+impl Cache {
+
+    pub fn new(config: &CacheConfig, ranges: &[AddressRange]) -> Result<Cache, String> {
+
+        if config.line_size == 0 || !config.line_size.is_power_of_two() {
+            return Err("cache line_size must be a power of two".to_string());
+        }
+        if config.associativity == 0 || config.associativity > MAX_ASSOCIATIVITY {
+            return Err(format!("cache associativity must be 1 to {}", MAX_ASSOCIATIVITY));
+        }
+        if config.size > MAX_CACHE_SIZE {
+            return Err(format!("cache size must not exceed {}", MAX_CACHE_SIZE));
+        }
+        if config.size / config.line_size > MAX_CACHE_LINES {
+            return Err(format!("a cache holds at most {} lines", MAX_CACHE_LINES));
+        }
+        let set_size = match config.line_size.checked_mul(config.associativity) {
+            Some(set_size) if config.size != 0 && config.size.is_multiple_of(set_size) => set_size,
+            _ => return Err("cache size must be a multiple of line_size * associativity".to_string()),
+        };
+
+        let set_count = config.size / set_size;
+
+        Ok(Cache {
+            config: config.clone(),
+            sets: vec![Vec::with_capacity(config.associativity); set_count],
+            clock: 0,
+            // xorshift must not start from zero
+            rng: if config.seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { config.seed },
+            total: CacheStats::default(),
+            per_instruction: BTreeMap::new(),
+            per_range: ranges.iter().map(|range| (*range, CacheStats::default())).collect(),
+        })
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn record(&mut self, pc: usize, address: u64, update: impl Fn(&mut CacheStats)) {
+        update(&mut self.total);
+        update(self.per_instruction.entry(pc).or_default());
+        for (range, stats) in self.per_range.iter_mut() {
+            if address >= range.start && address < range.end {
+                update(stats);
+            }
+        }
+    }
+
+    fn access(&mut self, pc: usize, address: u64, write: bool) -> Access {
+
+        self.clock += 1;
+
+        let line_size = self.config.line_size as u64;
+        let line_number = address / line_size;
+        let set_index = (line_number % self.sets.len() as u64) as usize;
+        let tag = line_number / self.sets.len() as u64;
+        let clock = self.clock;
+        let write_back = self.config.write_policy == WritePolicy::WriteBack;
+
+        if let Some(line) = self.sets[set_index].iter_mut().find(|line| line.tag == tag) {
+            line.used = clock;
+            if write && write_back {
+                line.dirty = true;
+            }
+            self.record(pc, address, |stats| stats.hits += 1);
+            return Access { hit: true, allocated: true, writeback: None };
+        }
+
+        self.record(pc, address, |stats| stats.misses += 1);
+
+        if write && !self.config.write_allocate {
+            return Access { hit: false, allocated: false, writeback: None };
+        }
+
+        let mut writeback = None;
+
+        if self.sets[set_index].len() == self.config.associativity {
+            let victim = match self.config.replacement {
+                ReplacementPolicy::Lru => self.oldest(set_index, |line| line.used),
+                ReplacementPolicy::Fifo => self.oldest(set_index, |line| line.inserted),
+                ReplacementPolicy::Random => (self.next_random() % self.config.associativity as u64) as usize,
+            };
+            let evicted = self.sets[set_index].swap_remove(victim);
+            let sets = self.sets.len() as u64;
+            self.record(pc, address, |stats| stats.evictions += 1);
+            if evicted.dirty {
+                self.record(pc, address, |stats| stats.writebacks += 1);
+                writeback = Some((evicted.tag * sets + set_index as u64) * line_size);
+            }
+        }
+
+        self.sets[set_index].push(Line {
+            tag,
+            dirty: write && write_back,
+            inserted: clock,
+            used: clock,
+        });
+
+        Access { hit: false, allocated: true, writeback }
+    }
+
+    fn oldest(&self, set_index: usize, age: impl Fn(&Line) -> u64) -> usize {
+        self.sets[set_index].iter()
+            .enumerate()
+            .min_by_key(|(_, line)| age(line))
+            .map(|(way, _)| way)
+            .unwrap_or_default()
+    }
+
+    fn report(&self) -> CacheLevelReport {
+        CacheLevelReport {
+            config: self.config.clone(),
+            total: self.total,
+            per_instruction: self.per_instruction.iter()
+                .map(|(index, stats)| InstructionCacheStats { index: *index, stats: *stats })
+                .collect(),
+            per_range: self.per_range.iter()
+                .map(|(range, stats)| RangeCacheStats { range: *range, stats: *stats })
+                .collect(),
+        }
+    }
+}
+
+// split L1 caches in front of an optional unified L2.
+// a level that is not configured simply passes accesses through to the next one.
+pub struct CacheHierarchy {
+    icache: Option<Cache>,
+    dcache: Option<Cache>,
+    l2: Option<Cache>,
+}
+
+impl CacheHierarchy {
+
+    pub fn new(setup: &CacheSetup) -> Result<CacheHierarchy, String> {
+
+        let build = |config: &Option<CacheConfig>| -> Result<Option<Cache>, String> {
+            match config {
+                Some(config) => Ok(Some(Cache::new(config, &setup.ranges)?)),
+                None => Ok(None),
+            }
+        };
+
+        Ok(CacheHierarchy {
+            icache: build(&setup.icache)?,
+            dcache: build(&setup.dcache)?,
+            l2: build(&setup.l2)?,
+        })
+    }
+
+    fn next_level(&mut self, pc: usize, access: Option<Access>, address: u64, write: bool) {
+        let Some(l2) = self.l2.as_mut() else { return; };
+
+        match access {
+            Some(access) => {
+                if let Some(dirty) = access.writeback {
+                    l2.access(pc, dirty, true);
+                }
+                if !access.hit {
+                    // a line fill is a read, unless L1 did not allocate and passes the write on
+                    l2.access(pc, address, write && !access.allocated);
+                }
+            }
+            // no L1 at this point
+            None => {
+                l2.access(pc, address, write);
+            }
+        }
+    }
+
+    // instruction fetch of code_segment[pc]
+    pub fn fetch(&mut self, pc: usize) {
+        let address = CODE_BASE + pc as u64 * INSTRUCTION_SIZE;
+        let access = self.icache.as_mut().map(|cache| cache.access(pc, address, false));
+        self.next_level(pc, access, address, false);
+    }
+
+    pub fn read(&mut self, pc: usize, address: u64) {
+        let access = self.dcache.as_mut().map(|cache| cache.access(pc, address, false));
+        self.next_level(pc, access, address, false);
+    }
+
+    pub fn write(&mut self, pc: usize, address: u64) {
+        let write_through = self.dcache.as_ref()
+            .map(|cache| cache.config.write_policy == WritePolicy::WriteThrough)
+            .unwrap_or(false);
+        let access = self.dcache.as_mut().map(|cache| cache.access(pc, address, true));
+
+        if write_through {
+            // every store goes down to the next level, hit or miss
+            if let Some(Access { writeback: Some(dirty), .. }) = access {
+                self.next_level(pc, None, dirty, true);
+            }
+            self.next_level(pc, None, address, true);
+        } else {
+            self.next_level(pc, access, address, true);
+        }
+    }
+
+    pub fn report(&self) -> CacheReport {
+        CacheReport {
+            icache: self.icache.as_ref().map(|cache| cache.report()),
+            dcache: self.dcache.as_ref().map(|cache| cache.report()),
+            l2: self.l2.as_ref().map(|cache| cache.report()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one set of two 32 byte lines, so every line competes for the same ways
+    fn cache(replacement: ReplacementPolicy, write_policy: WritePolicy) -> CacheConfig {
+        CacheConfig {
+            size: 64,
+            associativity: 2,
+            line_size: 32,
+            replacement,
+            write_policy,
+            write_allocate: true,
+            seed: 0,
+        }
+    }
+
+    fn counts(stats: CacheStats) -> (u64, u64, u64, u64) {
+        (stats.hits, stats.misses, stats.evictions, stats.writebacks)
+    }
+
+    const A: u64 = 0x000;
+    const B: u64 = 0x100;
+    const C: u64 = 0x200;
+
+    fn run(replacement: ReplacementPolicy, accesses: &[(u64, bool)]) -> CacheReport {
+        let setup = CacheSetup {
+            dcache: Some(cache(replacement, WritePolicy::WriteBack)),
+            l2: Some(CacheConfig { size: 1024, associativity: 4, ..cache(ReplacementPolicy::Lru, WritePolicy::WriteBack) }),
+            ..Default::default()
+        };
+        let mut hierarchy = CacheHierarchy::new(&setup).unwrap();
+        for (pc, (address, write)) in accesses.iter().enumerate() {
+            if *write {
+                hierarchy.write(pc, *address);
+            } else {
+                hierarchy.read(pc, *address);
+            }
+        }
+        hierarchy.report()
+    }
+
+    #[test]
+    fn lru_evicts_the_least_recently_used_line() {
+        // A is used again before C comes in, so B goes
+        let report = run(ReplacementPolicy::Lru, &[(A, false), (B, false), (A, false), (C, false), (A, false)]);
+        assert_eq!(counts(report.dcache.unwrap().total), (2, 3, 1, 0));
+    }
+
+    #[test]
+    fn fifo_evicts_the_first_line_in() {
+        // A came in first and goes despite its hit, then B makes room for A again
+        let report = run(ReplacementPolicy::Fifo, &[(A, false), (B, false), (A, false), (C, false), (A, false)]);
+        assert_eq!(counts(report.dcache.unwrap().total), (1, 4, 2, 0));
+    }
+
+    #[test]
+    fn same_line_hits() {
+        let report = run(ReplacementPolicy::Lru, &[(A, false), (A + 8, false), (A + 31, true), (A + 32, false)]);
+        // A + 32 is the next line, in the same (only) set
+        assert_eq!(counts(report.dcache.unwrap().total), (2, 2, 0, 0));
+    }
+
+    #[test]
+    fn dirty_eviction_is_written_back_to_l2() {
+        let report = run(ReplacementPolicy::Lru, &[(A, true), (B, false), (C, false)]);
+        let dcache = report.dcache.unwrap();
+        assert_eq!(counts(dcache.total), (0, 3, 1, 1));
+        // three line fills and the writeback of A, which L2 still holds
+        assert_eq!(counts(report.l2.unwrap().total), (1, 3, 0, 0));
+    }
+
+    #[test]
+    fn per_instruction_counts() {
+        let report = run(ReplacementPolicy::Fifo, &[(A, false), (B, false), (A, false)]);
+        let dcache = report.dcache.unwrap();
+        let hits: Vec<u64> = dcache.per_instruction.iter().map(|stats| stats.stats.hits).collect();
+        assert_eq!(hits, vec![0, 0, 1]);
+    }
+
+    #[test]
+    fn oversized_configs_are_rejected() {
+        let base = cache(ReplacementPolicy::Lru, WritePolicy::WriteBack);
+        for config in [
+            CacheConfig { size: 1 << 40, associativity: 1, line_size: 1, ..base.clone() },
+            CacheConfig { size: MAX_CACHE_SIZE, associativity: 1, line_size: 1, ..base.clone() },
+            CacheConfig { size: 1 << 20, associativity: usize::MAX, line_size: 1 << 20, ..base.clone() },
+            CacheConfig { size: 1 << 20, associativity: 2, line_size: 1 << 63, ..base.clone() },
+            CacheConfig { size: 0, ..base.clone() },
+        ] {
+            assert!(Cache::new(&config, &[]).is_err(), "{:?}", config);
+        }
+        let largest = CacheConfig { size: MAX_CACHE_SIZE, associativity: MAX_ASSOCIATIVITY, line_size: MAX_CACHE_SIZE / MAX_CACHE_LINES, ..base };
+        assert!(Cache::new(&largest, &[]).is_ok());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::models::consistency::{MemoryModel};
+use crate::services::executor::{Machine};
+
+// memory models. with Sequential every store goes straight to memory. with Tso and Relaxed
+// a store waits in the store buffer of its core first: the core reads its own buffered
+// stores back, the other cores see memory only. a buffered store becomes visible once it
+// has waited STORE_LATENCY instructions of its core (Relaxed adds up to 3 more, depending
+// on the address), when the buffer holds more than STORE_BUFFER_SIZE stores, and at
+//
+//  fence       waits until every store of the core is visible
+//
+// atomics, store conditionals and syscalls act as fences, and a core that stops (halt,
+// wfi, brk, a blocking read) drains its buffer. Tso keeps the stores of a core in program
+// order, Relaxed only keeps the order of stores to overlapping bytes. loads are never
+// reordered. the litmus runner (services/litmus.rs) tries every order the model allows
+// instead of the fixed latencies.
+
+pub const STORE_BUFFER_SIZE: usize = 8;
+pub const STORE_LATENCY: u64 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BufferedStore {
+    pub address: usize,
+    pub size: usize,
+    pub bytes: [u8; 16],
+    // instructions of its core since the store
+    pub age: u64,
+}
+
+impl BufferedStore {
+    fn overlaps(&self, other: &BufferedStore) -> bool {
+        self.address < other.address + other.size && other.address < self.address + self.size
+    }
+
+    fn latency(&self, model: MemoryModel) -> u64 {
+        match model {
+            MemoryModel::Relaxed => STORE_LATENCY + (self.address as u64 / 8) % 4,
+            _ => STORE_LATENCY,
+        }
+    }
+}
+
+// whether the model lets buffered store 'index' become visible now
+pub fn drainable(model: MemoryModel, buffer: &[BufferedStore], index: usize) -> bool {
+    match model {
+        MemoryModel::Sequential => false,
+        MemoryModel::Tso => index == 0,
+        MemoryModel::Relaxed => !buffer[..index].iter().any(|older| older.overlaps(&buffer[index])),
+    }
+}
+
+impl Machine {
+
+    // memory as the executing core sees it
+    #[inline(always)]
+    pub(crate) fn load_bytes(&self, address: usize, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self.memory[address..address + bytes.len()]);
+        for store in &self.store_buffer {
+            for (index, byte) in bytes.iter_mut().enumerate() {
+                let at = address + index;
+                if at >= store.address && at < store.address + store.size {
+                    *byte = store.bytes[at - store.address];
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn store_bytes(&mut self, address: usize, bytes: &[u8]) {
+        if self.memory_model == MemoryModel::Sequential {
+            self.commit(address, bytes);
+            return;
+        }
+        let mut store = BufferedStore { address, size: bytes.len(), bytes: [0; 16], age: 0 };
+        store.bytes[..bytes.len()].copy_from_slice(bytes);
+        self.store_buffer.push(store);
+        if self.store_buffer.len() > STORE_BUFFER_SIZE {
+            self.drain_store(0);
+        }
+    }
+
+    fn commit(&mut self, address: usize, bytes: &[u8]) {
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
+        self.invalidate_reservations(address, bytes.len());
+    }
+
+    pub fn drain_store(&mut self, index: usize) {
+        let store = self.store_buffer.remove(index);
+        self.commit(store.address, &store.bytes[..store.size]);
+    }
+
+    pub fn drain_stores(&mut self) {
+        while !self.store_buffer.is_empty() {
+            self.drain_store(0);
+        }
+    }
+
+    // one more instruction of the executing core has been executed
+    pub(crate) fn age_stores(&mut self) {
+        for store in self.store_buffer.iter_mut() {
+            store.age += 1;
+        }
+        let model = self.memory_model;
+        let mut index = 0;
+        while index < self.store_buffer.len() {
+            let store = &self.store_buffer[index];
+            if store.age >= store.latency(model) && drainable(model, &self.store_buffer, index) {
+                self.drain_store(index);
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
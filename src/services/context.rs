@@ -0,0 +1,267 @@
+use std::sync::{Arc, Mutex};
+
+use crate::models::run::{ContextRequest, RunResult, RunStatus};
+use crate::models::event::{EventKind};
+use crate::services::executor::{Machine, MachineState};
+use crate::services::interrupt::{timer_line};
+use crate::services::state::{InstreamState};
+use crate::services::events::{EventBus};
+
+pub const DEFAULT_CLOCK: u64 = 10;
+pub const DEFAULT_STEPS_PER_TICK: u64 = 1_000;
+// every tick of every run is executed by the worker thread, larger slices are cut down to this
+pub const MAX_STEPS_PER_TICK: u64 = 100_000;
+
+// every run has a lock of its own, the map of runs is only held to find one
+pub type SharedContext = Arc<Mutex<ExecutionContext>>;
+
+// a live run of the program: it keeps its machine between worker ticks, every tick raises
+// the timer interrupt of the ticking worker and, on ticks of its clock worker, executes
+// the next slice of instructions. the cores of a multi-core run share the slice, unless
+// they have clocks of their own.
+pub struct ExecutionContext {
+    pub id: String,
+    pub machine: Machine,
+    pub status: RunStatus,
+    pub clock: u64,
+    pub steps_per_tick: u64,
+    // the clock of every core, when they do not share 'clock'
+    pub core_clocks: Option<Vec<u64>>,
+    pub budget: Option<u64>,
+    // console bytes already published as events
+    pub console_sent: usize,
+}
+
+impl ExecutionContext {
+
+    pub fn new(id: String, mut machine: Machine, request: &ContextRequest) -> Result<ExecutionContext, String> {
+        let clock = request.clock.unwrap_or(DEFAULT_CLOCK);
+        if timer_line(clock).is_none() {
+            return Err(format!("no worker ticks every {}ms", clock));
+        }
+        machine.attach(&request.run)?;
+        if let Some(clocks) = &request.core_clocks {
+            if clocks.len() != machine.core_count() {
+                return Err(format!("{} core clocks for {} cores", clocks.len(), machine.core_count()));
+            }
+            if let Some(clock) = clocks.iter().find(|clock| timer_line(**clock).is_none()) {
+                return Err(format!("no worker ticks every {}ms", clock));
+            }
+        }
+        Ok(ExecutionContext {
+            id,
+            machine,
+            status: RunStatus::Running,
+            clock,
+            steps_per_tick: request.steps_per_tick.unwrap_or(DEFAULT_STEPS_PER_TICK).clamp(1, MAX_STEPS_PER_TICK),
+            core_clocks: request.core_clocks.clone(),
+            budget: request.run.budget,
+            console_sent: 0,
+        })
+    }
+
+    pub fn is_live(&self) -> bool {
+        matches!(self.status, RunStatus::Running | RunStatus::Waiting | RunStatus::WaitingForInput)
+    }
+
+    // instructions the next slice may execute
+    fn slice(&self) -> u64 {
+        match self.budget {
+            Some(budget) => self.steps_per_tick.min(budget.saturating_sub(self.machine.steps)),
+            None => self.steps_per_tick,
+        }
+    }
+
+    pub fn tick(&mut self, period: u64, events: &EventBus) {
+        if !self.is_live() {
+            return;
+        }
+        if let Some(line) = timer_line(period) {
+            self.machine.raise_all(line);
+        }
+
+        let advanced = match &self.core_clocks {
+            None if period != self.clock => return,
+            None => {
+                self.machine.ticks += 1;
+                self.machine.advance(self.slice())
+            },
+            Some(clocks) => {
+                let ticking: Vec<usize> = (0..clocks.len()).filter(|id| clocks[*id] == period).collect();
+                if ticking.is_empty() {
+                    return;
+                }
+                self.machine.ticks += 1;
+                self.advance_ticking(&ticking)
+            },
+        };
+        self.status = match advanced {
+            Some(status) => status,
+            None if self.budget.is_some_and(|budget| self.machine.steps >= budget) => {
+                self.machine.exhaust(self.machine.steps);
+                RunStatus::BudgetExhausted
+            },
+            None => RunStatus::Running,
+        };
+        self.console_sent = publish_run(events, &self.id, self.status, &self.machine, self.console_sent);
+    }
+
+    // a slice for every core in 'ticking'
+    fn advance_ticking(&mut self, ticking: &[usize]) -> Option<RunStatus> {
+        for id in ticking {
+            let status = self.machine.advance_on(*id, self.slice());
+            if matches!(status, Some(RunStatus::Faulted | RunStatus::Breakpoint)) {
+                return status;
+            }
+        }
+        let status = self.machine.cores_status();
+        self.machine.switch_to(0);
+        status
+    }
+
+    // continues a run stopped at a brk
+    pub fn resume(&mut self) -> Result<(), String> {
+        if self.status != RunStatus::Breakpoint {
+            return Err(format!("run is not at a breakpoint ({:?})", self.status));
+        }
+        self.machine.state = MachineState::Running;
+        self.status = RunStatus::Running;
+        Ok(())
+    }
+
+    pub fn result(&self) -> RunResult {
+        self.machine.result(&self.id, self.status)
+    }
+}
+
+// publishes the console output written since 'console_sent' and how the run stopped, if it
+// did. returns the new number of published console bytes.
+pub fn publish_run(events: &EventBus, id: &str, status: RunStatus, machine: &Machine, console_sent: usize) -> usize {
+    let console = &machine.devices.console;
+    if console.len() > console_sent {
+        events.run_event(EventKind::Console, id, String::from_utf8_lossy(&console[console_sent..]).into_owned());
+    }
+    match status {
+        RunStatus::Halted => events.run_event(EventKind::Halt, id, format!("halted at pc {}", machine.pc)),
+        RunStatus::Faulted | RunStatus::BudgetExhausted =>
+            events.run_event(EventKind::Fault, id, machine.fault.clone().unwrap_or_default()),
+        RunStatus::Breakpoint => events.run_event(EventKind::Breakpoint, id, format!("breakpoint at pc {}", machine.pc - 1)),
+        _ => {},
+    }
+    console.len()
+}
+
+pub fn find_run(state: &InstreamState, id: &str) -> Option<SharedContext> {
+    state.runs.lock().unwrap().get(id).cloned()
+}
+
+// called by the workers on every tick. the slices are executed with only their own run
+// locked, requests for the other runs (and new runs) do not wait for them.
+pub fn tick(state: &InstreamState, period: u64) {
+    let runs: Vec<SharedContext> = state.runs.lock().unwrap().values().cloned().collect();
+    for context in runs {
+        context.lock().unwrap().tick(period, &state.events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::assembler::{assemble};
+
+    fn context(request: ContextRequest) -> Result<ExecutionContext, String> {
+        ExecutionContext::new("run".to_string(), Machine::new(Vec::new()), &request)
+    }
+
+    fn live(source: &str, clock: u64) -> ExecutionContext {
+        let program = assemble(source, &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions;
+        let request = ContextRequest { clock: Some(clock), steps_per_tick: Some(100), ..Default::default() };
+        ExecutionContext::new("run".to_string(), Machine::new(program), &request).unwrap()
+    }
+
+    #[test]
+    fn slices_are_bounded() {
+        let huge = context(ContextRequest { steps_per_tick: Some(u64::MAX), ..Default::default() }).unwrap();
+        assert_eq!(huge.steps_per_tick, MAX_STEPS_PER_TICK);
+        let none = context(ContextRequest { steps_per_tick: Some(0), ..Default::default() }).unwrap();
+        assert_eq!(none.steps_per_tick, 1);
+    }
+
+    #[test]
+    fn clocks_are_worker_periods() {
+        assert!(context(ContextRequest { clock: Some(7), ..Default::default() }).is_err());
+        assert!(context(ContextRequest { clock: Some(250), ..Default::default() }).is_ok());
+        assert!(context(ContextRequest { core_clocks: Some(vec![10]), ..Default::default() }).is_ok());
+        assert!(context(ContextRequest { core_clocks: Some(vec![u64::MAX]), ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn a_tick_raises_the_timer_and_the_handler_returns() {
+        let events = EventBus::new();
+        let mut context = live("
+            ivec r0, timer
+            ien 1
+            ei
+        idle:
+            wfi
+            add r2, r2, 1
+            jmp idle
+        timer:
+            add r3, r3, 1
+            iack 0
+            iret", 10);
+
+        context.tick(10, &events);
+        assert_eq!(context.status, RunStatus::Waiting);
+        assert_eq!((context.machine.registers[2], context.machine.registers[3]), (0, 1));
+
+        // the 25ms worker raises its masked line but executes nothing on a 10ms clock
+        context.tick(25, &events);
+        assert_eq!(context.machine.registers[3], 1);
+
+        context.tick(10, &events);
+        assert_eq!(context.status, RunStatus::Waiting);
+        assert_eq!((context.machine.registers[2], context.machine.registers[3]), (1, 2));
+        let report = context.machine.interrupts.report();
+        assert_eq!(report.delivered[0], 2);
+        assert_eq!(report.pending, 1 << 1);
+        assert!(report.in_service.is_empty());
+    }
+
+    #[test]
+    fn a_higher_priority_line_preempts_a_handler() {
+        let events = EventBus::new();
+        // the line 3 handler waits for the line 0 handler to run inside it
+        let mut context = live("
+            add r1, r0, 3
+            ivec r1, slow
+            ivec r0, fast
+            ien 9
+            ei
+        idle:
+            wfi
+            jmp idle
+        slow:
+            iack 3
+        wait:
+            beq r6, r0, wait
+            add r8, r6, 0
+            iret
+        fast:
+            iack 0
+            add r6, r0, 1
+            iret", 100);
+
+        context.tick(100, &events);
+        assert_eq!(context.status, RunStatus::Running);
+        assert_eq!(context.machine.interrupts.report().in_service, vec![3]);
+
+        context.tick(10, &events);
+        context.tick(100, &events);
+        assert_eq!(context.status, RunStatus::Waiting);
+        assert_eq!(context.machine.registers[8], 1);
+        let report = context.machine.interrupts.report();
+        assert_eq!((report.delivered[0], report.delivered[3]), (1, 2));
+        assert!(report.in_service.is_empty());
+    }
+}
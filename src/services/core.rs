@@ -0,0 +1,321 @@
+use crate::models::float::{RoundingMode};
+use crate::models::run::{CoreReport, RunStatus};
+use crate::services::executor::{Machine, MachineState, REGISTER_COUNT, REG_SP};
+use crate::services::vector::{Vector};
+use crate::services::interrupt::{InterruptController};
+use crate::services::consistency::{BufferedStore};
+use crate::services::exception::{ExceptionUnit};
+use crate::services::protection::{Mpu};
+use crate::services::paging::{Mmu};
+use crate::models::protection::{Privilege};
+
+// multi-core runs. every core has its own registers (integer, FP and vector), pc, state,
+// interrupt controller, exception vectors, privilege level, mpu and mmu. everything else is
+// shared: the code_segment, the data memory, the devices and the cache and branch simulators.
+//
+//  coreid      rd = id of the executing core (0..)
+//  ncores      rd = number of cores
+//
+// the machine executes one core at a time in its own fields, the others are parked in
+// 'cores' until it is their turn. cores take turns of 'quantum' instructions (1 by default,
+// lockstep). a live run can also give every core a clock of its own (core_clocks), its
+// ticks then advance that core only.
+//
+// what a core sees of the stores of the others depends on the memory model of the run
+// (services/consistency.rs). with Sequential, the default, a store reaches memory as it
+// executes and memory is sequentially consistent. with Tso the stores of a core wait in its
+// store buffer and become visible later, in program order, so a core may read an old value
+// after its own store to another address. Relaxed also lets stores to different addresses
+// become visible out of order. fence (and the atomics) wait for the buffered stores.
+//
+// core n starts with its stack pointer CORE_STACK_SIZE * n bytes below the top of memory.
+// the exit syscall halts every core, halt only the core that executes it. a fault or brk
+// stops the whole run.
+
+pub const MAX_CORES: usize = 8;
+pub const CORE_STACK_SIZE: usize = 4 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct CoreState {
+    pub registers: [u64; REGISTER_COUNT],
+    pub fregisters: [f64; REGISTER_COUNT],
+    pub fflags: u64,
+    pub rounding: RoundingMode,
+    pub vregisters: [Vector; REGISTER_COUNT],
+    pub pc: usize,
+    pub state: MachineState,
+    pub interrupts: InterruptController,
+    pub exceptions: ExceptionUnit,
+    pub privilege: Privilege,
+    pub mpu: Mpu,
+    pub mmu: Mmu,
+    pub reservation: Option<usize>,
+    pub store_buffer: Vec<BufferedStore>,
+    // instructions executed by this core
+    pub steps: u64,
+    pub fault: Option<String>,
+}
+
+fn core_status(state: MachineState, fault: &Option<String>) -> RunStatus {
+    match state {
+        _ if fault.is_some() => RunStatus::Faulted,
+        MachineState::Running => RunStatus::Running,
+        MachineState::Halted => RunStatus::Halted,
+        MachineState::Waiting => RunStatus::Waiting,
+        MachineState::Blocked => RunStatus::WaitingForInput,
+        MachineState::Break => RunStatus::Breakpoint,
+    }
+}
+
+impl Machine {
+
+    pub fn core_count(&self) -> usize {
+        self.cores.len().max(1)
+    }
+
+    // turns a fresh machine into one with 'count' cores
+    pub fn set_cores(&mut self, count: usize, quantum: u64) -> Result<(), String> {
+        if count == 0 || count > MAX_CORES {
+            return Err(format!("a run has 1 to {} cores, not {}", MAX_CORES, count));
+        }
+        self.quantum = quantum.max(1);
+        if count == 1 {
+            return Ok(());
+        }
+        self.cores = (0..count).map(|id| {
+            let mut registers = self.registers;
+            registers[REG_SP as usize] = self.memory.len().saturating_sub(id * CORE_STACK_SIZE) as u64;
+            CoreState {
+                registers,
+                fregisters: self.fregisters,
+                fflags: self.fflags,
+                rounding: self.rounding,
+                vregisters: self.vregisters,
+                pc: self.pc,
+                state: self.state,
+                interrupts: self.interrupts.clone(),
+                exceptions: self.exceptions.clone(),
+                privilege: self.privilege,
+                mpu: self.mpu.clone(),
+                mmu: self.mmu.clone(),
+                reservation: None,
+                store_buffer: Vec::new(),
+                steps: 0,
+                fault: None,
+            }
+        }).collect();
+        self.core = 0;
+        Ok(())
+    }
+
+    // parks the executing core and brings in core 'id'
+    pub(crate) fn switch_to(&mut self, id: usize) {
+        if id == self.core || id >= self.cores.len() {
+            return;
+        }
+        let parked = &mut self.cores[self.core];
+        parked.registers = self.registers;
+        parked.fregisters = self.fregisters;
+        parked.fflags = self.fflags;
+        parked.rounding = self.rounding;
+        parked.vregisters = self.vregisters;
+        parked.pc = self.pc;
+        parked.state = self.state;
+        parked.interrupts = std::mem::take(&mut self.interrupts);
+        parked.exceptions = std::mem::take(&mut self.exceptions);
+        parked.privilege = self.privilege;
+        parked.mpu = std::mem::take(&mut self.mpu);
+        parked.mmu = std::mem::take(&mut self.mmu);
+        parked.reservation = self.reservation;
+        parked.store_buffer = std::mem::take(&mut self.store_buffer);
+
+        let core = &mut self.cores[id];
+        self.registers = core.registers;
+        self.fregisters = core.fregisters;
+        self.fflags = core.fflags;
+        self.rounding = core.rounding;
+        self.vregisters = core.vregisters;
+        self.pc = core.pc;
+        self.state = core.state;
+        self.interrupts = std::mem::take(&mut core.interrupts);
+        self.exceptions = std::mem::take(&mut core.exceptions);
+        self.privilege = core.privilege;
+        self.mpu = std::mem::take(&mut core.mpu);
+        self.mmu = std::mem::take(&mut core.mmu);
+        self.reservation = core.reservation;
+        self.store_buffer = std::mem::take(&mut core.store_buffer);
+        self.core = id;
+    }
+
+    // raises an interrupt line on every core, the timers tick for all of them
+    pub fn raise_all(&mut self, line: u8) {
+        self.interrupts.raise(line);
+        for core in self.cores.iter_mut() {
+            core.interrupts.raise(line);
+        }
+    }
+
+    // (state, interrupts) of core 'id', whether it is executing or parked
+    fn core_state(&self, id: usize) -> (MachineState, &InterruptController) {
+        if id == self.core {
+            (self.state, &self.interrupts)
+        } else {
+            (self.cores[id].state, &self.cores[id].interrupts)
+        }
+    }
+
+    // how the run stopped, None while at least one core can still execute
+    pub fn cores_status(&self) -> Option<RunStatus> {
+        let states: Vec<(MachineState, &InterruptController)> = (0..self.core_count()).map(|id| self.core_state(id)).collect();
+        if states.iter().any(|(state, _)| *state == MachineState::Break) {
+            return Some(RunStatus::Breakpoint);
+        }
+        let runnable = states.iter().any(|(state, interrupts)| match state {
+            MachineState::Running => true,
+            MachineState::Waiting => interrupts.deliverable().is_some(),
+            MachineState::Blocked => self.devices.input_ready() || interrupts.deliverable().is_some(),
+            _ => false,
+        });
+        if runnable {
+            return None;
+        }
+        if states.iter().all(|(state, _)| *state == MachineState::Halted) {
+            return Some(RunStatus::Halted);
+        }
+        if states.iter().any(|(state, _)| *state == MachineState::Blocked) {
+            return Some(RunStatus::WaitingForInput);
+        }
+        Some(RunStatus::Waiting)
+    }
+
+    // executes up to 'steps' instructions on core 'id'. a fault or brk leaves the core
+    // executing, so the run result shows its registers.
+    pub fn advance_on(&mut self, id: usize, steps: u64) -> Option<RunStatus> {
+        self.switch_to(id);
+        let before = self.steps;
+        let status = self.advance_core(steps);
+        if let Some(core) = self.cores.get_mut(id) {
+            core.steps += self.steps - before;
+            if status == Some(RunStatus::Faulted) {
+                let fault = format!("core {}: {}", id, self.fault.clone().unwrap_or_default());
+                core.fault = Some(fault.clone());
+                self.fault = Some(fault);
+            }
+        }
+        if self.exit_code.is_some() {
+            for core in self.cores.iter_mut() {
+                core.state = MachineState::Halted;
+            }
+        }
+        status
+    }
+
+    // round-robin over the cores, 'quantum' instructions at a time
+    pub(crate) fn advance_cores(&mut self, steps: u64) -> Option<RunStatus> {
+        let limit = self.steps.saturating_add(steps);
+        loop {
+            if let Some(status) = self.cores_status() {
+                if status != RunStatus::Breakpoint {
+                    self.switch_to(0);
+                }
+                return Some(status);
+            }
+            for id in 0..self.cores.len() {
+                if self.steps >= limit {
+                    self.switch_to(0);
+                    return None;
+                }
+                let quantum = self.quantum.min(limit - self.steps);
+                if let Some(status @ (RunStatus::Faulted | RunStatus::Breakpoint)) = self.advance_on(id, quantum) {
+                    return Some(status);
+                }
+            }
+        }
+    }
+
+    pub fn core_reports(&self) -> Vec<CoreReport> {
+        if self.cores.is_empty() {
+            return vec![CoreReport {
+                id: 0,
+                status: core_status(self.state, &self.fault),
+                pc: self.pc,
+                steps: self.steps,
+                registers: self.registers.to_vec(),
+                fregisters: self.fregisters.to_vec(),
+                fault: self.fault.clone(),
+                privilege: self.privilege,
+                pending_stores: self.store_buffer.len(),
+            }];
+        }
+        self.cores.iter().enumerate().map(|(id, core)| {
+            let executing = id == self.core;
+            CoreReport {
+                id,
+                status: core_status(if executing { self.state } else { core.state }, &core.fault),
+                pc: if executing { self.pc } else { core.pc },
+                steps: core.steps,
+                registers: if executing { self.registers.to_vec() } else { core.registers.to_vec() },
+                fregisters: if executing { self.fregisters.to_vec() } else { core.fregisters.to_vec() },
+                fault: core.fault.clone(),
+                privilege: if executing { self.privilege } else { core.privilege },
+                pending_stores: if executing { self.store_buffer.len() } else { core.store_buffer.len() },
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::exception::{ExceptionKind};
+    use crate::models::run::{RunRequest};
+    use crate::services::assembler::{assemble};
+
+    fn run(source: &str, cores: usize) -> (Machine, RunStatus) {
+        let program = assemble(source, &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions;
+        let mut machine = Machine::new(program);
+        machine.attach(&RunRequest { cores: Some(cores), quantum: Some(2), ..Default::default() }).unwrap();
+        let status = machine.run(1_000);
+        (machine, status)
+    }
+
+    #[test]
+    fn cores_take_turns_on_their_own_stacks() {
+        let (machine, status) = run("
+            coreid r1
+            ncores r6
+            sub sp, sp, 8
+            st r1, [sp + 0]
+            ld r2, [sp + 0]
+            add r3, sp, 0
+            add r4, r1, 1
+            mul r5, r1, 8
+            st r4, [r5 + 0x100]
+            halt", 3);
+        assert_eq!(status, RunStatus::Halted);
+        for report in machine.core_reports() {
+            let id = report.id as u64;
+            assert_eq!(report.status, RunStatus::Halted);
+            assert_eq!(report.steps, 10);
+            assert_eq!((report.registers[1], report.registers[2], report.registers[6]), (id, id, 3));
+            // below the top of its own stack
+            assert_eq!(report.registers[3], (machine.memory.len() - report.id * CORE_STACK_SIZE - 8) as u64);
+            let at = 0x100 + report.id * 8;
+            assert_eq!(machine.memory[at], id as u8 + 1);
+        }
+
+        // core 1 runs off the bottom of its stack
+        let (machine, status) = run("
+            coreid r1
+            beq r1, r0, done
+            sub sp, sp, 0x1000
+            sub sp, sp, 8
+            st r1, [sp + 0]
+        done:
+            halt", 2);
+        assert_eq!(status, RunStatus::Faulted);
+        let exception = machine.exception.as_ref().unwrap();
+        assert_eq!((exception.kind, exception.core), (ExceptionKind::StackOverflow, 1));
+        assert!(machine.fault.as_ref().unwrap().starts_with("core 1: "));
+    }
+}
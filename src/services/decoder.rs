@@ -0,0 +1,585 @@
+use crate::models::instruction::{Instruction};
+use crate::services::executor::{parse_imdval, REGISTER_COUNT, REG_LINK};
+use crate::services::syscall::{syscall_arguments, SYSCALL_ARGUMENTS, SYSCALL_RESULT};
+use crate::models::float::{is_float, RoundingMode};
+use crate::models::vector::{is_vector, split_vector_opcode, Lanes};
+use crate::services::float::{parse_float_imdval, FloatOp};
+use crate::services::vector::{to_lane, Reduction, VecOp};
+use crate::services::interrupt::{check_line};
+use crate::services::atomic::{AtomicOp};
+use crate::services::protection::{check_region};
+
+// the code_segment is decoded once before a run, so the executor never looks at opcode
+// strings or parses immediates while the program is running
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Sar,
+    Slt,
+    Sltu,
+}
+
+impl AluOp {
+
+    pub fn from_opcode(opcode: &str) -> Option<AluOp> {
+        match opcode {
+            "add" => Some(AluOp::Add),
+            "sub" => Some(AluOp::Sub),
+            "mul" => Some(AluOp::Mul),
+            "div" => Some(AluOp::Div),
+            "rem" => Some(AluOp::Rem),
+            "and" => Some(AluOp::And),
+            "or" => Some(AluOp::Or),
+            "xor" => Some(AluOp::Xor),
+            "shl" => Some(AluOp::Shl),
+            "shr" => Some(AluOp::Shr),
+            "sar" => Some(AluOp::Sar),
+            "slt" => Some(AluOp::Slt),
+            "sltu" => Some(AluOp::Sltu),
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn apply(self, src: u64, operand: u64) -> Result<u64, String> {
+        match self {
+            AluOp::Add => Ok(src.wrapping_add(operand)),
+            AluOp::Sub => Ok(src.wrapping_sub(operand)),
+            AluOp::Mul => Ok(src.wrapping_mul(operand)),
+            AluOp::Div | AluOp::Rem => {
+                if operand == 0 {
+                    return Err("division by zero".to_string());
+                }
+                let (lhs, rhs) = (src as i64, operand as i64);
+                let value = if self == AluOp::Div { lhs.wrapping_div(rhs) } else { lhs.wrapping_rem(rhs) };
+                Ok(value as u64)
+            },
+            AluOp::And => Ok(src & operand),
+            AluOp::Or => Ok(src | operand),
+            AluOp::Xor => Ok(src ^ operand),
+            AluOp::Shl => Ok(src.wrapping_shl(operand as u32)),
+            AluOp::Shr => Ok(src.wrapping_shr(operand as u32)),
+            AluOp::Sar => Ok((src as i64).wrapping_shr(operand as u32) as u64),
+            AluOp::Slt => Ok(((src as i64) < (operand as i64)) as u64),
+            AluOp::Sltu => Ok((src < operand) as u64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+impl Condition {
+
+    pub fn from_opcode(opcode: &str) -> Option<Condition> {
+        match opcode {
+            "beq" => Some(Condition::Eq),
+            "bne" => Some(Condition::Ne),
+            "blt" => Some(Condition::Lt),
+            "bge" => Some(Condition::Ge),
+            "bltu" => Some(Condition::Ltu),
+            "bgeu" => Some(Condition::Geu),
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn holds(self, lhs: u64, rhs: u64) -> bool {
+        match self {
+            Condition::Eq => lhs == rhs,
+            Condition::Ne => lhs != rhs,
+            Condition::Lt => (lhs as i64) < (rhs as i64),
+            Condition::Ge => (lhs as i64) >= (rhs as i64),
+            Condition::Ltu => lhs < rhs,
+            Condition::Geu => lhs >= rhs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloatUnaryOp {
+    Sqrt,
+    Neg,
+    Abs,
+    // fcvt.s.d / fcvt.d.s
+    ToSingle,
+    ToDouble,
+}
+
+// register operands are validated while decoding and stored as usize indexes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Nop,
+    Halt,
+    AluReg { op: AluOp, dst: usize, src: usize, ext: usize },
+    AluImm { op: AluOp, dst: usize, src: usize, imm: u64 },
+    Load { size: usize, dst: usize, base: usize, offset: u64 },
+    Store { size: usize, value: usize, base: usize, offset: u64 },
+    Branch { condition: Condition, src: usize, ext: usize, target: usize },
+    Jump { target: usize },
+    Call { target: usize },
+    Ret,
+    // 'double' selects .d over .s, fp registers are indexes into the FP register file
+    FloatArith { op: FloatOp, double: bool, dst: usize, src: usize, ext: usize },
+    FloatArithImm { op: FloatOp, double: bool, dst: usize, src: usize, imm: f64 },
+    FloatUnary { op: FloatUnaryOp, double: bool, dst: usize, src: usize },
+    FloatMulAdd { double: bool, dst: usize, src: usize, ext: usize },
+    FloatLoadImm { double: bool, dst: usize, imm: f64 },
+    FloatFromInt { double: bool, dst: usize, src: usize },
+    FloatToInt { double: bool, dst: usize, src: usize },
+    FloatBitsToInt { double: bool, dst: usize, src: usize },
+    IntBitsToFloat { double: bool, dst: usize, src: usize },
+    FloatLoad { double: bool, dst: usize, base: usize, offset: u64 },
+    FloatStore { double: bool, value: usize, base: usize, offset: u64 },
+    FloatCompare { double: bool, src: usize, ext: usize },
+    ReadFlags { dst: usize },
+    SetFlags { src: usize },
+    SetFlagsImm { flags: u64 },
+    ReadRounding { dst: usize },
+    SetRounding { src: usize },
+    SetRoundingImm { mode: RoundingMode },
+    // vector registers are indexes into the vector register file, 'imm' holds the bits of one lane
+    VecArith { op: VecOp, lanes: Lanes, dst: usize, src: usize, ext: usize },
+    VecArithImm { op: VecOp, lanes: Lanes, dst: usize, src: usize, imm: u64 },
+    VecLoad { dst: usize, base: usize, offset: u64 },
+    VecStore { value: usize, base: usize, offset: u64 },
+    VecSplat { lanes: Lanes, dst: usize, src: usize },
+    VecInsert { lanes: Lanes, dst: usize, src: usize, lane: usize },
+    VecExtract { lanes: Lanes, dst: usize, src: usize, lane: usize },
+    VecShuffle { lanes: Lanes, dst: usize, src: usize, pattern: u64 },
+    VecSwizzle { lanes: Lanes, dst: usize, src: usize, ext: usize },
+    VecReduce { op: Reduction, lanes: Lanes, dst: usize, src: usize },
+    EnableInterrupts,
+    DisableInterrupts,
+    // 'set' is true for ien and false for idis
+    InterruptMask { set: bool, src: usize },
+    InterruptMaskImm { set: bool, bits: u8 },
+    AcknowledgeInterrupt { src: usize },
+    AcknowledgeInterruptImm { line: u8 },
+    RaiseInterrupt { src: usize },
+    RaiseInterruptImm { line: u8 },
+    PendingInterrupts { dst: usize },
+    InterruptVector { src: usize, target: usize },
+    InterruptReturn,
+    WaitForInterrupt,
+    ExceptionVector { src: usize, target: usize },
+    ExceptionCause { dst: usize },
+    ExceptionPc { dst: usize },
+    ExceptionAddress { dst: usize },
+    SetExceptionPc { src: usize },
+    ExceptionReturn,
+    RegionBounds { region: usize, base: usize, size: usize },
+    RegionPermissions { region: usize, src: usize },
+    UserJump { target: usize },
+    ReadPrivilege { dst: usize },
+    PageTableRoot { src: usize },
+    TlbFlush,
+    Breakpoint,
+    CoreId { dst: usize },
+    CoreCount { dst: usize },
+    Fence,
+    Atomic { op: AtomicOp, dst: usize, base: usize, value: usize, offset: u64 },
+    LoadReserved { size: usize, dst: usize, base: usize, offset: u64 },
+    // 'success' is written to dst when the store happens, 'success ^ 1' when it does not
+    StoreConditional { size: usize, success: u64, dst: usize, value: usize, base: usize, offset: u64 },
+    // the service number comes from r1
+    Syscall,
+    SyscallImm { number: u64 },
+    // decoding failed, the fault is raised only if the instruction is executed
+    Illegal,
+}
+
+impl Op {
+    pub fn is_interrupt(&self) -> bool {
+        matches!(self, Op::EnableInterrupts | Op::DisableInterrupts | Op::InterruptMask { .. }
+            | Op::InterruptMaskImm { .. } | Op::AcknowledgeInterrupt { .. } | Op::AcknowledgeInterruptImm { .. }
+            | Op::RaiseInterrupt { .. } | Op::RaiseInterruptImm { .. } | Op::PendingInterrupts { .. }
+            | Op::InterruptVector { .. } | Op::InterruptReturn | Op::WaitForInterrupt)
+    }
+
+    pub fn is_exception(&self) -> bool {
+        matches!(self, Op::ExceptionVector { .. } | Op::ExceptionCause { .. } | Op::ExceptionPc { .. }
+            | Op::ExceptionAddress { .. } | Op::SetExceptionPc { .. } | Op::ExceptionReturn)
+    }
+
+    pub fn is_protection(&self) -> bool {
+        matches!(self, Op::RegionBounds { .. } | Op::RegionPermissions { .. } | Op::UserJump { .. } | Op::ReadPrivilege { .. })
+    }
+
+    // the opcodes user mode may not execute
+    pub fn is_privileged(&self) -> bool {
+        matches!(self, Op::Halt | Op::WaitForInterrupt | Op::EnableInterrupts | Op::DisableInterrupts
+            | Op::InterruptMask { .. } | Op::InterruptMaskImm { .. } | Op::AcknowledgeInterrupt { .. }
+            | Op::AcknowledgeInterruptImm { .. } | Op::InterruptVector { .. } | Op::InterruptReturn
+            | Op::ExceptionVector { .. } | Op::SetExceptionPc { .. } | Op::ExceptionReturn
+            | Op::RegionBounds { .. } | Op::RegionPermissions { .. } | Op::UserJump { .. }
+            | Op::PageTableRoot { .. } | Op::TlbFlush)
+    }
+
+    pub fn is_paging(&self) -> bool {
+        matches!(self, Op::PageTableRoot { .. } | Op::TlbFlush)
+    }
+
+    pub fn is_atomic(&self) -> bool {
+        matches!(self, Op::Atomic { .. } | Op::LoadReserved { .. } | Op::StoreConditional { .. })
+    }
+
+    pub fn is_vector(&self) -> bool {
+        matches!(self, Op::VecArith { .. } | Op::VecArithImm { .. } | Op::VecLoad { .. } | Op::VecStore { .. }
+            | Op::VecSplat { .. } | Op::VecInsert { .. } | Op::VecExtract { .. } | Op::VecShuffle { .. }
+            | Op::VecSwizzle { .. } | Op::VecReduce { .. })
+    }
+
+    // integer registers the op reads, bit n <=> rn. the FP and vector register files are
+    // not included. every op is listed, a new one has to say what it reads.
+    pub fn reads(&self) -> u32 {
+        let bits = |registers: &[usize]| registers.iter().fold(0, |bits, register| bits | 1 << register);
+        match *self {
+            Op::AluReg { src, ext, .. } | Op::Branch { src, ext, .. } => bits(&[src, ext]),
+            Op::AluImm { src, .. } => bits(&[src]),
+            Op::Load { base, .. } | Op::LoadReserved { base, .. } => bits(&[base]),
+            Op::Store { value, base, .. } => bits(&[value, base]),
+            // the expected value comes in dst
+            Op::Atomic { op: AtomicOp::CompareSwap, dst, base, value, .. } => bits(&[dst, base, value]),
+            Op::Atomic { base, value, .. } | Op::StoreConditional { base, value, .. } => bits(&[base, value]),
+            Op::Ret => bits(&[REG_LINK as usize]),
+            Op::FloatFromInt { src, .. } | Op::IntBitsToFloat { src, .. } | Op::SetFlags { src }
+                | Op::SetRounding { src } => bits(&[src]),
+            Op::FloatLoad { base, .. } | Op::FloatStore { base, .. } | Op::VecLoad { base, .. }
+                | Op::VecStore { base, .. } => bits(&[base]),
+            // float lanes take their scalar from an FP register
+            Op::VecSplat { lanes, src, .. } | Op::VecInsert { lanes, src, .. } => {
+                if lanes.is_float() { 0 } else { bits(&[src]) }
+            },
+            Op::InterruptMask { src, .. } | Op::AcknowledgeInterrupt { src } | Op::RaiseInterrupt { src }
+                | Op::InterruptVector { src, .. } | Op::ExceptionVector { src, .. } | Op::SetExceptionPc { src }
+                | Op::RegionPermissions { src, .. } | Op::PageTableRoot { src } => bits(&[src]),
+            Op::RegionBounds { base, size, .. } => bits(&[base, size]),
+            // the service number and every argument a service may take
+            Op::Syscall => bits(&[SYSCALL_RESULT as usize]) | SYSCALL_ARGUMENTS.iter().fold(0, |reads, register| reads | 1 << register),
+            Op::SyscallImm { number } => syscall_arguments(number).unwrap_or(&[]).iter().fold(0, |reads, register| reads | 1 << register),
+            Op::Nop | Op::Halt | Op::Jump { .. } | Op::Call { .. } | Op::FloatArith { .. } | Op::FloatArithImm { .. }
+                | Op::FloatUnary { .. } | Op::FloatMulAdd { .. } | Op::FloatLoadImm { .. } | Op::FloatToInt { .. }
+                | Op::FloatBitsToInt { .. } | Op::FloatCompare { .. } | Op::ReadFlags { .. } | Op::SetFlagsImm { .. }
+                | Op::ReadRounding { .. } | Op::SetRoundingImm { .. } | Op::VecArith { .. } | Op::VecArithImm { .. }
+                | Op::VecExtract { .. } | Op::VecShuffle { .. } | Op::VecSwizzle { .. } | Op::VecReduce { .. }
+                | Op::EnableInterrupts | Op::DisableInterrupts | Op::InterruptMaskImm { .. }
+                | Op::AcknowledgeInterruptImm { .. } | Op::RaiseInterruptImm { .. } | Op::PendingInterrupts { .. }
+                | Op::InterruptReturn | Op::WaitForInterrupt | Op::ExceptionCause { .. } | Op::ExceptionPc { .. }
+                | Op::ExceptionAddress { .. } | Op::ExceptionReturn | Op::UserJump { .. } | Op::ReadPrivilege { .. }
+                | Op::TlbFlush | Op::Breakpoint | Op::CoreId { .. } | Op::CoreCount { .. } | Op::Fence | Op::Illegal => 0,
+        }
+    }
+
+    // the integer register the op writes, if any
+    pub fn writes(&self) -> Option<usize> {
+        match *self {
+            Op::AluReg { dst, .. } | Op::AluImm { dst, .. } | Op::Load { dst, .. } | Op::Atomic { dst, .. }
+                | Op::LoadReserved { dst, .. } | Op::StoreConditional { dst, .. } => Some(dst),
+            Op::Call { .. } => Some(REG_LINK as usize),
+            Op::FloatToInt { dst, .. } | Op::FloatBitsToInt { dst, .. } | Op::ReadFlags { dst }
+                | Op::ReadRounding { dst } => Some(dst),
+            Op::VecExtract { lanes, dst, .. } | Op::VecReduce { lanes, dst, .. } => (!lanes.is_float()).then_some(dst),
+            Op::PendingInterrupts { dst } | Op::ExceptionCause { dst } | Op::ExceptionPc { dst }
+                | Op::ExceptionAddress { dst } | Op::ReadPrivilege { dst } | Op::CoreId { dst }
+                | Op::CoreCount { dst } => Some(dst),
+            Op::Syscall | Op::SyscallImm { .. } => Some(SYSCALL_RESULT as usize),
+            Op::Nop | Op::Halt | Op::Store { .. } | Op::Branch { .. } | Op::Jump { .. } | Op::Ret
+                | Op::FloatArith { .. } | Op::FloatArithImm { .. } | Op::FloatUnary { .. } | Op::FloatMulAdd { .. }
+                | Op::FloatLoadImm { .. } | Op::FloatFromInt { .. } | Op::IntBitsToFloat { .. } | Op::FloatLoad { .. }
+                | Op::FloatStore { .. } | Op::FloatCompare { .. } | Op::SetFlags { .. } | Op::SetFlagsImm { .. }
+                | Op::SetRounding { .. } | Op::SetRoundingImm { .. } | Op::VecArith { .. } | Op::VecArithImm { .. }
+                | Op::VecLoad { .. } | Op::VecStore { .. } | Op::VecSplat { .. } | Op::VecInsert { .. }
+                | Op::VecShuffle { .. } | Op::VecSwizzle { .. } | Op::EnableInterrupts | Op::DisableInterrupts
+                | Op::InterruptMask { .. } | Op::InterruptMaskImm { .. } | Op::AcknowledgeInterrupt { .. }
+                | Op::AcknowledgeInterruptImm { .. } | Op::RaiseInterrupt { .. } | Op::RaiseInterruptImm { .. }
+                | Op::InterruptVector { .. } | Op::InterruptReturn | Op::WaitForInterrupt | Op::ExceptionVector { .. }
+                | Op::SetExceptionPc { .. } | Op::ExceptionReturn | Op::RegionBounds { .. }
+                | Op::RegionPermissions { .. } | Op::UserJump { .. } | Op::PageTableRoot { .. } | Op::TlbFlush
+                | Op::Breakpoint | Op::Fence | Op::Illegal => None,
+        }
+    }
+}
+
+fn register(index: u8) -> Result<usize, String> {
+    if index as usize >= REGISTER_COUNT {
+        return Err(format!("invalid register r{}", index));
+    }
+    Ok(index as usize)
+}
+
+// decode errors of a branch, jump or vector whose target is missing or outside of the program
+pub fn is_target_error(message: &str) -> bool {
+    message.starts_with("branch ")
+}
+
+// 'length' is the size of the program, a target equal to it ends the program
+fn target(imdval: Option<i64>, length: usize) -> Result<usize, String> {
+    match imdval {
+        Some(target) if target >= 0 && (target as usize) <= length => Ok(target as usize),
+        Some(target) => Err(format!("branch target {} out of range", target)),
+        None => Err("branch without target".to_string()),
+    }
+}
+
+fn decode_float(instruction: &Instruction) -> Result<Op, String> {
+
+    let opcode = instruction.opcode.as_str();
+    let double = opcode.split('.').any(|part| part == "d") || matches!(opcode, "fld" | "fsd");
+    let dst = register(instruction.regdst);
+    let src = register(instruction.regsrc);
+    let ext = register(instruction.regext);
+
+    // the remaining immediates are integers
+    let integer_imdval = || -> Result<Option<i64>, String> { parse_imdval(&instruction.imdval) };
+
+    if let Some(op) = FloatOp::from_opcode(opcode) {
+        return Ok(match parse_float_imdval(&instruction.imdval)? {
+            Some(imm) => Op::FloatArithImm { op, double, dst: dst?, src: src?, imm },
+            None => Op::FloatArith { op, double, dst: dst?, src: src?, ext: ext? },
+        });
+    }
+
+    let unary = match opcode {
+        "fsqrt.s" | "fsqrt.d" => Some(FloatUnaryOp::Sqrt),
+        "fneg.s" | "fneg.d" => Some(FloatUnaryOp::Neg),
+        "fabs.s" | "fabs.d" => Some(FloatUnaryOp::Abs),
+        "fcvt.s.d" => Some(FloatUnaryOp::ToSingle),
+        "fcvt.d.s" => Some(FloatUnaryOp::ToDouble),
+        _ => None,
+    };
+    if let Some(op) = unary {
+        return Ok(Op::FloatUnary { op, double, dst: dst?, src: src? });
+    }
+
+    match opcode {
+        "fmadd.s" | "fmadd.d" => Ok(Op::FloatMulAdd { double, dst: dst?, src: src?, ext: ext? }),
+        "fli.s" | "fli.d" => match parse_float_imdval(&instruction.imdval)? {
+            Some(imm) => Ok(Op::FloatLoadImm { double, dst: dst?, imm }),
+            None => Err(format!("{} without a value", opcode)),
+        },
+        "fcvt.s.l" | "fcvt.d.l" => Ok(Op::FloatFromInt { double, dst: dst?, src: src? }),
+        "fcvt.l.s" | "fcvt.l.d" => Ok(Op::FloatToInt { double, dst: dst?, src: src? }),
+        "fmv.x.s" | "fmv.x.d" => Ok(Op::FloatBitsToInt { double, dst: dst?, src: src? }),
+        "fmv.s.x" | "fmv.d.x" => Ok(Op::IntBitsToFloat { double, dst: dst?, src: src? }),
+        "flw" | "fld" => Ok(Op::FloatLoad { double, dst: dst?, base: src?, offset: integer_imdval()?.unwrap_or(0) as u64 }),
+        "fsw" | "fsd" => Ok(Op::FloatStore { double, value: ext?, base: src?, offset: integer_imdval()?.unwrap_or(0) as u64 }),
+        "fcmp.s" | "fcmp.d" => Ok(Op::FloatCompare { double, src: src?, ext: ext? }),
+        "frflags" => Ok(Op::ReadFlags { dst: dst? }),
+        "fsflags" => match integer_imdval()? {
+            Some(flags) => Ok(Op::SetFlagsImm { flags: flags as u64 }),
+            None => Ok(Op::SetFlags { src: src? }),
+        },
+        "frrm" => Ok(Op::ReadRounding { dst: dst? }),
+        "fsrm" => match integer_imdval()? {
+            Some(bits) => match RoundingMode::from_bits(bits as u64) {
+                Some(mode) => Ok(Op::SetRoundingImm { mode }),
+                None => Err(format!("invalid rounding mode {}", bits)),
+            },
+            None => Ok(Op::SetRounding { src: src? }),
+        },
+        _ => Err(format!("illegal opcode '{}'", opcode)),
+    }
+}
+
+fn decode_vector(instruction: &Instruction) -> Result<Op, String> {
+
+    let opcode = instruction.opcode.as_str();
+    let Some((base, lanes)) = split_vector_opcode(opcode) else {
+        return Err(format!("illegal opcode '{}'", opcode));
+    };
+    let dst = register(instruction.regdst);
+    let src = register(instruction.regsrc);
+    let ext = register(instruction.regext);
+    let imdval = parse_imdval(&instruction.imdval);
+
+    let Some(lanes) = lanes else {
+        let offset = imdval?.unwrap_or(0) as u64;
+        return match base {
+            "vld" => Ok(Op::VecLoad { dst: dst?, base: src?, offset }),
+            _ => Ok(Op::VecStore { value: ext?, base: src?, offset }),
+        };
+    };
+
+    let lane = || -> Result<usize, String> {
+        match imdval.clone()? {
+            Some(lane) if lane >= 0 && (lane as usize) < lanes.count() => Ok(lane as usize),
+            Some(lane) => Err(format!("lane {} out of range for {}", lane, opcode)),
+            None => Err(format!("{} without a lane", opcode)),
+        }
+    };
+
+    if let Some(op) = VecOp::from_base(base) {
+        // float lanes take float literals
+        let imm = if lanes.is_float() {
+            parse_float_imdval(&instruction.imdval)?.map(|imm| imm.to_bits())
+        } else {
+            imdval?.map(|imm| imm as u64)
+        };
+        return Ok(match imm {
+            Some(imm) => Op::VecArithImm { op, lanes, dst: dst?, src: src?, imm: to_lane(lanes, imm) },
+            None => Op::VecArith { op, lanes, dst: dst?, src: src?, ext: ext? },
+        });
+    }
+    if let Some(op) = Reduction::from_base(base) {
+        return Ok(Op::VecReduce { op, lanes, dst: dst?, src: src? });
+    }
+
+    match base {
+        "vsplat" => Ok(Op::VecSplat { lanes, dst: dst?, src: src? }),
+        "vins" => Ok(Op::VecInsert { lanes, dst: dst?, src: src?, lane: lane()? }),
+        "vext" => Ok(Op::VecExtract { lanes, dst: dst?, src: src?, lane: lane()? }),
+        "vshuf" => match imdval? {
+            Some(pattern) => Ok(Op::VecShuffle { lanes, dst: dst?, src: src?, pattern: pattern as u64 }),
+            None => Ok(Op::VecSwizzle { lanes, dst: dst?, src: src?, ext: ext? }),
+        },
+        _ => Err(format!("illegal opcode '{}'", opcode)),
+    }
+}
+
+pub fn decode(instruction: &Instruction, length: usize) -> Result<Op, String> {
+
+    let opcode = instruction.opcode.as_str();
+    if is_float(opcode) {
+        return decode_float(instruction);
+    }
+    if is_vector(opcode) {
+        return decode_vector(instruction);
+    }
+
+    let imdval = parse_imdval(&instruction.imdval)?;
+
+    if let Some(op) = AluOp::from_opcode(opcode) {
+        let dst = register(instruction.regdst)?;
+        let src = register(instruction.regsrc)?;
+        return Ok(match imdval {
+            Some(imm) => Op::AluImm { op, dst, src, imm: imm as u64 },
+            None => Op::AluReg { op, dst, src, ext: register(instruction.regext)? },
+        });
+    }
+
+    if let Some(condition) = Condition::from_opcode(opcode) {
+        return Ok(Op::Branch {
+            condition,
+            src: register(instruction.regsrc)?,
+            ext: register(instruction.regext)?,
+            target: target(imdval, length)?,
+        });
+    }
+
+    let offset = imdval.unwrap_or(0) as u64;
+
+    if let Some(op) = AtomicOp::from_opcode(opcode) {
+        return Ok(Op::Atomic {
+            op,
+            dst: register(instruction.regdst)?,
+            base: register(instruction.regsrc)?,
+            value: register(instruction.regext)?,
+            offset,
+        });
+    }
+
+    match opcode {
+        "nop" => Ok(Op::Nop),
+        "halt" => Ok(Op::Halt),
+        "ld" | "ldb" => Ok(Op::Load {
+            size: if opcode == "ld" { 8 } else { 1 },
+            dst: register(instruction.regdst)?,
+            base: register(instruction.regsrc)?,
+            offset,
+        }),
+        "st" | "stb" => Ok(Op::Store {
+            size: if opcode == "st" { 8 } else { 1 },
+            value: register(instruction.regext)?,
+            base: register(instruction.regsrc)?,
+            offset,
+        }),
+        "jmp" => Ok(Op::Jump { target: target(imdval, length)? }),
+        "call" => Ok(Op::Call { target: target(imdval, length)? }),
+        "ret" => Ok(Op::Ret),
+        "ei" => Ok(Op::EnableInterrupts),
+        "di" => Ok(Op::DisableInterrupts),
+        "ien" | "idis" => match imdval {
+            Some(bits) if (0..=u8::MAX as i64).contains(&bits) => Ok(Op::InterruptMaskImm { set: opcode == "ien", bits: bits as u8 }),
+            Some(bits) => Err(format!("invalid interrupt mask {}", bits)),
+            None => Ok(Op::InterruptMask { set: opcode == "ien", src: register(instruction.regsrc)? }),
+        },
+        "iack" => match imdval {
+            Some(line) => Ok(Op::AcknowledgeInterruptImm { line: check_line(line as u64)? }),
+            None => Ok(Op::AcknowledgeInterrupt { src: register(instruction.regsrc)? }),
+        },
+        "iraise" => match imdval {
+            Some(line) => Ok(Op::RaiseInterruptImm { line: check_line(line as u64)? }),
+            None => Ok(Op::RaiseInterrupt { src: register(instruction.regsrc)? }),
+        },
+        "ipend" => Ok(Op::PendingInterrupts { dst: register(instruction.regdst)? }),
+        "ivec" => Ok(Op::InterruptVector { src: register(instruction.regsrc)?, target: target(imdval, length)? }),
+        "iret" => Ok(Op::InterruptReturn),
+        "wfi" => Ok(Op::WaitForInterrupt),
+        "xvec" => Ok(Op::ExceptionVector { src: register(instruction.regsrc)?, target: target(imdval, length)? }),
+        "xcause" => Ok(Op::ExceptionCause { dst: register(instruction.regdst)? }),
+        "xepc" => Ok(Op::ExceptionPc { dst: register(instruction.regdst)? }),
+        "xaddr" => Ok(Op::ExceptionAddress { dst: register(instruction.regdst)? }),
+        "xsetepc" => Ok(Op::SetExceptionPc { src: register(instruction.regsrc)? }),
+        "xret" => Ok(Op::ExceptionReturn),
+        "mpubase" => Ok(Op::RegionBounds {
+            region: check_region(imdval.ok_or("mpubase without a region")?)?,
+            base: register(instruction.regsrc)?,
+            size: register(instruction.regext)?,
+        }),
+        "mpuperm" => Ok(Op::RegionPermissions {
+            region: check_region(imdval.ok_or("mpuperm without a region")?)?,
+            src: register(instruction.regsrc)?,
+        }),
+        "ujmp" => Ok(Op::UserJump { target: target(imdval, length)? }),
+        "priv" => Ok(Op::ReadPrivilege { dst: register(instruction.regdst)? }),
+        "ptroot" => Ok(Op::PageTableRoot { src: register(instruction.regsrc)? }),
+        "tlbflush" => Ok(Op::TlbFlush),
+        "brk" => Ok(Op::Breakpoint),
+        "coreid" => Ok(Op::CoreId { dst: register(instruction.regdst)? }),
+        "ncores" => Ok(Op::CoreCount { dst: register(instruction.regdst)? }),
+        "fence" => Ok(Op::Fence),
+        "lwarx" | "ldrex" => Ok(Op::LoadReserved {
+            size: if opcode == "lwarx" { 4 } else { 8 },
+            dst: register(instruction.regdst)?,
+            base: register(instruction.regsrc)?,
+            offset,
+        }),
+        "stwcx." | "strex" => Ok(Op::StoreConditional {
+            size: if opcode == "stwcx." { 4 } else { 8 },
+            success: if opcode == "stwcx." { 1 } else { 0 },
+            dst: register(instruction.regdst)?,
+            value: register(instruction.regext)?,
+            base: register(instruction.regsrc)?,
+            offset,
+        }),
+        "ecall" | "syscall" => match imdval {
+            Some(number) => Ok(Op::SyscallImm { number: number as u64 }),
+            None => Ok(Op::Syscall),
+        },
+        _ => Err(format!("illegal opcode '{}'", opcode)),
+    }
+}
+
+pub fn decode_program(program: &[Instruction]) -> Vec<Op> {
+    program.iter()
+        .map(|instruction| decode(instruction, program.len()).unwrap_or(Op::Illegal))
+        .collect()
+}
@@ -0,0 +1,247 @@
+use std::collections::VecDeque;
+
+use crate::models::device::{GpioReport};
+
+// memory mapped devices live above the data memory, every device register is a 64 bit word.
+// ld / st access a whole register, ldb / stb a single byte of it. the FP and vector loads
+// and stores can not reach the devices.
+//
+//  0x1000_0000  uart      +0x0  data     write: send the low byte to the console
+//                                        read: next input byte, -1 when there is none
+//                         +0x8  status   bit 0: ready to send (always set)
+//                                        bit 1: input available
+//                                        bit 2: end of input (closed and nothing left)
+//  0x1000_1000  gpio      +0x0  output
+//                         +0x8  input    read only
+//                         +0x10 direction
+//  0x1000_2000  counter   +0x0  value    read only, number of instructions executed
+
+pub const MMIO_BASE: u64 = 0x1000_0000;
+pub const UART_BASE: u64 = 0x1000_0000;
+pub const GPIO_BASE: u64 = 0x1000_1000;
+pub const COUNTER_BASE: u64 = 0x1000_2000;
+const DEVICE_SIZE: u64 = 0x1000;
+
+// console output beyond this is dropped, the same goes for the error console
+pub const MAX_CONSOLE: usize = 1024 * 1024;
+// at most this many bytes can be queued for the program to read
+pub const MAX_INPUT: usize = 1024 * 1024;
+
+const UART_STATUS_READY: u64 = 1;
+const UART_STATUS_INPUT: u64 = 2;
+const UART_STATUS_END: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Register {
+    UartData,
+    UartStatus,
+    GpioOutput,
+    GpioInput,
+    GpioDirection,
+    Counter,
+}
+
+#[inline(always)]
+pub fn is_mmio(address: u64) -> bool {
+    address >= MMIO_BASE
+}
+
+// (register, byte inside of it)
+fn register(address: u64, size: usize) -> Result<(Register, usize), String> {
+    let byte = (address % 8) as usize;
+    if byte + size > 8 {
+        return Err(format!("misaligned device access at 0x{:x}", address));
+    }
+    let (base, offset) = (address - address % DEVICE_SIZE, (address % DEVICE_SIZE) / 8);
+    let register = match (base, offset) {
+        (UART_BASE, 0) => Register::UartData,
+        (UART_BASE, 1) => Register::UartStatus,
+        (GPIO_BASE, 0) => Register::GpioOutput,
+        (GPIO_BASE, 1) => Register::GpioInput,
+        (GPIO_BASE, 2) => Register::GpioDirection,
+        (COUNTER_BASE, 0) => Register::Counter,
+        _ => return Err(format!("no device at 0x{:x}", address)),
+    };
+    Ok((register, byte))
+}
+
+fn bytes_of(value: u64, byte: usize, size: usize) -> u64 {
+    let value = value >> (byte * 8);
+    if size == 8 { value } else { value & ((1 << (size * 8)) - 1) }
+}
+
+fn with_bytes(word: u64, byte: usize, size: usize, value: u64) -> u64 {
+    let mask = if size == 8 { u64::MAX } else { ((1u64 << (size * 8)) - 1) << (byte * 8) };
+    (word & !mask) | ((value << (byte * 8)) & mask)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceBus {
+    pub console: Vec<u8>,
+    // written with the write syscall on stream 2
+    pub error_console: Vec<u8>,
+    // bytes waiting to be read by the program
+    pub input: VecDeque<u8>,
+    // no more input will be queued
+    pub input_closed: bool,
+    pub gpio_output: u64,
+    pub gpio_input: u64,
+    pub gpio_direction: u64,
+}
+
+impl DeviceBus {
+
+    pub fn new() -> DeviceBus {
+        DeviceBus::default()
+    }
+
+    // 'steps' is the current value of the free running counter
+    pub fn read(&mut self, address: u64, size: usize, steps: u64) -> Result<u64, String> {
+        let (register, byte) = register(address, size)?;
+        let word = match register {
+            Register::UartData => self.input.pop_front().map(|byte| byte as u64).unwrap_or(u64::MAX),
+            Register::UartStatus => {
+                let mut status = UART_STATUS_READY;
+                if !self.input.is_empty() {
+                    status |= UART_STATUS_INPUT;
+                } else if self.input_closed {
+                    status |= UART_STATUS_END;
+                }
+                status
+            },
+            Register::GpioOutput => self.gpio_output,
+            Register::GpioInput => self.gpio_input,
+            Register::GpioDirection => self.gpio_direction,
+            Register::Counter => steps,
+        };
+        Ok(bytes_of(word, byte, size))
+    }
+
+    pub fn write(&mut self, address: u64, size: usize, value: u64) -> Result<(), String> {
+        let (register, byte) = register(address, size)?;
+        match register {
+            Register::UartData => self.console_write(&[value as u8]),
+            Register::GpioOutput => self.gpio_output = with_bytes(self.gpio_output, byte, size, value),
+            Register::GpioDirection => self.gpio_direction = with_bytes(self.gpio_direction, byte, size, value),
+            Register::UartStatus | Register::GpioInput | Register::Counter => {
+                return Err(format!("device register at 0x{:x} is read only", address));
+            },
+        }
+        Ok(())
+    }
+
+    pub fn console_write(&mut self, bytes: &[u8]) {
+        let room = MAX_CONSOLE.saturating_sub(self.console.len());
+        self.console.extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+
+    pub fn error_write(&mut self, bytes: &[u8]) {
+        let room = MAX_CONSOLE.saturating_sub(self.error_console.len());
+        self.error_console.extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+
+    // queues input for the program, fails when the stream is closed or full
+    pub fn push_input(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.input_closed {
+            return Err("input stream is closed".to_string());
+        }
+        if self.input.len() + bytes.len() > MAX_INPUT {
+            return Err(format!("input queue is limited to {} bytes", MAX_INPUT));
+        }
+        self.input.extend(bytes);
+        Ok(())
+    }
+
+    // a blocking read can go ahead: there is input, or there never will be
+    pub fn input_ready(&self) -> bool {
+        !self.input.is_empty() || self.input_closed
+    }
+
+    pub fn console_text(&self) -> String {
+        String::from_utf8_lossy(&self.console).into_owned()
+    }
+
+    pub fn gpio(&self) -> GpioReport {
+        GpioReport {
+            output: self.gpio_output,
+            input: self.gpio_input,
+            direction: self.gpio_direction,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::exception::{ExceptionKind};
+    use crate::models::run::{RunStatus};
+    use crate::services::assembler::{assemble};
+    use crate::services::executor::{Machine};
+
+    fn run(source: &str, input: &[u8]) -> (Machine, RunStatus) {
+        let program = assemble(source, &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions;
+        let mut machine = Machine::new(program);
+        machine.devices.input.extend(input);
+        let status = machine.run(1_000);
+        (machine, status)
+    }
+
+    #[test]
+    fn mmio_registers_and_the_console_cap() {
+        let (machine, status) = run("
+            li r1, 0x10000000
+            add r2, r0, 72
+            st r2, [r1 + 0]
+            add r2, r0, 0x169
+            stb r2, [r1 + 0]
+            ld r3, [r1 + 8]
+            ld r4, [r1 + 0]
+            ld r5, [r1 + 0]
+            ld r6, [r1 + 8]
+            li r1, 0x10001000
+            li r2, 0x1234
+            st r2, [r1 + 0]
+            ldb r7, [r1 + 1]
+            halt", b"x");
+        assert_eq!(status, RunStatus::Halted);
+        // only the low byte reaches the console
+        assert_eq!(machine.devices.console_text(), "Hi");
+        assert_eq!(machine.registers[3], UART_STATUS_READY | UART_STATUS_INPUT);
+        assert_eq!((machine.registers[4], machine.registers[5]), (b'x' as u64, u64::MAX));
+        assert_eq!(machine.registers[6], UART_STATUS_READY);
+        assert_eq!((machine.devices.gpio().output, machine.registers[7]), (0x1234, 0x12));
+
+        let (machine, status) = run("li r1, 0x10002000\nst r1, [r1 + 0]\nhalt", b"");
+        assert_eq!(status, RunStatus::Faulted);
+        assert_eq!(machine.exception.as_ref().unwrap().kind, ExceptionKind::AccessFault);
+
+        // output beyond MAX_CONSOLE is dropped
+        let mut bus = DeviceBus::new();
+        bus.console_write(&vec![b'a'; MAX_CONSOLE - 1]);
+        bus.console_write(b"bc");
+        bus.console_write(b"d");
+        assert_eq!(bus.console.len(), MAX_CONSOLE);
+        assert_eq!(bus.console.last(), Some(&b'b'));
+    }
+
+    #[test]
+    fn the_input_queue_is_bounded() {
+        let mut bus = DeviceBus::new();
+        bus.push_input(&vec![b'a'; MAX_INPUT - 1]).unwrap();
+        // all or nothing
+        assert!(bus.push_input(b"bc").is_err());
+        assert_eq!(bus.input.len(), MAX_INPUT - 1);
+        bus.push_input(b"b").unwrap();
+        assert!(bus.push_input(b"c").is_err());
+
+        // reading makes room again
+        bus.input.pop_front();
+        bus.push_input(b"c").unwrap();
+        assert_eq!(bus.input.len(), MAX_INPUT);
+
+        bus.input_closed = true;
+        bus.input.clear();
+        assert_eq!(bus.push_input(b"d"), Err("input stream is closed".to_string()));
+        assert!(bus.input_ready());
+    }
+}
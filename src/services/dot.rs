@@ -0,0 +1,117 @@
+use std::fmt::Write;
+
+use crate::models::instruction::{Instruction};
+use crate::models::analysis::{EdgeKind};
+use crate::services::analysis::{analyze};
+
+// the text goes between double quotes, control characters are shown the way rust writes
+// them (a newline as \n) so an instruction always stays on its line
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for character in text.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            _ if character.is_control() => {
+                for part in character.escape_default() {
+                    if part == '\\' { escaped.push_str("\\\\") } else { escaped.push(part) }
+                }
+            },
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+fn edge_label(kind: EdgeKind) -> &'static str {
+    match kind {
+        EdgeKind::Taken => "taken",
+        EdgeKind::FallThrough => "fall-through",
+        EdgeKind::Jump => "jump",
+        EdgeKind::Call => "call",
+        EdgeKind::Return => "return",
+        EdgeKind::Interrupt => "interrupt",
+        EdgeKind::Exception => "exception",
+    }
+}
+
+// renders the basic blocks of a program as a graphviz digraph. with 'annotate' the blocks and
+// instructions show how many times they were executed, 'counts' holds those of the last run.
+// loading or editing the program clears the last run, the graph then says there are no counts
+// rather than showing every instruction as never executed.
+pub fn render_dot(program: &[Instruction], counts: Option<&[u64]>, annotate: bool) -> String {
+
+    let report = analyze(program);
+    // counts of a different program are of no use
+    let counts = counts.filter(|counts| annotate && counts.len() == program.len());
+    let mut dot = String::new();
+
+    let _ = writeln!(dot, "digraph code_segment {{");
+    let _ = writeln!(dot, "    node [shape=box, fontname=\"monospace\"];");
+    if annotate && counts.is_none() {
+        let _ = writeln!(dot, "    label=\"no execution counts, the program has not run since it was loaded or edited\";");
+    }
+
+    for block in &report.blocks {
+        let mut label = format!("B{}", block.id);
+        if let Some(counts) = counts {
+            let _ = write!(label, " (x{})", counts[block.start]);
+        }
+        label.push_str("\\l");
+
+        for index in block.start..=block.end {
+            let _ = write!(label, "{:>4}: {}", index, escape(&program[index].to_string()));
+            if let Some(counts) = counts {
+                let _ = write!(label, "  ; x{}", counts[index]);
+            }
+            label.push_str("\\l");
+        }
+
+        let style = if block.reachable { "" } else { ", style=dashed" };
+        let _ = writeln!(dot, "    B{} [label=\"{}\"{}];", block.id, label, style);
+    }
+
+    for block in &report.blocks {
+        for edge in &block.successors {
+            let _ = writeln!(dot, "    B{} -> B{} [label=\"{}\"];", block.id, edge.to, edge_label(edge.kind));
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(opcode: &str, imdval: &str) -> Instruction {
+        Instruction { opcode: opcode.to_string(), imdval: imdval.to_string(), regsrc: 0, regext: 0, regdst: 1 }
+    }
+
+    #[test]
+    fn escapes_quotes_and_control_characters() {
+        assert_eq!(escape("say \"hi\"\\"), "say \\\"hi\\\"\\\\");
+        assert_eq!(escape("a\nb\r\u{7}"), "a\\\\nb\\\\r\\\\u{7}");
+        let dot = render_dot(&[instruction("add", "1\n}")], None, false);
+        assert_eq!(dot.lines().filter(|line| line.contains("add r1")).count(), 1);
+    }
+
+    #[test]
+    fn says_when_there_are_no_counts() {
+        let program = [instruction("add", "1"), instruction("halt", "")];
+        let note = "no execution counts";
+
+        let dot = render_dot(&program, Some(&[1, 1]), true);
+        assert!(dot.contains("(x1)") && !dot.contains(note));
+
+        // cleared by a load or an edit, or of a program of a different length
+        for counts in [None, Some(&[1u64][..])] {
+            let dot = render_dot(&program, counts, true);
+            assert!(dot.contains(note) && !dot.contains("; x"));
+        }
+
+        let dot = render_dot(&program, None, false);
+        assert!(!dot.contains(note));
+    }
+}
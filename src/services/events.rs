@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex};
+
+use crate::models::event::{Event, EventKind};
+
+// the most recent events are kept for the subscribers to pick up, a subscriber that
+// falls further behind than this misses events
+pub const EVENT_BACKLOG: usize = 1024;
+
+struct EventLog {
+    next_id: u64,
+    events: VecDeque<Event>,
+}
+
+// events are published by the workers and the runs and read by any number of subscribers,
+// each of them remembers the id of the next event it wants
+pub struct EventBus {
+    log: Mutex<EventLog>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new()
+    }
+}
+
+impl EventBus {
+
+    pub fn new() -> EventBus {
+        EventBus {
+            log: Mutex::new(EventLog { next_id: 1, events: VecDeque::new() }),
+        }
+    }
+
+    pub fn publish(&self, kind: EventKind, worker: Option<&str>, run: Option<&str>, message: String) {
+        let mut log = self.log.lock().unwrap();
+        let id = log.next_id;
+        log.next_id += 1;
+        if log.events.len() == EVENT_BACKLOG {
+            log.events.pop_front();
+        }
+        log.events.push_back(Event {
+            id,
+            kind,
+            worker: worker.map(str::to_string),
+            run: run.map(str::to_string),
+            message,
+        });
+    }
+
+    pub fn worker_event(&self, kind: EventKind, worker: &str, message: String) {
+        self.publish(kind, Some(worker), None, message);
+    }
+
+    pub fn run_event(&self, kind: EventKind, run: &str, message: String) {
+        self.publish(kind, None, Some(run), message);
+    }
+
+    // events from id 'from' on, and the id to ask for next time
+    pub fn since(&self, from: u64) -> (Vec<Event>, u64) {
+        let log = self.log.lock().unwrap();
+        let events = log.events.iter()
+            .filter(|event| event.id >= from)
+            .cloned()
+            .collect();
+        (events, log.next_id)
+    }
+
+    // id of the next event, a new subscriber starts here
+    pub fn next_id(&self) -> u64 {
+        self.log.lock().unwrap().next_id
+    }
+}
@@ -0,0 +1,318 @@
+use crate::models::exception::{ExceptionKind, ExceptionReport};
+use crate::models::protection::{Privilege};
+use crate::services::executor::{Machine};
+use crate::services::decoder::{Op};
+
+// precise exceptions. an instruction that can not execute raises an exception before it
+// changes anything, the pc still points at it. with a handler installed for the cause the
+// core continues there, otherwise the run faults and its result carries the exception.
+//
+//  xvec        vector[rs] = imdval                  (handler instruction index, rs = cause)
+//  xcause      rd = cause of the last exception     (see ExceptionKind)
+//  xepc        rd = pc of the instruction that raised it
+//  xaddr       rd = data address of a memory exception, 0 otherwise
+//  xsetepc     epc = rs
+//  xret        leave the handler and continue at epc
+//
+// xret executes the faulting instruction again, a handler that fixed nothing skips it:
+//
+//   xepc r5
+//   add r5, r5, 1
+//   xsetepc r5
+//   xret
+//
+// an exception raised inside a handler is never caught. budget exhaustion ends the run as
+// an exception as well, but no handler can catch it. every core has its own vectors.
+//
+// the stack of core n is the CORE_STACK_SIZE bytes below its initial sp (services/core.rs),
+// an access through sp below it raises StackOverflow. accesses through other registers are
+// only checked against the size of the memory.
+
+pub const EXCEPTION_CAUSES: usize = 10;
+
+// an exception on its way to a handler or to the run result
+#[derive(Debug, Clone)]
+pub struct Exception {
+    pub kind: ExceptionKind,
+    pub address: Option<u64>,
+    pub region: Option<usize>,
+    pub message: String,
+}
+
+impl Exception {
+    pub fn new(kind: ExceptionKind, message: String) -> Exception {
+        Exception { kind, address: None, region: None, message }
+    }
+
+    pub fn at(kind: ExceptionKind, address: u64, message: String) -> Exception {
+        Exception { kind, address: Some(address), region: None, message }
+    }
+}
+
+// the services raise plain messages, those that need a kind of their own are mapped
+// where they are called
+impl From<String> for Exception {
+    fn from(message: String) -> Exception {
+        Exception::new(ExceptionKind::InvalidOperation, message)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExceptionUnit {
+    pub vectors: [Option<usize>; EXCEPTION_CAUSES],
+    pub in_handler: bool,
+    // privilege level the handler returns to
+    pub previous: Privilege,
+    // of the last exception, they stay readable after xret
+    pub cause: u64,
+    pub epc: usize,
+    pub address: u64,
+    // exceptions that went to a handler
+    pub handled: u64,
+}
+
+impl ExceptionUnit {
+
+    pub fn new() -> ExceptionUnit {
+        ExceptionUnit::default()
+    }
+
+    // leaves the handler, returns the pc to continue at
+    pub fn leave(&mut self) -> Result<usize, String> {
+        if !self.in_handler {
+            return Err("xret outside of an exception handler".to_string());
+        }
+        self.in_handler = false;
+        Ok(self.epc)
+    }
+}
+
+// ld and st, like the atomics, access naturally aligned words. ldb and stb always do.
+pub fn check_alignment(address: u64, size: usize) -> Result<(), Exception> {
+    if address.is_multiple_of(size as u64) {
+        return Ok(());
+    }
+    let message = format!("misaligned {} byte access at 0x{:x}", size, address);
+    Err(Exception::at(ExceptionKind::MisalignedAccess, address, message))
+}
+
+// a device access the bus refused
+pub fn device_exception(address: u64, size: usize, message: String) -> Exception {
+    let misaligned = (address % 8) as usize + size > 8;
+    let kind = if misaligned { ExceptionKind::MisalignedAccess } else { ExceptionKind::AccessFault };
+    Exception::at(kind, address, message)
+}
+
+pub fn check_cause(cause: u64) -> Result<usize, String> {
+    match ExceptionKind::from_cause(cause) {
+        Some(kind) => Ok(kind.cause() as usize),
+        None => Err(format!("invalid exception cause {}", cause)),
+    }
+}
+
+impl Machine {
+
+    // sends an exception raised at 'pc' to its handler, or hands it back when there is none
+    pub(crate) fn take_exception(&mut self, pc: usize, exception: Exception) -> Result<(), Exception> {
+        let unit = &mut self.exceptions;
+        let handler = match exception.kind {
+            _ if unit.in_handler => None,
+            ExceptionKind::BudgetExhausted => None,
+            kind => unit.vectors[kind.cause() as usize],
+        };
+        let Some(handler) = handler else {
+            return Err(exception);
+        };
+        unit.in_handler = true;
+        unit.cause = exception.kind.cause();
+        unit.epc = pc;
+        unit.address = exception.address.unwrap_or(0);
+        unit.handled += 1;
+        unit.previous = self.privilege;
+        self.privilege = Privilege::Supervisor;
+        self.pc = handler;
+        Ok(())
+    }
+
+    pub(crate) fn step_exception(&mut self, op: Op, next_pc: &mut usize) -> Result<(), Exception> {
+        match op {
+            Op::ExceptionVector { src, target } => {
+                let cause = check_cause(self.registers[src])?;
+                self.exceptions.vectors[cause] = Some(target);
+            },
+            Op::ExceptionCause { dst } => self.set(dst, self.exceptions.cause),
+            Op::ExceptionPc { dst } => self.set(dst, self.exceptions.epc as u64),
+            Op::ExceptionAddress { dst } => self.set(dst, self.exceptions.address),
+            Op::SetExceptionPc { src } => {
+                let epc = self.registers[src];
+                if epc > self.ops.len() as u64 {
+                    return Err(format!("exception return address {} out of range", epc).into());
+                }
+                self.exceptions.epc = epc as usize;
+            },
+            Op::ExceptionReturn => {
+                *next_pc = self.exceptions.leave()?;
+                self.privilege = self.exceptions.previous;
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // exceptions that went to a handler, on all cores
+    pub fn exceptions_handled(&self) -> u64 {
+        let parked = self.cores.iter().enumerate()
+            .filter(|(id, _)| *id != self.core)
+            .map(|(_, core)| core.exceptions.handled);
+        self.exceptions.handled + parked.sum::<u64>()
+    }
+
+    // ends the run of the executing core with an exception nobody handled
+    pub(crate) fn fail(&mut self, exception: Exception) {
+        self.fault = Some(format!("{} at pc {}", exception.message, self.pc));
+        self.exception = Some(ExceptionReport {
+            kind: exception.kind,
+            cause: exception.kind.cause(),
+            core: self.core,
+            pc: self.pc,
+            instruction: self.code.get(self.pc).cloned(),
+            address: exception.address,
+            region: exception.region,
+            message: exception.message,
+            nested: self.exceptions.in_handler,
+        });
+    }
+
+    // the budget is up, 'budget' is the number of instructions the run was allowed
+    pub fn exhaust(&mut self, budget: u64) {
+        let message = format!("budget of {} instructions exhausted", budget);
+        self.fail(Exception::new(ExceptionKind::BudgetExhausted, message.clone()));
+        // not the fault of the instruction at pc
+        self.fault = Some(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::run::{RunStatus};
+    use crate::services::assembler::{assemble};
+
+    fn run(source: &str) -> (Machine, RunStatus) {
+        let program = assemble(source, &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions;
+        let mut machine = Machine::new(program);
+        let status = machine.run(1_000);
+        (machine, status)
+    }
+
+    #[test]
+    fn misaligned_loads_and_stores_raise_an_exception() {
+        for access in ["ld r1, [r2 + 0x103]", "st r1, [r2 + 0x104]"] {
+            let (machine, status) = run(&format!("{}\nhalt", access));
+            assert_eq!(status, RunStatus::Faulted);
+            let exception = machine.exception.as_ref().unwrap();
+            assert_eq!(exception.kind, ExceptionKind::MisalignedAccess);
+            assert_eq!(exception.pc, 0);
+        }
+
+        // bytes are always aligned
+        let (machine, status) = run("add r1, r0, 7\nstb r1, [r0 + 0x103]\nldb r2, [r0 + 0x103]\nhalt");
+        assert_eq!(status, RunStatus::Halted);
+        assert_eq!(machine.registers[2], 7);
+    }
+
+    #[test]
+    fn misaligned_access_goes_to_its_handler() {
+        let (machine, status) = run("
+            add r1, r0, 2
+            xvec r1, handler
+            ld r3, [r0 + 0x105]
+            halt
+        handler:
+            xcause r4
+            xaddr r5
+            xepc r6
+            add r6, r6, 1
+            xsetepc r6
+            xret");
+        assert_eq!(status, RunStatus::Halted);
+        assert_eq!(&machine.registers[4..7], &[2, 0x105, 3]);
+    }
+
+    #[test]
+    fn division_by_zero_is_precise() {
+        for opcode in ["div", "rem"] {
+            let (machine, status) = run(&format!("add r1, r0, 7\nadd r3, r0, 5\n{} r3, r1, r0\nhalt", opcode));
+            assert_eq!(status, RunStatus::Faulted);
+            let exception = machine.exception.as_ref().unwrap();
+            assert_eq!((exception.kind, exception.cause, exception.pc), (ExceptionKind::DivisionByZero, 1, 2));
+            assert!(!exception.nested);
+            // the destination is untouched
+            assert_eq!(machine.registers[3], 5);
+        }
+    }
+
+    #[test]
+    fn an_illegal_opcode_goes_to_its_handler() {
+        let mut program = assemble("xvec r0, handler\nnop\nhalt\nhandler:\nxcause r4\nxepc r5\nhalt",
+            &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions;
+        program[1].opcode = "frobnicate".to_string();
+
+        let mut machine = Machine::new(program.clone());
+        assert_eq!(machine.run(1_000), RunStatus::Halted);
+        assert_eq!(&machine.registers[4..6], &[0, 1]);
+        assert_eq!(machine.exceptions_handled(), 1);
+
+        // without the vector the run faults
+        program[0].opcode = "nop".to_string();
+        let mut machine = Machine::new(program);
+        assert_eq!(machine.run(1_000), RunStatus::Faulted);
+        let exception = machine.exception.as_ref().unwrap();
+        assert_eq!((exception.kind, exception.pc), (ExceptionKind::IllegalInstruction, 1));
+        assert_eq!(exception.instruction.as_ref().unwrap().opcode, "frobnicate");
+    }
+
+    #[test]
+    fn the_stack_overflows_through_sp_only() {
+        // the stack of core 0 is the CORE_STACK_SIZE bytes below the top of memory
+        let (machine, status) = run("
+            sub sp, sp, 0x1000
+            st r1, [sp + 0]
+            add r2, sp, 0
+            sub r2, r2, 8
+            st r1, [r2 + 0]
+            sub sp, sp, 8
+            st r1, [sp + 0]
+            halt");
+        assert_eq!(status, RunStatus::Faulted);
+        let exception = machine.exception.as_ref().unwrap();
+        assert_eq!(exception.kind, ExceptionKind::StackOverflow);
+        assert_eq!((exception.pc, exception.address), (6, Some(0xeff8)));
+    }
+
+    #[test]
+    fn budget_exhaustion_can_not_be_caught() {
+        let handlers: String = (0..EXCEPTION_CAUSES).map(|cause| format!("add r1, r0, {}\nxvec r1, handler\n", cause)).collect();
+        let (machine, status) = run(&format!("{}spin:\njmp spin\nhandler:\nhalt", handlers));
+        assert_eq!(status, RunStatus::BudgetExhausted);
+        assert_eq!(machine.exception.as_ref().unwrap().kind, ExceptionKind::BudgetExhausted);
+        assert_eq!(machine.exceptions_handled(), 0);
+    }
+
+    #[test]
+    fn an_exception_in_a_handler_is_nested() {
+        let (machine, status) = run("
+            add r1, r0, 1
+            xvec r1, handler
+            div r3, r1, r0
+            halt
+        handler:
+            rem r4, r1, r0
+            xret");
+        assert_eq!(status, RunStatus::Faulted);
+        let exception = machine.exception.as_ref().unwrap();
+        assert_eq!((exception.kind, exception.pc), (ExceptionKind::DivisionByZero, 4));
+        assert!(exception.nested);
+        assert_eq!(machine.exceptions_handled(), 1);
+    }
+}
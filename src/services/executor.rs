@@ -0,0 +1,682 @@
+use crate::models::instruction::{Instruction};
+use crate::models::run::{RunRequest, RunResult, RunStatus};
+use crate::services::cache::{CacheHierarchy};
+use crate::services::branch::{BranchObserver};
+use crate::services::decoder::{decode, decode_program, AluOp, Condition, FloatUnaryOp, Op};
+use crate::models::float::{RoundingMode};
+use crate::services::float;
+use crate::services::vector::{self, Vector};
+use crate::services::interrupt::{check_line, InterruptController};
+use crate::services::device::{is_mmio, DeviceBus};
+use crate::services::syscall::{SYSCALL_RESULT};
+use crate::services::core::{CoreState, CORE_STACK_SIZE};
+use crate::services::consistency::{BufferedStore};
+use crate::models::consistency::{MemoryModel};
+use crate::services::exception::{check_alignment, device_exception, Exception, ExceptionUnit};
+use crate::models::exception::{ExceptionKind, ExceptionReport};
+use crate::services::protection::{Mpu, PERMISSION_READ, PERMISSION_WRITE};
+use crate::models::protection::{Privilege};
+use crate::services::paging::{Mmu};
+
+// the instreams machine:
+//
+// - 32 general purpose 64 bit registers, r0 always reads as zero
+// - code lives in its own address space (code_segment), the pc is an instruction index
+// - a flat, byte addressable, little endian data memory
+//
+// every instruction has the same shape: 'regdst = regsrc <op> operand', where the operand
+// is 'imdval' when one is given and 'regext' otherwise ("0x" or "" means no immediate).
+//
+//  add, sub, mul, div, rem, and, or, xor, shl, shr, sar, slt, sltu
+//  ld  / ldb   regdst = memory[regsrc + imdval]           (64 bit / 8 bit)
+//  st  / stb   memory[regsrc + imdval] = regext           (64 bit / 8 bit)
+//              ld and st take 8 byte aligned addresses, others raise MisalignedAccess
+//  beq, bne, blt, bge, bltu, bgeu   if regsrc <cmp> regext => pc = imdval
+//  jmp         pc = imdval
+//  call        r31 = pc + 1, pc = imdval
+//  ret         pc = r31
+//  nop, halt
+//  brk         stops the run, a live run can be resumed after it
+//
+// floating point opcodes and the FP register file are described in services/float.rs,
+// the vector registers and SIMD opcodes in services/vector.rs, the interrupt
+// controller in services/interrupt.rs, multi-core runs in services/core.rs, their
+// atomics in services/atomic.rs and memory models in services/consistency.rs. ld / st
+// above the data memory reach the memory mapped devices of services/device.rs, ecall the
+// system calls of services/syscall.rs.
+//
+// running past the last instruction halts the machine. the program is decoded before it runs,
+// so malformed instructions (bad registers, branch targets, opcodes) fault when they are reached.
+// faults are precise exceptions that a program can handle, see services/exception.rs,
+// services/protection.rs runs untrusted code in user mode and services/paging.rs translates
+// virtual addresses.
+
+pub const REGISTER_COUNT: usize = 32;
+pub const MEMORY_SIZE: usize = 64 * 1024;
+pub const DEFAULT_BUDGET: u64 = 100_000;
+// a /run executes its whole budget in one request, larger budgets are cut down to this
+pub const MAX_BUDGET: u64 = 10_000_000;
+
+pub const REG_ZERO: u8 = 0;
+pub const REG_SP: u8 = 30;
+pub const REG_LINK: u8 = 31;
+
+// "0x" / "" => no immediate, "0x1f", "-0x10", "42", "-7" => value
+pub fn parse_imdval(imdval: &str) -> Result<Option<i64>, String> {
+
+    let text = imdval.trim();
+    if text.is_empty() || text == "0x" {
+        return Ok(None);
+    }
+
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).map(|value| value as i64),
+        None => digits.parse::<i64>(),
+    };
+
+    match value {
+        Ok(value) => Ok(Some(if negative { value.wrapping_neg() } else { value })),
+        Err(_) => Err(format!("invalid imdval '{}'", imdval)),
+    }
+}
+
+pub fn is_alu(opcode: &str) -> bool {
+    AluOp::from_opcode(opcode).is_some()
+}
+
+pub fn is_branch(opcode: &str) -> bool {
+    Condition::from_opcode(opcode).is_some()
+}
+
+// opcodes whose imdval is an instruction index
+pub fn has_target(opcode: &str) -> bool {
+    is_branch(opcode) || matches!(opcode, "jmp" | "call" | "ivec" | "xvec" | "ujmp")
+}
+
+// 'src <opcode> operand' for the arithmetic and logic opcodes
+pub fn alu(opcode: &str, src: u64, operand: u64) -> Result<u64, String> {
+    match AluOp::from_opcode(opcode) {
+        Some(op) => op.apply(src, operand),
+        None => Err(format!("illegal opcode '{}'", opcode)),
+    }
+}
+
+fn division(message: String) -> Exception {
+    Exception::new(ExceptionKind::DivisionByZero, message)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MachineState {
+    Running,
+    Halted,
+    // stopped at a wfi until an interrupt can be delivered
+    Waiting,
+    // a read syscall waits for input, it is executed again when input arrives
+    Blocked,
+    // stopped after a brk
+    Break,
+}
+
+pub struct Machine {
+    pub code: Vec<Instruction>,
+    pub ops: Vec<Op>,
+    pub registers: [u64; REGISTER_COUNT],
+    pub fregisters: [f64; REGISTER_COUNT],
+    // result of the last fcmp, see float::FLAG_*
+    pub fflags: u64,
+    pub rounding: RoundingMode,
+    pub vregisters: [Vector; REGISTER_COUNT],
+    pub memory: Vec<u8>,
+    pub pc: usize,
+    pub steps: u64,
+    // number of times each instruction was executed
+    pub counts: Vec<u64>,
+    pub state: MachineState,
+    pub fault: Option<String>,
+    pub cache: Option<CacheHierarchy>,
+    pub branch: Option<BranchObserver>,
+    pub interrupts: InterruptController,
+    pub devices: DeviceBus,
+    // set by the exit syscall
+    pub exit_code: Option<i64>,
+    pub syscall_errors: Vec<String>,
+    // clock ticks of a live run, returned by the time syscall
+    pub ticks: u64,
+    // set by the yield syscall, ends the current call to advance()
+    pub yielded: bool,
+    // the parked cores of a multi-core run (empty with a single core), 'core' is the one executing
+    pub cores: Vec<CoreState>,
+    pub core: usize,
+    pub quantum: u64,
+    // granule reserved by the last lwarx / ldrex of the executing core
+    pub reservation: Option<usize>,
+    pub memory_model: MemoryModel,
+    // stores of the executing core that the other cores do not see yet, oldest first
+    pub store_buffer: Vec<BufferedStore>,
+    pub exceptions: ExceptionUnit,
+    // the exception that ended the run
+    pub exception: Option<ExceptionReport>,
+    pub privilege: Privilege,
+    pub mpu: Mpu,
+    pub mmu: Mmu,
+}
+
+impl Machine {
+
+    pub fn new(code: Vec<Instruction>) -> Machine {
+        let mut machine = Machine {
+            counts: vec![0; code.len()],
+            ops: decode_program(&code),
+            code,
+            registers: [0; REGISTER_COUNT],
+            fregisters: [0.0; REGISTER_COUNT],
+            fflags: 0,
+            rounding: RoundingMode::default(),
+            vregisters: [0; REGISTER_COUNT],
+            memory: vec![0; MEMORY_SIZE],
+            pc: 0,
+            steps: 0,
+            state: MachineState::Running,
+            fault: None,
+            cache: None,
+            branch: None,
+            interrupts: InterruptController::new(),
+            devices: DeviceBus::new(),
+            exit_code: None,
+            syscall_errors: Vec::new(),
+            ticks: 0,
+            yielded: false,
+            cores: Vec::new(),
+            core: 0,
+            quantum: 1,
+            reservation: None,
+            memory_model: MemoryModel::Sequential,
+            store_buffer: Vec::new(),
+            exceptions: ExceptionUnit::new(),
+            exception: None,
+            privilege: Privilege::Supervisor,
+            mpu: Mpu::new(),
+            mmu: Mmu::default(),
+        };
+        // the stack grows down from the top of memory
+        machine.registers[REG_SP as usize] = MEMORY_SIZE as u64;
+        machine
+    }
+
+    // attaches the simulators a run request asks for
+    pub fn attach(&mut self, request: &RunRequest) -> Result<(), String> {
+        if let Some(setup) = &request.cache {
+            self.cache = Some(CacheHierarchy::new(setup)?);
+        }
+        if let Some(config) = &request.branch_predictor {
+            self.branch = Some(BranchObserver::new(config)?);
+        }
+        if let Some(input) = &request.input {
+            self.devices.input.extend(input.bytes());
+        }
+        self.memory_model = request.memory_model.unwrap_or_default();
+        if let Some(config) = &request.paging {
+            self.mmu = Mmu::new(config)?;
+        }
+        self.set_cores(request.cores.unwrap_or(1), request.quantum.unwrap_or(1))
+    }
+
+    pub fn result(&self, id: &str, status: RunStatus) -> RunResult {
+        RunResult {
+            id: id.to_string(),
+            status,
+            steps: self.steps,
+            core: self.core,
+            cores: self.core_reports(),
+            pc: self.pc,
+            registers: self.registers.to_vec(),
+            fregisters: self.fregisters.to_vec(),
+            fflags: self.fflags,
+            rounding: self.rounding,
+            vregisters: self.vregisters.iter().map(|vector| format!("0x{:032x}", vector)).collect(),
+            counts: self.counts.clone(),
+            fault: self.fault.clone(),
+            exception: self.exception.clone(),
+            exceptions_handled: self.exceptions_handled(),
+            privilege: self.privilege,
+            regions: self.mpu.report(),
+            tlb: self.mmu.report(),
+            cache: self.cache.as_ref().map(|cache| cache.report()),
+            branch: self.branch.as_ref().map(|branch| branch.report()),
+            interrupts: self.interrupts.report(),
+            console: self.devices.console_text(),
+            input_pending: self.devices.input.len(),
+            input_closed: self.devices.input_closed,
+            error_console: String::from_utf8_lossy(&self.devices.error_console).into_owned(),
+            exit_code: self.exit_code,
+            syscall_errors: self.syscall_errors.clone(),
+            gpio: self.devices.gpio(),
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn set(&mut self, index: usize, value: u64) {
+        self.registers[index] = value;
+        self.registers[REG_ZERO as usize] = 0;
+    }
+
+    #[inline(always)]
+    pub(crate) fn address(&mut self, base: usize, offset: u64, size: usize, access: u8) -> Result<usize, Exception> {
+        let mut address = self.registers[base].wrapping_add(offset);
+        if self.mmu.root.is_some() {
+            address = self.translate(address, size, access)?;
+        }
+        if address.checked_add(size as u64).is_none_or(|end| end > self.memory.len() as u64) {
+            let message = format!("memory access out of bounds at 0x{:x}", address);
+            return Err(Exception::at(ExceptionKind::AccessFault, address, message));
+        }
+        if base == REG_SP as usize && address < self.stack_limit() {
+            let message = format!("stack overflow at 0x{:x}", address);
+            return Err(Exception::at(ExceptionKind::StackOverflow, address, message));
+        }
+        self.protect(address, size, access)?;
+        Ok(address as usize)
+    }
+
+    // lowest address of the stack of the executing core, a program with virtual memory
+    // places its stacks itself
+    fn stack_limit(&self) -> u64 {
+        if self.mmu.root.is_some() {
+            return 0;
+        }
+        self.memory.len().saturating_sub((self.core + 1) * CORE_STACK_SIZE) as u64
+    }
+
+    #[inline(always)]
+    pub(crate) fn read(&mut self, pc: usize, address: usize, size: usize) -> u64 {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.read(pc, address as u64);
+        }
+        let mut bytes = [0u8; 8];
+        self.load_bytes(address, &mut bytes[..size]);
+        u64::from_le_bytes(bytes)
+    }
+
+    #[inline(always)]
+    pub(crate) fn write(&mut self, pc: usize, address: usize, size: usize, value: u64) {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.write(pc, address as u64);
+        }
+        self.store_bytes(address, &value.to_le_bytes()[..size]);
+    }
+
+    // the floating point opcodes, kept out of step() so the integer dispatch stays small
+    fn step_float(&mut self, pc: usize, op: Op) -> Result<(), Exception> {
+        let mode = self.rounding;
+        match op {
+            Op::FloatArith { op, double, dst, src, ext } => {
+                self.fregisters[dst] = float::arithmetic(op, self.fregisters[src], self.fregisters[ext], double, mode, &mut self.fflags);
+            },
+            Op::FloatArithImm { op, double, dst, src, imm } => {
+                self.fregisters[dst] = float::arithmetic(op, self.fregisters[src], imm, double, mode, &mut self.fflags);
+            },
+            Op::FloatUnary { op, double, dst, src } => {
+                let value = self.fregisters[src];
+                self.fregisters[dst] = match op {
+                    FloatUnaryOp::Sqrt => float::square_root(value, double, mode, &mut self.fflags),
+                    FloatUnaryOp::Neg => float::negate(value, double),
+                    FloatUnaryOp::Abs => float::absolute(value, double),
+                    FloatUnaryOp::ToSingle => float::to_single(value, mode, &mut self.fflags),
+                    FloatUnaryOp::ToDouble => value as f32 as f64,
+                };
+            },
+            Op::FloatMulAdd { double, dst, src, ext } => {
+                self.fregisters[dst] = float::multiply_add(self.fregisters[src], self.fregisters[ext], self.fregisters[dst], double, mode, &mut self.fflags);
+            },
+            Op::FloatLoadImm { double, dst, imm } => {
+                self.fregisters[dst] = if double { imm } else { float::to_single(imm, mode, &mut self.fflags) };
+            },
+            Op::FloatFromInt { double, dst, src } => {
+                self.fregisters[dst] = float::from_integer(self.registers[src] as i64, double, mode, &mut self.fflags);
+            },
+            Op::FloatToInt { double, dst, src } => {
+                let value = float::to_integer(self.fregisters[src], double, mode, &mut self.fflags);
+                self.set(dst, value as u64);
+            },
+            Op::FloatBitsToInt { double, dst, src } => {
+                let value = self.fregisters[src];
+                let bits = if double { value.to_bits() } else { (value as f32).to_bits() as u64 };
+                self.set(dst, bits);
+            },
+            Op::IntBitsToFloat { double, dst, src } => {
+                let bits = self.registers[src];
+                self.fregisters[dst] = if double { f64::from_bits(bits) } else { f32::from_bits(bits as u32) as f64 };
+            },
+            Op::FloatLoad { double, dst, base, offset } => {
+                let size = if double { 8 } else { 4 };
+                let address = self.address(base, offset, size, PERMISSION_READ)?;
+                let bits = self.read(pc, address, size);
+                self.fregisters[dst] = if double { f64::from_bits(bits) } else { f32::from_bits(bits as u32) as f64 };
+            },
+            Op::FloatStore { double, value, base, offset } => {
+                let size = if double { 8 } else { 4 };
+                let address = self.address(base, offset, size, PERMISSION_WRITE)?;
+                let value = self.fregisters[value];
+                let bits = if double { value.to_bits() } else { (value as f32).to_bits() as u64 };
+                self.write(pc, address, size, bits);
+            },
+            Op::FloatCompare { double, src, ext } => {
+                // the exceptions stay
+                self.fflags = (self.fflags & !float::COMPARE_FLAGS) | float::compare(self.fregisters[src], self.fregisters[ext], double);
+            },
+            Op::ReadFlags { dst } => self.set(dst, self.fflags),
+            Op::SetFlags { src } => self.fflags = self.registers[src],
+            Op::SetFlagsImm { flags } => self.fflags = flags,
+            Op::ReadRounding { dst } => self.set(dst, self.rounding.bits()),
+            Op::SetRounding { src } => {
+                self.rounding = match RoundingMode::from_bits(self.registers[src]) {
+                    Some(mode) => mode,
+                    None => return Err(format!("invalid rounding mode {}", self.registers[src]).into()),
+                };
+            },
+            Op::SetRoundingImm { mode } => self.rounding = mode,
+            _ => {},
+        }
+        Ok(())
+    }
+
+    fn step_vector(&mut self, pc: usize, op: Op) -> Result<(), Exception> {
+        let mode = self.rounding;
+        match op {
+            Op::VecArith { op, lanes, dst, src, ext } => {
+                self.vregisters[dst] = vector::lanewise(op, lanes, self.vregisters[src], self.vregisters[ext], mode, &mut self.fflags);
+            },
+            Op::VecArithImm { op, lanes, dst, src, imm } => {
+                self.vregisters[dst] = vector::lanewise(op, lanes, self.vregisters[src], vector::splat(lanes, imm), mode, &mut self.fflags);
+            },
+            Op::VecLoad { dst, base, offset } => {
+                let address = self.address(base, offset, 16, PERMISSION_READ)?;
+                if let Some(cache) = self.cache.as_mut() {
+                    cache.read(pc, address as u64);
+                }
+                let mut bytes = [0u8; 16];
+                self.load_bytes(address, &mut bytes);
+                self.vregisters[dst] = Vector::from_le_bytes(bytes);
+            },
+            Op::VecStore { value, base, offset } => {
+                let address = self.address(base, offset, 16, PERMISSION_WRITE)?;
+                if let Some(cache) = self.cache.as_mut() {
+                    cache.write(pc, address as u64);
+                }
+                self.store_bytes(address, &self.vregisters[value].to_le_bytes());
+            },
+            Op::VecSplat { lanes, dst, src } => {
+                let bits = vector::to_lane(lanes, self.scalar(lanes.is_float(), src));
+                self.vregisters[dst] = vector::splat(lanes, bits);
+            },
+            Op::VecInsert { lanes, dst, src, lane } => {
+                let bits = vector::to_lane(lanes, self.scalar(lanes.is_float(), src));
+                self.vregisters[dst] = vector::with_lane(self.vregisters[dst], lanes, lane, bits);
+            },
+            Op::VecExtract { lanes, dst, src, lane } => {
+                let value = vector::from_lane(lanes, vector::lane(self.vregisters[src], lanes, lane));
+                self.set_scalar(lanes.is_float(), dst, value);
+            },
+            Op::VecShuffle { lanes, dst, src, pattern } => {
+                self.vregisters[dst] = vector::shuffle(lanes, self.vregisters[src], pattern);
+            },
+            Op::VecSwizzle { lanes, dst, src, ext } => {
+                self.vregisters[dst] = vector::swizzle(lanes, self.vregisters[src], self.vregisters[ext]);
+            },
+            Op::VecReduce { op, lanes, dst, src } => {
+                let value = vector::reduce(op, lanes, self.vregisters[src], mode, &mut self.fflags);
+                self.set_scalar(lanes.is_float(), dst, value);
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // an integer register, or the bits of an FP register
+    fn scalar(&self, float: bool, index: usize) -> u64 {
+        if float { self.fregisters[index].to_bits() } else { self.registers[index] }
+    }
+
+    fn set_scalar(&mut self, float: bool, index: usize, value: u64) {
+        if float {
+            self.fregisters[index] = f64::from_bits(value);
+        } else {
+            self.set(index, value);
+        }
+    }
+
+    fn step_interrupt(&mut self, op: Op, next_pc: &mut usize) -> Result<(), Exception> {
+        let interrupts = &mut self.interrupts;
+        match op {
+            Op::EnableInterrupts => interrupts.enabled = true,
+            Op::DisableInterrupts => interrupts.enabled = false,
+            Op::InterruptMask { set, src } => {
+                let bits = self.registers[src] as u8;
+                interrupts.mask = if set { interrupts.mask | bits } else { interrupts.mask & !bits };
+            },
+            Op::InterruptMaskImm { set, bits } => {
+                interrupts.mask = if set { interrupts.mask | bits } else { interrupts.mask & !bits };
+            },
+            Op::AcknowledgeInterrupt { src } => interrupts.acknowledge(check_line(self.registers[src])?),
+            Op::AcknowledgeInterruptImm { line } => interrupts.acknowledge(line),
+            Op::RaiseInterrupt { src } => interrupts.raise(check_line(self.registers[src])?),
+            Op::RaiseInterruptImm { line } => interrupts.raise(line),
+            Op::PendingInterrupts { dst } => {
+                let pending = interrupts.pending as u64;
+                self.set(dst, pending);
+            },
+            Op::InterruptVector { src, target } => {
+                let line = check_line(self.registers[src])?;
+                interrupts.vectors[line as usize] = Some(target);
+            },
+            Op::InterruptReturn => (*next_pc, self.privilege) = interrupts.leave()?,
+            Op::WaitForInterrupt => self.state = MachineState::Waiting,
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // executes one instruction, or enters an interrupt handler first when one is due. an
+    // exception the program does not handle is returned with the pc at the instruction.
+    #[inline(always)]
+    pub fn step(&mut self) -> Result<(), Exception> {
+
+        if let Some(line) = self.interrupts.deliverable() {
+            self.pc = self.interrupts.enter(line, self.pc, self.privilege)?;
+            self.privilege = Privilege::Supervisor;
+            self.state = MachineState::Running;
+            self.reservation = None;
+        }
+        if self.state != MachineState::Running {
+            return Ok(());
+        }
+
+        let pc = self.pc;
+        let Some(op) = self.ops.get(pc).copied() else {
+            self.state = MachineState::Halted;
+            return Ok(());
+        };
+
+        self.counts[pc] += 1;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.fetch(pc);
+        }
+
+        match self.execute(pc, op) {
+            Ok(next_pc) => {
+                self.steps += 1;
+                self.pc = next_pc;
+                Ok(())
+            },
+            Err(exception) => self.take_exception(pc, exception),
+        }
+    }
+
+    // the effects of one instruction, returns the pc of the next one
+    #[inline(always)]
+    fn execute(&mut self, pc: usize, op: Op) -> Result<usize, Exception> {
+
+        if self.privilege == Privilege::User {
+            self.check_user(pc, op)?;
+        }
+
+        let mut next_pc = pc + 1;
+
+        match op {
+            Op::Nop => {},
+            Op::Breakpoint => self.state = MachineState::Break,
+            Op::CoreId { dst } => self.set(dst, self.core as u64),
+            Op::CoreCount { dst } => self.set(dst, self.core_count() as u64),
+            Op::Fence => self.drain_stores(),
+            Op::Halt => {
+                self.state = MachineState::Halted;
+                next_pc = pc;
+            },
+            Op::AluReg { op, dst, src, ext } => {
+                let value = op.apply(self.registers[src], self.registers[ext]).map_err(division)?;
+                self.set(dst, value);
+            },
+            Op::AluImm { op, dst, src, imm } => {
+                let value = op.apply(self.registers[src], imm).map_err(division)?;
+                self.set(dst, value);
+            },
+            Op::Load { size, dst, base, offset } => {
+                let device = self.registers[base].wrapping_add(offset);
+                check_alignment(device, size)?;
+                let value = if is_mmio(device) {
+                    self.protect(device, size, PERMISSION_READ)?;
+                    self.devices.read(device, size, self.steps).map_err(|message| device_exception(device, size, message))?
+                } else {
+                    let address = self.address(base, offset, size, PERMISSION_READ)?;
+                    self.read(pc, address, size)
+                };
+                self.set(dst, value);
+            },
+            Op::Store { size, value, base, offset } => {
+                let device = self.registers[base].wrapping_add(offset);
+                check_alignment(device, size)?;
+                if is_mmio(device) {
+                    self.protect(device, size, PERMISSION_WRITE)?;
+                    self.devices.write(device, size, self.registers[value]).map_err(|message| device_exception(device, size, message))?;
+                } else {
+                    let address = self.address(base, offset, size, PERMISSION_WRITE)?;
+                    self.write(pc, address, size, self.registers[value]);
+                }
+            },
+            Op::Branch { condition, src, ext, target } => {
+                let taken = condition.holds(self.registers[src], self.registers[ext]);
+                if let Some(branch) = self.branch.as_mut() {
+                    branch.observe(pc, taken);
+                }
+                if taken {
+                    next_pc = target;
+                }
+            },
+            Op::Jump { target } => next_pc = target,
+            Op::Call { target } => {
+                self.set(REG_LINK as usize, (pc + 1) as u64);
+                next_pc = target;
+            },
+            Op::Ret => {
+                let target = self.registers[REG_LINK as usize];
+                if target > self.ops.len() as u64 {
+                    return Err(format!("return address {} out of range", target).into());
+                }
+                next_pc = target as usize;
+            },
+            Op::Syscall | Op::SyscallImm { .. } => {
+                let number = match op {
+                    Op::SyscallImm { number } => number,
+                    _ => self.registers[SYSCALL_RESULT as usize],
+                };
+                if self.privilege == Privilege::User && self.exceptions.vectors[ExceptionKind::Syscall.cause() as usize].is_some() {
+                    return Err(Exception::new(ExceptionKind::Syscall, format!("syscall {}", number)));
+                }
+                self.syscall(pc, number);
+                if matches!(self.state, MachineState::Halted | MachineState::Blocked) {
+                    next_pc = pc;
+                }
+            },
+            Op::Illegal => {
+                let message = decode(&self.code[pc], self.code.len()).err().unwrap_or_default();
+                return Err(Exception::new(ExceptionKind::IllegalInstruction, message));
+            },
+            op if op.is_vector() => self.step_vector(pc, op)?,
+            op if op.is_atomic() => self.step_atomic(pc, op)?,
+            op if op.is_interrupt() => self.step_interrupt(op, &mut next_pc)?,
+            op if op.is_exception() => self.step_exception(op, &mut next_pc)?,
+            op if op.is_protection() => self.step_protection(op, &mut next_pc),
+            op if op.is_paging() => self.step_paging(op)?,
+            op => self.step_float(pc, op)?,
+        }
+
+        Ok(next_pc)
+    }
+
+    // executes up to 'steps' more instructions. returns the status the machine stopped
+    // with, or None when it is still running.
+    pub fn advance(&mut self, steps: u64) -> Option<RunStatus> {
+        if self.cores.is_empty() {
+            self.advance_core(steps)
+        } else {
+            self.advance_cores(steps)
+        }
+    }
+
+    // advance() of the executing core alone
+    pub(crate) fn advance_core(&mut self, steps: u64) -> Option<RunStatus> {
+        let limit = self.steps.saturating_add(steps);
+        loop {
+            let stopped = match self.state {
+                MachineState::Halted => Some(RunStatus::Halted),
+                MachineState::Break => Some(RunStatus::Breakpoint),
+                MachineState::Waiting if self.interrupts.deliverable().is_none() => Some(RunStatus::Waiting),
+                MachineState::Blocked if self.devices.input_ready() => {
+                    self.state = MachineState::Running;
+                    None
+                },
+                MachineState::Blocked if self.interrupts.deliverable().is_none() => Some(RunStatus::WaitingForInput),
+                _ => None,
+            };
+            if let Some(status) = stopped {
+                // a core that stops lets the others see its stores
+                self.drain_stores();
+                return Some(status);
+            }
+            if self.steps >= limit {
+                return None;
+            }
+            if let Err(exception) = self.step() {
+                self.fail(exception);
+                return Some(RunStatus::Faulted);
+            }
+            if !self.store_buffer.is_empty() {
+                self.age_stores();
+            }
+            if self.yielded {
+                self.yielded = false;
+                return None;
+            }
+        }
+    }
+
+    // runs until the machine halts, faults, waits for an interrupt or uses up its budget
+    pub fn run(&mut self, budget: u64) -> RunStatus {
+        loop {
+            match self.advance(budget.saturating_sub(self.steps)) {
+                Some(status) => return status,
+                // a yield, there is nobody to yield to
+                None if self.steps < budget => continue,
+                None => {
+                    self.exhaust(budget);
+                    return RunStatus::BudgetExhausted;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,494 @@
+use crate::models::float::{RoundingMode};
+
+// floating point support. the FP register file holds f64 values, the single precision (.s)
+// opcodes read their operands as f32 and round their result to f32 before storing it back.
+//
+//  fadd, fsub, fmul, fdiv, fmin, fmax   fd = fs <op> operand    (operand: imdval or fx)
+//  fsqrt, fneg, fabs                    fd = <op> fs
+//  fmadd                                fd = fs * fx + fd       (fused, rounded once)
+//  fli                                  fd = imdval
+//  fcvt.s.l / fcvt.d.l                  fd = rs                 (integer to float)
+//  fcvt.l.s / fcvt.l.d                  rd = fs                 (float to integer, saturating)
+//  fcvt.s.d / fcvt.d.s                  fd = fs                 (change precision)
+//  fmv.x.s / fmv.x.d                    rd = bits of fs
+//  fmv.s.x / fmv.d.x                    fd = float with the bits of rs
+//  flw / fld                            fd = memory[rs + imdval]   (32 / 64 bit)
+//  fsw / fsd                            memory[rs + imdval] = fx
+//  fcmp.s / fcmp.d                      compare bits of fflags = compare(fs, fx)
+//  frflags / fsflags                    rd = fflags / fflags = imdval or rs
+//  frrm / fsrm                          rd = rounding mode / rounding mode = imdval or rs
+//
+// every opcode exists with a .s and a .d suffix except the ones spelled out above. every
+// rounding opcode follows the rounding mode, fmadd included.
+//
+// fflags holds the result of the last fcmp in its low bits (FLAG_LESS .. FLAG_UNORDERED) and
+// the IEEE 754 exceptions above them, accrued: an opcode only sets them, fsflags clears
+// them. underflow is detected after rounding (a tiny and inexact result). fneg, fabs and
+// the moves never raise anything.
+// float literals in imdval use rust syntax ("1.5", "-2e-3", "inf", "NaN"), hex values
+// ("0x10") are read as integers.
+
+// fflags, written by fcmp
+pub const FLAG_LESS: u64 = 1;
+pub const FLAG_EQUAL: u64 = 2;
+pub const FLAG_GREATER: u64 = 4;
+pub const FLAG_UNORDERED: u64 = 8;
+pub const COMPARE_FLAGS: u64 = FLAG_LESS | FLAG_EQUAL | FLAG_GREATER | FLAG_UNORDERED;
+// the exceptions, accrued in fflags
+pub const FLAG_INVALID: u64 = 16;
+pub const FLAG_DIVIDE_BY_ZERO: u64 = 32;
+pub const FLAG_OVERFLOW: u64 = 64;
+pub const FLAG_UNDERFLOW: u64 = 128;
+pub const FLAG_INEXACT: u64 = 256;
+
+// "0x" / "" => no immediate, decimal and special literals as f64, "0x.." as an integer value
+pub fn parse_float_imdval(imdval: &str) -> Result<Option<f64>, String> {
+    let text = imdval.trim();
+    if text.is_empty() || text == "0x" {
+        return Ok(None);
+    }
+    if text.trim_start_matches('-').starts_with("0x") {
+        return crate::services::executor::parse_imdval(text).map(|value| value.map(|value| value as f64));
+    }
+    match text.parse::<f64>() {
+        Ok(value) => Ok(Some(value)),
+        Err(_) => Err(format!("invalid float imdval '{}'", imdval)),
+    }
+}
+
+// rust arithmetic rounds to nearest. for the directed modes the correctly rounded result is
+// at most one ulp away: 'error' is (exact result - rounded result), or has at least its sign.
+fn direct_f64(value: f64, error: f64, mode: RoundingMode) -> f64 {
+    if error == 0.0 || error.is_nan() || value.is_nan() {
+        return value;
+    }
+    match mode {
+        RoundingMode::NearestEven => value,
+        RoundingMode::Down if error < 0.0 => value.next_down(),
+        RoundingMode::Up if error > 0.0 => value.next_up(),
+        RoundingMode::TowardZero if value > 0.0 && error < 0.0 => value.next_down(),
+        RoundingMode::TowardZero if value < 0.0 && error > 0.0 => value.next_up(),
+        _ => value,
+    }
+}
+
+fn direct_f32(value: f32, error: f64, mode: RoundingMode) -> f32 {
+    if error == 0.0 || error.is_nan() || value.is_nan() {
+        return value;
+    }
+    match mode {
+        RoundingMode::NearestEven => value,
+        RoundingMode::Down if error < 0.0 => value.next_down(),
+        RoundingMode::Up if error > 0.0 => value.next_up(),
+        RoundingMode::TowardZero if value > 0.0 && error < 0.0 => value.next_down(),
+        RoundingMode::TowardZero if value < 0.0 && error > 0.0 => value.next_up(),
+        _ => value,
+    }
+}
+
+// a rounded f64 result plus the sign carrying error term described above
+#[derive(Clone, Copy)]
+struct Exact {
+    value: f64,
+    error: f64,
+}
+
+// an overflow to infinity means the exact result is huge but finite, the error points back to zero
+fn overflow(value: f64, operands_finite: bool) -> f64 {
+    if value.is_infinite() && operands_finite { -value.signum() } else { 0.0 }
+}
+
+fn add(a: f64, b: f64) -> Exact {
+    let value = a + b;
+    if !value.is_finite() {
+        return Exact { value, error: overflow(value, a.is_finite() && b.is_finite()) };
+    }
+    // two-sum
+    let bb = value - a;
+    let error = (a - (value - bb)) + (b - bb);
+    Exact { value, error }
+}
+
+fn mul(a: f64, b: f64) -> Exact {
+    let value = a * b;
+    if !value.is_finite() {
+        return Exact { value, error: overflow(value, a.is_finite() && b.is_finite()) };
+    }
+    Exact { value, error: a.mul_add(b, -value) }
+}
+
+fn div(a: f64, b: f64) -> Exact {
+    let value = a / b;
+    if !value.is_finite() {
+        return Exact { value, error: overflow(value, a.is_finite() && b != 0.0) };
+    }
+    // remainder a - value * b is exact, the quotient error has its sign times the sign of b
+    let remainder = (-value).mul_add(b, a);
+    Exact { value, error: remainder * b.signum() }
+}
+
+fn sqrt(a: f64) -> Exact {
+    let value = a.sqrt();
+    if !value.is_finite() || value == 0.0 {
+        return Exact { value, error: 0.0 };
+    }
+    Exact { value, error: (-value).mul_add(value, a) }
+}
+
+// f32 arithmetic is carried out in f64 and rounded once more. the difference between the two
+// is exact, when it is zero the f64 error term decides the direction.
+fn narrow(exact: Exact) -> Exact {
+    let single = exact.value as f32;
+    let difference = exact.value - single as f64;
+    let error = if single.is_infinite() && exact.value.is_finite() {
+        // overflowed while narrowing
+        -(single as f64).signum()
+    } else if difference != 0.0 {
+        difference
+    } else {
+        exact.error
+    };
+    Exact { value: single as f64, error }
+}
+
+// the exceptions of a rounded result. 'exact' is rounded to nearest, so it overflowed when
+// it is infinite but not exact.
+fn raise(exact: Exact, result: f64, tiny: f64, flags: &mut u64) {
+    if exact.error == 0.0 || exact.error.is_nan() {
+        return;
+    }
+    *flags |= FLAG_INEXACT;
+    if exact.value.is_infinite() {
+        *flags |= FLAG_OVERFLOW;
+    } else if result.abs() < tiny {
+        *flags |= FLAG_UNDERFLOW;
+    }
+}
+
+// a NaN out of operands that are not NaN
+fn invalid(result: f64, operands: &[f64], flags: &mut u64) {
+    if result.is_nan() && !operands.iter().any(|operand| operand.is_nan()) {
+        *flags |= FLAG_INVALID;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Min,
+    Max,
+}
+
+impl FloatOp {
+    pub fn from_opcode(opcode: &str) -> Option<FloatOp> {
+        match opcode.split('.').next().unwrap_or("") {
+            "fadd" => Some(FloatOp::Add),
+            "fsub" => Some(FloatOp::Sub),
+            "fmul" => Some(FloatOp::Mul),
+            "fdiv" => Some(FloatOp::Div),
+            "fmin" => Some(FloatOp::Min),
+            "fmax" => Some(FloatOp::Max),
+            _ => None,
+        }
+    }
+}
+
+// operands of single precision opcodes are read as f32
+fn operand(value: f64, double: bool) -> f64 {
+    if double { value } else { value as f32 as f64 }
+}
+
+// next_down(inf) is f64::MAX, so overflows are handled by the error term as well
+fn finish(exact: Exact, double: bool, mode: RoundingMode, flags: &mut u64) -> f64 {
+    if double {
+        let result = direct_f64(exact.value, exact.error, mode);
+        raise(exact, result, f64::MIN_POSITIVE, flags);
+        result
+    } else {
+        let single = narrow(exact);
+        let result = direct_f32(single.value as f32, single.error, mode) as f64;
+        raise(single, result, f32::MIN_POSITIVE as f64, flags);
+        result
+    }
+}
+
+pub fn arithmetic(op: FloatOp, lhs: f64, rhs: f64, double: bool, mode: RoundingMode, flags: &mut u64) -> f64 {
+    let (lhs, rhs) = (operand(lhs, double), operand(rhs, double));
+    if op == FloatOp::Div && rhs == 0.0 && lhs.is_finite() && lhs != 0.0 {
+        *flags |= FLAG_DIVIDE_BY_ZERO;
+    }
+    let exact = match op {
+        FloatOp::Add => add(lhs, rhs),
+        FloatOp::Sub => add(lhs, -rhs),
+        FloatOp::Mul => mul(lhs, rhs),
+        FloatOp::Div => div(lhs, rhs),
+        FloatOp::Min => Exact { value: lhs.min(rhs), error: 0.0 },
+        FloatOp::Max => Exact { value: lhs.max(rhs), error: 0.0 },
+    };
+    invalid(exact.value, &[lhs, rhs], flags);
+    finish(exact, double, mode, flags)
+}
+
+pub fn square_root(value: f64, double: bool, mode: RoundingMode, flags: &mut u64) -> f64 {
+    let value = operand(value, double);
+    let exact = sqrt(value);
+    invalid(exact.value, &[value], flags);
+    finish(exact, double, mode, flags)
+}
+
+// the error term of a sum of doubles, computed exactly (Shewchuk's expansion sum). only its
+// sign and whether it is zero matter to the rounding.
+fn exact_sum(values: &[f64]) -> f64 {
+    let mut expansion: Vec<f64> = Vec::with_capacity(values.len());
+    for &value in values {
+        let mut carry = value;
+        for component in expansion.iter_mut() {
+            let sum = carry + *component;
+            let back = sum - carry;
+            let error = (carry - (sum - back)) + (*component - back);
+            *component = error;
+            carry = sum;
+        }
+        expansion.push(carry);
+    }
+    // the components grow in magnitude, the last one that is not zero has the sign of the sum
+    expansion.iter().rev().find(|component| **component != 0.0).copied().unwrap_or(0.0)
+}
+
+// fd = a * b + c, rounded once in the current mode
+pub fn multiply_add(a: f64, b: f64, c: f64, double: bool, mode: RoundingMode, flags: &mut u64) -> f64 {
+    let (a, b, c) = (operand(a, double), operand(b, double), operand(c, double));
+    let exact = if double {
+        let value = a.mul_add(b, c);
+        if !value.is_finite() {
+            Exact { value, error: overflow(value, a.is_finite() && b.is_finite() && c.is_finite()) }
+        } else {
+            // a * b is product + low exactly, the error is what is left of the whole sum
+            let product = a * b;
+            let low = a.mul_add(b, -product);
+            Exact { value, error: exact_sum(&[product, low, c, -value]) }
+        }
+    } else {
+        // the product of two f32 is exact in f64
+        add(a * b, c)
+    };
+    invalid(exact.value, &[a, b, c], flags);
+    finish(exact, double, mode, flags)
+}
+
+// fneg and fabs only change the sign, .s narrows like every single precision opcode
+pub fn negate(value: f64, double: bool) -> f64 {
+    -operand(value, double)
+}
+
+pub fn absolute(value: f64, double: bool) -> f64 {
+    operand(value, double).abs()
+}
+
+// the error of the conversion is exact in i128
+pub fn from_integer(value: i64, double: bool, mode: RoundingMode, flags: &mut u64) -> f64 {
+    let rounded = if double { value as f64 } else { value as f32 as f64 };
+    let exact = Exact { value: rounded, error: (value as i128 - rounded as i128) as f64 };
+    finish(exact, double, mode, flags)
+}
+
+// NaN converts to 0, out of range values saturate, both are invalid
+pub fn to_integer(value: f64, double: bool, mode: RoundingMode, flags: &mut u64) -> i64 {
+    let value = operand(value, double);
+    let rounded = match mode {
+        RoundingMode::NearestEven => value.round_ties_even(),
+        RoundingMode::TowardZero => value.trunc(),
+        RoundingMode::Down => value.floor(),
+        RoundingMode::Up => value.ceil(),
+    };
+    // i64::MAX as f64 is 2^63, which is out of range already
+    if rounded.is_nan() || rounded < i64::MIN as f64 || rounded >= i64::MAX as f64 {
+        *flags |= FLAG_INVALID;
+    } else if rounded != value {
+        *flags |= FLAG_INEXACT;
+    }
+    rounded as i64
+}
+
+pub fn to_single(value: f64, mode: RoundingMode, flags: &mut u64) -> f64 {
+    finish(Exact { value, error: 0.0 }, false, mode, flags)
+}
+
+// the compare bits of fflags
+pub fn compare(lhs: f64, rhs: f64, double: bool) -> u64 {
+    let (lhs, rhs) = (operand(lhs, double), operand(rhs, double));
+    match lhs.partial_cmp(&rhs) {
+        Some(std::cmp::Ordering::Less) => FLAG_LESS,
+        Some(std::cmp::Ordering::Equal) => FLAG_EQUAL,
+        Some(std::cmp::Ordering::Greater) => FLAG_GREATER,
+        None => FLAG_UNORDERED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::run::{RunStatus};
+    use crate::services::assembler::{assemble};
+    use crate::services::executor::{Machine};
+
+    const MODES: [RoundingMode; 4] = [RoundingMode::NearestEven, RoundingMode::TowardZero, RoundingMode::Down, RoundingMode::Up];
+
+    // (result, flags) in every mode, in the order of MODES
+    fn rounded(operation: impl Fn(RoundingMode, &mut u64) -> f64) -> Vec<(f64, u64)> {
+        MODES.iter().map(|mode| {
+            let mut flags = 0;
+            let value = operation(*mode, &mut flags);
+            (value, flags)
+        }).collect()
+    }
+
+    fn values(results: &[(f64, u64)]) -> Vec<f64> {
+        results.iter().map(|(value, _)| *value).collect()
+    }
+
+    #[test]
+    fn arithmetic_follows_the_rounding_mode() {
+        let tiny = 2f64.powi(-60);
+        let up = 1f64.next_up();
+        let results = rounded(|mode, flags| arithmetic(FloatOp::Add, 1.0, tiny, true, mode, flags));
+        assert_eq!(values(&results), vec![1.0, 1.0, 1.0, up]);
+        assert!(results.iter().all(|(_, flags)| *flags == FLAG_INEXACT));
+
+        let results = rounded(|mode, flags| arithmetic(FloatOp::Sub, -1.0, tiny, true, mode, flags));
+        assert_eq!(values(&results), vec![-1.0, -1.0, -up, -1.0]);
+
+        // 1/3 in single precision, nearest rounds up
+        let results = rounded(|mode, flags| arithmetic(FloatOp::Div, 1.0, 3.0, false, mode, flags));
+        let third = 1.0f32 / 3.0;
+        let below = third.next_down() as f64;
+        assert_eq!(values(&results), vec![third as f64, below, below, third as f64]);
+
+        let results = rounded(|mode, flags| square_root(2.0, true, mode, flags));
+        assert_eq!(results[0].0, 2f64.sqrt());
+        assert_eq!(results[3].0, results[2].0.next_up());
+
+        // exact results raise nothing
+        assert!(rounded(|mode, flags| arithmetic(FloatOp::Mul, 1.5, -2.5, false, mode, flags)).iter().all(|result| *result == (-3.75, 0)));
+    }
+
+    #[test]
+    fn overflow_rounds_to_infinity_or_the_largest_value() {
+        let results = rounded(|mode, flags| arithmetic(FloatOp::Mul, f64::MAX, 2.0, true, mode, flags));
+        assert_eq!(values(&results), vec![f64::INFINITY, f64::MAX, f64::MAX, f64::INFINITY]);
+        assert!(results.iter().all(|(_, flags)| *flags == FLAG_OVERFLOW | FLAG_INEXACT));
+
+        let results = rounded(|mode, flags| arithmetic(FloatOp::Mul, -(f32::MAX as f64), 2.0, false, mode, flags));
+        assert_eq!(values(&results), vec![f64::NEG_INFINITY, -f32::MAX as f64, f64::NEG_INFINITY, -f32::MAX as f64]);
+        assert!(results.iter().all(|(_, flags)| *flags == FLAG_OVERFLOW | FLAG_INEXACT));
+
+        let results = rounded(|mode, flags| to_single(1e40, mode, flags));
+        assert_eq!(values(&results), vec![f64::INFINITY, f32::MAX as f64, f32::MAX as f64, f64::INFINITY]);
+
+        // an infinite operand is not an overflow
+        let mut flags = 0;
+        assert_eq!(arithmetic(FloatOp::Add, f64::INFINITY, 1.0, true, RoundingMode::NearestEven, &mut flags), f64::INFINITY);
+        assert_eq!(flags, 0);
+    }
+
+    #[test]
+    fn invalid_divide_by_zero_and_underflow() {
+        let flags_of = |operation: &dyn Fn(&mut u64) -> f64| {
+            let mut flags = 0;
+            operation(&mut flags);
+            flags
+        };
+        let mode = RoundingMode::NearestEven;
+        assert_eq!(flags_of(&|flags| arithmetic(FloatOp::Div, 1.0, 0.0, true, mode, flags)), FLAG_DIVIDE_BY_ZERO);
+        assert_eq!(flags_of(&|flags| arithmetic(FloatOp::Div, 0.0, 0.0, true, mode, flags)), FLAG_INVALID);
+        assert_eq!(flags_of(&|flags| arithmetic(FloatOp::Sub, f64::INFINITY, f64::INFINITY, false, mode, flags)), FLAG_INVALID);
+        assert_eq!(flags_of(&|flags| square_root(-1.0, true, mode, flags)), FLAG_INVALID);
+        assert_eq!(flags_of(&|flags| multiply_add(f64::INFINITY, 0.0, 1.0, true, mode, flags)), FLAG_INVALID);
+        // a NaN operand is passed on quietly
+        assert_eq!(flags_of(&|flags| arithmetic(FloatOp::Add, f64::NAN, 1.0, true, mode, flags)), 0);
+
+        assert_eq!(flags_of(&|flags| arithmetic(FloatOp::Div, f64::MIN_POSITIVE, 3.0, true, mode, flags)), FLAG_UNDERFLOW | FLAG_INEXACT);
+        // tiny but exact
+        assert_eq!(flags_of(&|flags| arithmetic(FloatOp::Div, f64::MIN_POSITIVE, 2.0, true, mode, flags)), 0);
+        assert_eq!(flags_of(&|flags| arithmetic(FloatOp::Mul, f32::MIN_POSITIVE as f64, 0.3, false, mode, flags)), FLAG_UNDERFLOW | FLAG_INEXACT);
+    }
+
+    #[test]
+    fn multiply_add_is_rounded_once_in_the_mode() {
+        // (1 + 2^-52)^2 - 1 = 2^-51 + 2^-104, which needs 54 bits
+        let a = 1f64.next_up();
+        let low = 2f64.powi(-51);
+        let results = rounded(|mode, flags| multiply_add(a, a, -1.0, true, mode, flags));
+        assert_eq!(values(&results), vec![low, low, low, low.next_up()]);
+        assert!(results.iter().all(|(_, flags)| *flags == FLAG_INEXACT));
+
+        // exact after the cancellation
+        let a = 1.0 + 2f64.powi(-30);
+        let results = rounded(|mode, flags| multiply_add(a, a, -(1.0 + 2f64.powi(-29)), true, mode, flags));
+        assert!(results.iter().all(|result| *result == (2f64.powi(-60), 0)));
+
+        // (1 + 2^-12)^2 = 1 + 2^-11 + 2^-24, below the last bit of an f32
+        let a = 1.0 + 2f64.powi(-12);
+        let sum = 1.0f32 + 2f32.powi(-11);
+        let results = rounded(|mode, flags| multiply_add(a, a, 0.0, false, mode, flags));
+        assert_eq!(values(&results), vec![sum as f64, sum as f64, sum as f64, sum.next_up() as f64]);
+
+        let results = rounded(|mode, flags| multiply_add(-f64::MAX, 2.0, 0.0, true, mode, flags));
+        assert_eq!(values(&results), vec![f64::NEG_INFINITY, -f64::MAX, f64::NEG_INFINITY, -f64::MAX]);
+    }
+
+    #[test]
+    fn conversions_follow_the_rounding_mode() {
+        let big = (1i64 << 53) + 1;
+        let results = rounded(|mode, flags| from_integer(big, true, mode, flags));
+        let even = (1i64 << 53) as f64;
+        assert_eq!(values(&results), vec![even, even, even, even + 2.0]);
+        assert!(results.iter().all(|(_, flags)| *flags == FLAG_INEXACT));
+        assert_eq!(rounded(|mode, flags| from_integer(-7, false, mode, flags))[1], (-7.0, 0));
+
+        for (value, expected) in [(2.5, [2, 2, 2, 3]), (-2.5, [-2, -2, -3, -2]), (3.7, [4, 3, 3, 4])] {
+            for (mode, expected) in MODES.iter().zip(expected) {
+                let mut flags = 0;
+                assert_eq!(to_integer(value, true, *mode, &mut flags), expected, "{} {:?}", value, mode);
+                assert_eq!(flags, FLAG_INEXACT);
+            }
+        }
+        for (value, expected) in [(f64::NAN, 0), (1e30, i64::MAX), (-1e30, i64::MIN), (9.3e18, i64::MAX)] {
+            let mut flags = 0;
+            assert_eq!(to_integer(value, true, RoundingMode::NearestEven, &mut flags), expected);
+            assert_eq!(flags, FLAG_INVALID);
+        }
+        let mut flags = 0;
+        assert_eq!(to_integer(-3.0, false, RoundingMode::Up, &mut flags), -3);
+        assert_eq!(flags, 0);
+    }
+
+    #[test]
+    fn single_precision_sign_opcodes_narrow() {
+        let value = 1.0 + 2f64.powi(-40);
+        assert_eq!(negate(value, false), -1.0);
+        assert_eq!(absolute(-value, false), 1.0);
+        assert_eq!(negate(value, true), -value);
+    }
+
+    #[test]
+    fn fcmp_keeps_the_accrued_exceptions() {
+        let program = assemble("
+            fli.d f1, 1
+            fli.d f2, 0
+            fdiv.d f3, f1, f2
+            fcmp.d f1, f2
+            frflags r1
+            fsflags 0
+            fcmp.d f2, f1
+            frflags r2
+            halt", &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions;
+        let mut machine = Machine::new(program);
+        assert_eq!(machine.run(100), RunStatus::Halted);
+        assert_eq!(machine.registers[1], FLAG_DIVIDE_BY_ZERO | FLAG_GREATER);
+        assert_eq!(machine.registers[2], FLAG_LESS);
+    }
+}
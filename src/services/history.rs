@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::models::instruction::{Instruction};
+use crate::models::history::{Change, DiffEntry, HistoryReport, ProgramDiff, VersionOrigin, VersionSummary};
+
+// every program that becomes the code_segment is kept as a version, numbered from 1 and
+// never reused. the oldest versions are dropped beyond MAX_VERSIONS.
+
+pub const MAX_VERSIONS: usize = 50;
+// characters of the session key kept with a version, enough to tell sessions apart
+pub const SESSION_PREFIX: usize = 8;
+// size of the lcs table of a diff, 16 MiB
+pub const MAX_DIFF_CELLS: usize = 4 * 1024 * 1024;
+
+pub struct ProgramVersion {
+    pub summary: VersionSummary,
+    pub instructions: Vec<Instruction>,
+}
+
+#[derive(Default)]
+pub struct ProgramHistory {
+    versions: VecDeque<ProgramVersion>,
+    // number of the last version recorded
+    last: u64,
+}
+
+// the part of 'key' a version keeps, only when it is the session key of the server
+pub fn session_of(key: Option<&str>, master_key: &str) -> Option<String> {
+    key.filter(|key| *key == master_key && master_key != "0")
+        .map(|key| key.chars().take(SESSION_PREFIX).collect())
+}
+
+impl ProgramHistory {
+
+    pub fn new() -> ProgramHistory {
+        ProgramHistory::default()
+    }
+
+    // returns the number of the new version
+    pub fn record(&mut self, instructions: &[Instruction], origin: VersionOrigin, session: Option<String>) -> u64 {
+        self.last += 1;
+        let version = self.last;
+        let loaded_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        self.versions.push_back(ProgramVersion {
+            summary: VersionSummary { version, loaded_at, session, origin, length: instructions.len() },
+            instructions: instructions.to_vec(),
+        });
+        if self.versions.len() > MAX_VERSIONS {
+            self.versions.pop_front();
+        }
+        version
+    }
+
+    pub fn get(&self, version: u64) -> Result<&ProgramVersion, String> {
+        self.versions.iter()
+            .find(|kept| kept.summary.version == version)
+            .ok_or_else(|| format!("version {} is not in the history", version))
+    }
+
+    pub fn current(&self) -> Option<u64> {
+        self.versions.back().map(|kept| kept.summary.version)
+    }
+
+    pub fn report(&self) -> HistoryReport {
+        HistoryReport {
+            current: self.current(),
+            versions: self.versions.iter().map(|kept| kept.summary.clone()).collect(),
+        }
+    }
+
+    // records version 'version' again as the newest one, returns the number of the new version
+    // and its instructions
+    pub fn rollback(&mut self, version: u64, session: Option<String>) -> Result<(u64, Vec<Instruction>), String> {
+        let instructions = self.get(version)?.instructions.clone();
+        let current = self.record(&instructions, VersionOrigin::Rollback(version), session);
+        Ok((current, instructions))
+    }
+
+    pub fn diff(&self, from: Option<u64>, to: Option<u64>) -> Result<ProgramDiff, String> {
+        let current = self.current().ok_or("no program has been loaded yet")?;
+        let to = to.unwrap_or(current);
+        let from = match from {
+            Some(from) => from,
+            None => self.versions.iter().rev()
+                .map(|kept| kept.summary.version)
+                .find(|version| *version < to)
+                .ok_or_else(|| format!("no version before {}", to))?,
+        };
+        Ok(diff(from, &self.get(from)?.instructions, to, &self.get(to)?.instructions))
+    }
+}
+
+fn same(a: &Instruction, b: &Instruction) -> bool {
+    a.opcode == b.opcode && a.imdval == b.imdval && a.regsrc == b.regsrc && a.regext == b.regext && a.regdst == b.regdst
+}
+
+// instruction level diff along a longest common subsequence. the instructions both versions
+// start and end with are matched first, the lcs table only covers the part in between and
+// is not built beyond MAX_DIFF_CELLS: a larger changed part is reported as deleted and
+// inserted as a whole, and the diff is marked as not minimal.
+fn diff(from: u64, old: &[Instruction], to: u64, new: &[Instruction]) -> ProgramDiff {
+
+    let prefix = old.iter().zip(new).take_while(|(a, b)| same(a, b)).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| same(a, b)).count();
+    let old_changed = &old[prefix..old.len() - suffix];
+    let new_changed = &new[prefix..new.len() - suffix];
+
+    let entry = |change: Change, from_index: Option<usize>, to_index: Option<usize>, instruction: &Instruction| DiffEntry {
+        change,
+        from_index,
+        to_index,
+        instruction: instruction.clone(),
+        text: instruction.to_string(),
+    };
+    let deleted = |i: usize| entry(Change::Deleted, Some(prefix + i), None, &old_changed[i]);
+    let inserted = |j: usize| entry(Change::Inserted, None, Some(prefix + j), &new_changed[j]);
+
+    let cells = (old_changed.len() + 1).saturating_mul(new_changed.len() + 1);
+    if cells > MAX_DIFF_CELLS {
+        return ProgramDiff {
+            from,
+            to,
+            unchanged: prefix + suffix,
+            minimal: false,
+            changes: (0..old_changed.len()).map(deleted).chain((0..new_changed.len()).map(inserted)).collect(),
+        };
+    }
+
+    let (old, new) = (old_changed, new_changed);
+    let width = new.len() + 1;
+    // common[i * width + j]: length of the lcs of old[i..] and new[j..]
+    let mut common = vec![0u32; cells];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i * width + j] = if same(&old[i], &new[j]) {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && same(&old[i], &new[j]) {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[(i + 1) * width + j] >= common[i * width + j + 1]) {
+            changes.push(deleted(i));
+            i += 1;
+        } else {
+            changes.push(inserted(j));
+            j += 1;
+        }
+    }
+
+    ProgramDiff {
+        from,
+        to,
+        unchanged: prefix + suffix + common[0] as usize,
+        minimal: true,
+        changes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(opcode: &str, imdval: &str) -> Instruction {
+        Instruction { opcode: opcode.to_string(), imdval: imdval.to_string(), regsrc: 0, regext: 0, regdst: 1 }
+    }
+
+    fn program(imdvals: &[u64]) -> Vec<Instruction> {
+        imdvals.iter().map(|imdval| instruction("add", &imdval.to_string())).collect()
+    }
+
+    // (change, from_index, to_index, imdval) of every change
+    fn changes(diff: &ProgramDiff) -> Vec<(Change, Option<usize>, Option<usize>, String)> {
+        diff.changes.iter()
+            .map(|entry| (entry.change, entry.from_index, entry.to_index, entry.instruction.imdval.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn diff_of_an_insertion_and_a_deletion() {
+        let inserted = diff(1, &program(&[1, 2, 3]), 2, &program(&[1, 2, 9, 3]));
+        assert_eq!(changes(&inserted), vec![(Change::Inserted, None, Some(2), "9".to_string())]);
+        assert_eq!((inserted.unchanged, inserted.minimal), (3, true));
+
+        let deleted = diff(1, &program(&[1, 2, 3, 4]), 2, &program(&[2, 3, 4]));
+        assert_eq!(changes(&deleted), vec![(Change::Deleted, Some(0), None, "1".to_string())]);
+        assert_eq!(deleted.unchanged, 3);
+
+        let same = diff(1, &program(&[1, 2]), 2, &program(&[1, 2]));
+        assert!(same.changes.is_empty());
+        assert_eq!(same.unchanged, 2);
+    }
+
+    #[test]
+    fn diff_of_a_replacement() {
+        let replaced = diff(1, &program(&[1, 2, 3, 4, 5]), 2, &program(&[1, 7, 3, 8, 5]));
+        assert_eq!(changes(&replaced), vec![
+            (Change::Deleted, Some(1), None, "2".to_string()),
+            (Change::Inserted, None, Some(1), "7".to_string()),
+            (Change::Deleted, Some(3), None, "4".to_string()),
+            (Change::Inserted, None, Some(3), "8".to_string()),
+        ]);
+        assert_eq!(replaced.unchanged, 3);
+
+        // a different register is a different instruction
+        let mut changed = program(&[1, 2]);
+        changed[1].regsrc = 4;
+        assert_eq!(diff(1, &program(&[1, 2]), 2, &changed).changes.len(), 2);
+    }
+
+    #[test]
+    fn large_changes_are_not_aligned() {
+        let side = 2 * 1024 + 1;
+        let old: Vec<u64> = (0..side as u64).collect();
+        let new: Vec<u64> = (0..side as u64).map(|value| value + 1).collect();
+        let mut old = program(&old);
+        let mut new = program(&new);
+        // the common start and end are still matched
+        old.insert(0, instruction("nop", ""));
+        new.insert(0, instruction("nop", ""));
+        old.push(instruction("halt", ""));
+        new.push(instruction("halt", ""));
+
+        let diff = diff(1, &old, 2, &new);
+        assert!(!diff.minimal);
+        assert_eq!(diff.unchanged, 2);
+        assert_eq!(diff.changes.len(), 2 * side);
+        assert_eq!(diff.changes[0].from_index, Some(1));
+        assert_eq!(diff.changes[side].to_index, Some(1));
+    }
+
+    #[test]
+    fn rollback_is_a_new_version() {
+        let mut history = ProgramHistory::new();
+        history.record(&program(&[1]), VersionOrigin::Load, None);
+        history.record(&program(&[1, 2]), VersionOrigin::Edit, Some("3f2a9c1e".to_string()));
+
+        let (current, instructions) = history.rollback(1, None).unwrap();
+        assert_eq!(current, 3);
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(history.current(), Some(3));
+        assert_eq!(history.get(3).unwrap().summary.origin, VersionOrigin::Rollback(1));
+
+        // the rollback itself can be compared, and undone
+        assert_eq!(changes(&history.diff(None, None).unwrap()), vec![(Change::Deleted, Some(1), None, "2".to_string())]);
+        assert!(history.diff(Some(1), Some(3)).unwrap().changes.is_empty());
+        assert_eq!(history.rollback(2, None).unwrap().0, 4);
+        assert!(history.rollback(9, None).is_err());
+    }
+}
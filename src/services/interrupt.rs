@@ -0,0 +1,109 @@
+use crate::models::interrupt::{InterruptReport};
+use crate::models::protection::{Privilege};
+
+// the interrupt controller has 8 lines, a lower line number means a higher priority.
+// lines 0..=4 are the timers driven by the periodic workers (10, 25, 50, 100 and 250ms),
+// the remaining lines are free for software (iraise) and devices.
+//
+//  ei / di         enable / disable interrupts globally (disabled at reset)
+//  ien / idis      mask |= operand / mask &= !operand   (operand: imdval or rs, bit n <=> line n)
+//  ivec            vector[rs] = imdval                  (handler instruction index)
+//  iack            clear the pending bit of line imdval / rs
+//  iraise          set the pending bit of line imdval / rs
+//  ipend           rd = pending lines
+//  iret            return from the current handler
+//  wfi             wait until an interrupt can be delivered
+//
+// an interrupt is delivered before the next instruction when interrupts are enabled, its
+// line is pending and unmasked, and its priority is higher than the one of every handler
+// in service. delivery saves the pc and jumps to the handler, nothing else is saved: a
+// handler that uses registers has to preserve them itself. handlers run in supervisor mode,
+// iret returns to the mode that was interrupted (services/protection.rs). pending bits are cleared by
+// iack only, a handler that returns without acknowledging its line is entered again.
+
+pub const INTERRUPT_LINES: usize = 8;
+
+// (worker period in ms, line)
+const TIMER_LINES: &[(u64, u8)] = &[(10, 0), (25, 1), (50, 2), (100, 3), (250, 4)];
+
+pub fn timer_line(period: u64) -> Option<u8> {
+    TIMER_LINES.iter()
+        .find(|(ms, _)| *ms == period)
+        .map(|(_, line)| *line)
+}
+
+pub fn check_line(line: u64) -> Result<u8, String> {
+    if line as usize >= INTERRUPT_LINES {
+        return Err(format!("invalid interrupt line {}", line));
+    }
+    Ok(line as u8)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InterruptController {
+    pub enabled: bool,
+    pub mask: u8,
+    pub pending: u8,
+    pub vectors: [Option<usize>; INTERRUPT_LINES],
+    // (line, return pc, interrupted privilege) of the handlers in service, innermost last
+    in_service: Vec<(u8, usize, Privilege)>,
+    delivered: [u64; INTERRUPT_LINES],
+}
+
+impl InterruptController {
+
+    pub fn new() -> InterruptController {
+        InterruptController::default()
+    }
+
+    pub fn raise(&mut self, line: u8) {
+        self.pending |= 1 << line;
+    }
+
+    pub fn acknowledge(&mut self, line: u8) {
+        self.pending &= !(1 << line);
+    }
+
+    // the line that would be delivered now, if any
+    #[inline(always)]
+    pub fn deliverable(&self) -> Option<u8> {
+        let ready = self.pending & self.mask;
+        if !self.enabled || ready == 0 {
+            return None;
+        }
+        let line = ready.trailing_zeros() as u8;
+        match self.in_service.last() {
+            Some((current, _, _)) if *current <= line => None,
+            _ => Some(line),
+        }
+    }
+
+    // enters the handler of 'line', returns the pc to continue at
+    pub fn enter(&mut self, line: u8, pc: usize, privilege: Privilege) -> Result<usize, String> {
+        let Some(handler) = self.vectors[line as usize] else {
+            return Err(format!("no handler installed for interrupt line {}", line));
+        };
+        self.in_service.push((line, pc, privilege));
+        self.delivered[line as usize] += 1;
+        Ok(handler)
+    }
+
+    // leaves the innermost handler, returns the pc and privilege it interrupted
+    pub fn leave(&mut self) -> Result<(usize, Privilege), String> {
+        match self.in_service.pop() {
+            Some((_, pc, privilege)) => Ok((pc, privilege)),
+            None => Err("iret outside of an interrupt handler".to_string()),
+        }
+    }
+
+    pub fn report(&self) -> InterruptReport {
+        InterruptReport {
+            enabled: self.enabled,
+            mask: self.mask,
+            pending: self.pending,
+            vectors: self.vectors.to_vec(),
+            in_service: self.in_service.iter().map(|(line, _, _)| *line).collect(),
+            delivered: self.delivered.to_vec(),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::models::instruction::{Instruction, ProgramSource};
+use crate::models::library::{LibraryEntry, LibraryReport, Manifest};
+
+// named programs kept on disk, one ProgramSource per file plus a manifest.json listing them.
+// every file is written atomically (a temporary file renamed over the old one), a program
+// file before the manifest, so a crash leaves either the old or the new version of each. a
+// program file the manifest misses is picked up again when the library is opened.
+
+pub const LIBRARY_DIRECTORY: &str = "library";
+// environment variable naming the library directory, LIBRARY_DIRECTORY when unset
+pub const LIBRARY_VARIABLE: &str = "INSTREAMS_LIBRARY";
+const MANIFEST: &str = "manifest.json";
+const MAX_NAME: usize = 64;
+
+pub struct ProgramLibrary {
+    directory: PathBuf,
+    manifest: Manifest,
+    programs: BTreeMap<String, Vec<Instruction>>,
+}
+
+// writes 'path' through a temporary file in the same directory, readers never see half a file
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+    write_through(path, contents, false)
+}
+
+// write_atomic for files only the user running the server may read (0600 on unix)
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    write_through(path, contents, true)
+}
+
+fn write_through(path: &Path, contents: &[u8], private: bool) -> Result<(), String> {
+    let temporary = path.with_extension("tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    // the mode only applies to a file being created, not to one left over by a crash
+    let _ = fs::remove_file(&temporary);
+    let written = options.open(&temporary)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temporary, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temporary);
+        return Err(format!("cannot write {}: {}", path.display(), e));
+    }
+    // the rename itself is only durable once the directory is synced, not every platform can
+    if let Some(directory) = path.parent() {
+        let _ = fs::File::open(directory).and_then(|directory| directory.sync_all());
+    }
+    Ok(())
+}
+
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME {
+        return Err(format!("a program name has 1 to {} characters", MAX_NAME));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(format!("'{}': a program name has letters, digits, '_' and '-' only", name));
+    }
+    if name == "manifest" {
+        return Err("'manifest' is the name of the library's own file".to_string());
+    }
+    Ok(())
+}
+
+// an entry of the manifest is a valid name and the file save() gives it
+fn check_entry(name: &str, entry: &LibraryEntry) -> Result<(), String> {
+    check_name(name)?;
+    if entry.file != format!("{}.json", name) {
+        return Err(format!("'{}' is not the file of program '{}'", entry.file, name));
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+fn read_program(path: &Path) -> Result<Vec<Instruction>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let source: ProgramSource = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(source.instructions)
+}
+
+impl ProgramLibrary {
+
+    // the directory named by LIBRARY_VARIABLE
+    pub fn from_environment() -> Result<ProgramLibrary, String> {
+        let directory = std::env::var(LIBRARY_VARIABLE).unwrap_or_else(|_| LIBRARY_DIRECTORY.to_string());
+        ProgramLibrary::open(directory)
+    }
+
+    // creates the directory if needed and reads every program into memory. programs that
+    // cannot be read are left out with a message, they do not keep the server from starting.
+    pub fn open(directory: impl Into<PathBuf>) -> Result<ProgramLibrary, String> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory).map_err(|e| format!("cannot create {}: {}", directory.display(), e))?;
+
+        let manifest_path = directory.join(MANIFEST);
+        // without a readable manifest the programs are found by their files below
+        let mut manifest: Manifest = match fs::read_to_string(&manifest_path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                eprintln!("::: library: {} rebuilt, {}", manifest_path.display(), e);
+                Manifest::default()
+            }),
+            Err(_) => Manifest::default(),
+        };
+
+        let mut programs = BTreeMap::new();
+        manifest.programs.retain(|name, entry| {
+            // the manifest names files in the directory, nothing else is read
+            if let Err(e) = check_entry(name, entry) {
+                eprintln!("::: library: {} left out, {}", name, e);
+                return false;
+            }
+            match read_program(&directory.join(&entry.file)) {
+                Ok(instructions) => {
+                    entry.length = instructions.len();
+                    programs.insert(name.clone(), instructions);
+                    true
+                },
+                Err(e) => {
+                    eprintln!("::: library: {} left out, {}", name, e);
+                    false
+                },
+            }
+        });
+
+        // saved by a process that stopped before it wrote the manifest
+        let mut adopted = false;
+        let files = fs::read_dir(&directory).map_err(|e| format!("cannot read {}: {}", directory.display(), e))?;
+        for file in files.flatten() {
+            let path = file.path();
+            let name = match (path.extension().and_then(|e| e.to_str()), path.file_stem().and_then(|s| s.to_str())) {
+                (Some("json"), Some(name)) => name.to_string(),
+                _ => continue,
+            };
+            if programs.contains_key(&name) || check_name(&name).is_err() {
+                continue;
+            }
+            match read_program(&path) {
+                Ok(instructions) => {
+                    manifest.programs.insert(name.clone(), LibraryEntry {
+                        file: format!("{}.json", name),
+                        length: instructions.len(),
+                        saved_at: now(),
+                    });
+                    programs.insert(name, instructions);
+                    adopted = true;
+                },
+                Err(e) => eprintln!("::: library: {} left out, {}", name, e),
+            }
+        }
+
+        let library = ProgramLibrary { directory, manifest, programs };
+        if adopted {
+            library.write_manifest()?;
+        }
+        Ok(library)
+    }
+
+    fn write_manifest(&self) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(&self.manifest).map_err(|e| e.to_string())?;
+        write_atomic(&self.directory.join(MANIFEST), text.as_bytes())
+    }
+
+    pub fn save(&mut self, name: &str, instructions: &[Instruction]) -> Result<LibraryEntry, String> {
+        check_name(name)?;
+        let entry = LibraryEntry {
+            file: format!("{}.json", name),
+            length: instructions.len(),
+            saved_at: now(),
+        };
+        let source = ProgramSource { instructions: instructions.to_vec() };
+        let text = serde_json::to_string_pretty(&source).map_err(|e| e.to_string())?;
+        write_atomic(&self.directory.join(&entry.file), text.as_bytes())?;
+
+        let previous = self.manifest.programs.insert(name.to_string(), entry.clone());
+        if let Err(e) = self.write_manifest() {
+            // the program file is picked up again at the next start anyway
+            match previous {
+                Some(previous) => self.manifest.programs.insert(name.to_string(), previous),
+                None => self.manifest.programs.remove(name),
+            };
+            return Err(e);
+        }
+        self.programs.insert(name.to_string(), instructions.to_vec());
+        Ok(entry)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&[Instruction], String> {
+        self.programs.get(name)
+            .map(|instructions| instructions.as_slice())
+            .ok_or_else(|| format!("no program '{}' in the library", name))
+    }
+
+    pub fn report(&self) -> LibraryReport {
+        LibraryReport {
+            directory: self.directory.display().to_string(),
+            programs: self.manifest.programs.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("instreams-library-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn program(length: usize) -> Vec<Instruction> {
+        (0..length).map(|at| Instruction { opcode: "add".to_string(), imdval: at.to_string(), regsrc: 1, regext: 0, regdst: 1 }).collect()
+    }
+
+    #[test]
+    fn saved_programs_are_there_after_a_reopen() {
+        let directory = directory("reopen");
+        let mut library = ProgramLibrary::open(&directory).unwrap();
+        let entry = library.save("loop", &program(3)).unwrap();
+        assert_eq!((entry.file.as_str(), entry.length), ("loop.json", 3));
+        library.save("loop", &program(2)).unwrap();
+        assert_eq!(library.get("loop").unwrap(), program(2).as_slice());
+
+        let library = ProgramLibrary::open(&directory).unwrap();
+        assert_eq!(library.get("loop").unwrap(), program(2).as_slice());
+        assert_eq!(library.report().programs["loop"].length, 2);
+        assert!(library.get("other").is_err());
+
+        // a program file without a manifest entry is picked up again
+        fs::remove_file(directory.join(MANIFEST)).unwrap();
+        let library = ProgramLibrary::open(&directory).unwrap();
+        assert_eq!(library.get("loop").unwrap().len(), 2);
+        assert!(directory.join(MANIFEST).exists());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn names_are_checked() {
+        let directory = directory("names");
+        let mut library = ProgramLibrary::open(&directory).unwrap();
+        for name in ["", "../escape", "a/b", "dot.json", "manifest", &"x".repeat(MAX_NAME + 1)] {
+            assert!(library.save(name, &program(1)).is_err(), "{:?}", name);
+        }
+        assert!(library.save("ok_name-2", &program(1)).is_ok());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn manifest_entries_stay_in_the_directory() {
+        let directory = directory("manifest");
+        let outside = directory.with_extension("outside.json");
+        fs::write(&outside, serde_json::to_string(&ProgramSource { instructions: program(1) }).unwrap()).unwrap();
+        fs::create_dir_all(&directory).unwrap();
+        let file = format!("../{}", outside.file_name().unwrap().to_str().unwrap());
+        fs::write(directory.join(MANIFEST), format!(r#"{{"programs":{{
+            "stolen":{{"file":"{}","length":1,"saved_at":0}},
+            "../up":{{"file":"../up.json","length":1,"saved_at":0}}}}}}"#, file)).unwrap();
+
+        let library = ProgramLibrary::open(&directory).unwrap();
+        assert!(library.get("stolen").is_err());
+        assert!(library.report().programs.is_empty());
+        let _ = fs::remove_dir_all(&directory);
+        let _ = fs::remove_file(&outside);
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::models::instruction::{Instruction};
+use crate::models::consistency::{LitmusRequest, LitmusResult, LitmusOutcome};
+use crate::services::executor::{parse_imdval, Machine, MachineState, REGISTER_COUNT, REG_SP};
+use crate::services::consistency::{drainable, BufferedStore};
+use crate::services::core::{CORE_STACK_SIZE};
+use crate::services::vector::{Vector};
+
+// the litmus runner explores every execution of a short multi-core program under a memory
+// model. from every state each running core may execute its next instruction and each
+// buffered store the model allows may become visible, the final values of the observed
+// registers and words are the outcomes. states are compared by their contents, so a core
+// spinning in a loop does not keep the search going.
+//
+// the runner works on a LITMUS_MEMORY byte data memory, followed by a CORE_STACK_SIZE
+// stack for every core, without caches, branch predictors or interrupts, and the input of
+// the program is closed. the search runs to completion (or to its bounds) in one request,
+// so the number of cores and the bounds are limited to MAX_LITMUS_CORES, MAX_STATES and
+// MAX_STEPS.
+//
+// store buffering: can both cores read 0?
+//
+//   0: coreid r2               {"cores": 2, "memory_model": "Tso",
+//   1: add r3, r0, 1            "observe": ["0:r1", "1:r1"],
+//   2: bne r2, r0, 6            "exists": {"0:r1": 0, "1:r1": 0}}
+//   3: st r3, [r0 + 0x100]
+//   4: ld r1, [r0 + 0x108]     not with Sequential, with Tso and Relaxed it can. a fence
+//   5: halt                    between the store and the load rules it out again.
+//   6: st r3, [r0 + 0x108]
+//   7: ld r1, [r0 + 0x100]
+//   8: halt
+
+pub const LITMUS_MEMORY: usize = 4 * 1024;
+pub const MAX_LITMUS_CORES: usize = 4;
+pub const DEFAULT_MAX_STATES: usize = 100_000;
+pub const MAX_STATES: usize = 250_000;
+pub const DEFAULT_MAX_STEPS: u64 = 1_000;
+pub const MAX_STEPS: u64 = 10_000;
+
+enum Observation {
+    Register { core: usize, register: usize },
+    Memory { address: usize },
+}
+
+fn parse_observation(name: &str, cores: usize) -> Result<Observation, String> {
+    if let Some(address) = name.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        return match parse_imdval(address)? {
+            Some(address) if address >= 0 && address as usize + 8 <= LITMUS_MEMORY => Ok(Observation::Memory { address: address as usize }),
+            _ => Err(format!("'{}' is not a word of the {} byte litmus memory", name, LITMUS_MEMORY)),
+        };
+    }
+    let parsed = name.split_once(':').and_then(|(core, register)| {
+        let register = register.strip_prefix('r')?.parse::<usize>().ok()?;
+        Some((core.parse::<usize>().ok()?, register))
+    });
+    match parsed {
+        Some((core, register)) if core < cores && register < REGISTER_COUNT => Ok(Observation::Register { core, register }),
+        _ => Err(format!("can not observe '{}', use \"core:rN\" or \"[address]\"", name)),
+    }
+}
+
+// a state of the search as the visited set keeps it, compared in full. the memory is kept as
+// the bytes that differ from the initial memory, a litmus test only changes a few words.
+#[derive(PartialEq, Eq, Hash)]
+struct StateKey {
+    cores: Vec<CoreKey>,
+    memory: Vec<(usize, u8)>,
+    exit_code: Option<i64>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct CoreKey {
+    registers: [u64; REGISTER_COUNT],
+    // the bits, so NaN == NaN
+    fregisters: [u64; REGISTER_COUNT],
+    vregisters: [Vector; REGISTER_COUNT],
+    pc: usize,
+    state: MachineState,
+    reservation: Option<usize>,
+    store_buffer: Vec<BufferedStore>,
+}
+
+// the state of one core, executing or parked
+struct CoreView<'a> {
+    registers: &'a [u64; REGISTER_COUNT],
+    fregisters: &'a [f64; REGISTER_COUNT],
+    vregisters: &'a [Vector; REGISTER_COUNT],
+    pc: usize,
+    state: MachineState,
+    reservation: Option<usize>,
+    store_buffer: &'a [BufferedStore],
+}
+
+impl Machine {
+
+    fn view(&self, id: usize) -> CoreView<'_> {
+        if id == self.core {
+            return CoreView {
+                registers: &self.registers,
+                fregisters: &self.fregisters,
+                vregisters: &self.vregisters,
+                pc: self.pc,
+                state: self.state,
+                reservation: self.reservation,
+                store_buffer: &self.store_buffer,
+            };
+        }
+        let core = &self.cores[id];
+        CoreView {
+            registers: &core.registers,
+            fregisters: &core.fregisters,
+            vregisters: &core.vregisters,
+            pc: core.pc,
+            state: core.state,
+            reservation: core.reservation,
+            store_buffer: &core.store_buffer,
+        }
+    }
+
+    // a copy to explore a choice with, the litmus runner attaches no simulators
+    fn fork(&self) -> Machine {
+        Machine {
+            code: self.code.clone(),
+            ops: self.ops.clone(),
+            registers: self.registers,
+            fregisters: self.fregisters,
+            fflags: self.fflags,
+            rounding: self.rounding,
+            vregisters: self.vregisters,
+            memory: self.memory.clone(),
+            pc: self.pc,
+            steps: self.steps,
+            counts: self.counts.clone(),
+            state: self.state,
+            fault: self.fault.clone(),
+            cache: None,
+            branch: None,
+            interrupts: self.interrupts.clone(),
+            devices: self.devices.clone(),
+            exit_code: self.exit_code,
+            syscall_errors: self.syscall_errors.clone(),
+            ticks: self.ticks,
+            yielded: self.yielded,
+            cores: self.cores.clone(),
+            core: self.core,
+            quantum: self.quantum,
+            reservation: self.reservation,
+            memory_model: self.memory_model,
+            store_buffer: self.store_buffer.clone(),
+            exceptions: self.exceptions.clone(),
+            exception: self.exception.clone(),
+            privilege: self.privilege,
+            mpu: self.mpu.clone(),
+            mmu: self.mmu.clone(),
+        }
+    }
+
+    // identifies the state regardless of the path that led to it. 'initial' is the memory
+    // the search started with.
+    fn key(&self, initial: &[u8]) -> StateKey {
+        StateKey {
+            cores: (0..self.core_count()).map(|id| {
+                let core = self.view(id);
+                CoreKey {
+                    registers: *core.registers,
+                    fregisters: core.fregisters.map(f64::to_bits),
+                    vregisters: *core.vregisters,
+                    pc: core.pc,
+                    state: core.state,
+                    reservation: core.reservation,
+                    store_buffer: core.store_buffer.to_vec(),
+                }
+            }).collect(),
+            memory: self.changed_memory(initial),
+            exit_code: self.exit_code,
+        }
+    }
+
+    // (address, byte) of every byte that is not what it was in 'initial'
+    fn changed_memory(&self, initial: &[u8]) -> Vec<(usize, u8)> {
+        const CHUNK: usize = 64;
+        let mut changed = Vec::new();
+        for (chunk, (bytes, initial)) in self.memory.chunks(CHUNK).zip(initial.chunks(CHUNK)).enumerate() {
+            if bytes == initial {
+                continue;
+            }
+            changed.extend(bytes.iter().zip(initial)
+                .enumerate()
+                .filter(|(_, (byte, initial))| byte != initial)
+                .map(|(offset, (byte, _))| (chunk * CHUNK + offset, *byte)));
+        }
+        changed
+    }
+
+    fn observe(&self, observations: &[(String, Observation)]) -> BTreeMap<String, u64> {
+        observations.iter().map(|(name, observation)| {
+            let value = match observation {
+                Observation::Register { core, register } => self.view(*core).registers[*register],
+                Observation::Memory { address } => {
+                    let mut bytes = [0u8; 8];
+                    bytes.copy_from_slice(&self.memory[*address..*address + 8]);
+                    u64::from_le_bytes(bytes)
+                },
+            };
+            (name.clone(), value)
+        }).collect()
+    }
+}
+
+pub fn explore(code: Vec<Instruction>, request: &LitmusRequest) -> Result<LitmusResult, String> {
+
+    if request.cores == 0 || request.cores > MAX_LITMUS_CORES {
+        return Err(format!("a litmus test has 1 to {} cores, not {}", MAX_LITMUS_CORES, request.cores));
+    }
+    let max_states = request.max_states.unwrap_or(DEFAULT_MAX_STATES);
+    if max_states > MAX_STATES {
+        return Err(format!("max_states is at most {}", MAX_STATES));
+    }
+    let max_steps = request.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
+    if max_steps > MAX_STEPS {
+        return Err(format!("max_steps is at most {}", MAX_STEPS));
+    }
+
+    let observations = request.observe.iter()
+        .map(|name| parse_observation(name, request.cores).map(|observation| (name.clone(), observation)))
+        .collect::<Result<Vec<_>, String>>()?;
+    if let Some(name) = request.exists.iter().flatten().map(|(name, _)| name).find(|name| !request.observe.contains(name)) {
+        return Err(format!("'{}' of exists is not observed", name));
+    }
+
+    // the stacks start at the top of memory and every core gets its own, set_cores puts the
+    // stack pointers CORE_STACK_SIZE apart
+    let mut machine = Machine::new(code);
+    machine.memory.truncate(LITMUS_MEMORY + request.cores * CORE_STACK_SIZE);
+    machine.registers[REG_SP as usize] = machine.memory.len() as u64;
+    machine.memory_model = request.memory_model;
+    machine.devices.input_closed = true;
+    for (address, value) in &request.memory {
+        match parse_imdval(address)? {
+            Some(at) if at >= 0 && at as usize + 8 <= LITMUS_MEMORY => {
+                machine.memory[at as usize..at as usize + 8].copy_from_slice(&value.to_le_bytes());
+            },
+            _ => return Err(format!("initial word '{}' outside of the litmus memory", address)),
+        }
+    }
+    machine.set_cores(request.cores, 1)?;
+    let initial = machine.memory.clone();
+
+    let mut outcomes: BTreeMap<(BTreeMap<String, u64>, Option<String>), u64> = BTreeMap::new();
+    let mut visited = HashSet::new();
+    let mut complete = true;
+    let mut pending = vec![machine];
+
+    while let Some(machine) = pending.pop() {
+        if !visited.insert(machine.key(&initial)) {
+            continue;
+        }
+        if visited.len() > max_states {
+            complete = false;
+            break;
+        }
+
+        let mut moves = 0;
+        for id in 0..machine.core_count() {
+            let core = machine.view(id);
+            if core.state == MachineState::Running {
+                moves += 1;
+                if machine.steps >= max_steps {
+                    complete = false;
+                    continue;
+                }
+                let mut next = machine.fork();
+                next.switch_to(id);
+                if let Err(exception) = next.step() {
+                    let fault = format!("core {}: {} at pc {}", id, exception.message, next.pc);
+                    *outcomes.entry((BTreeMap::new(), Some(fault))).or_default() += 1;
+                    continue;
+                }
+                // exit ends every core
+                if next.exit_code.is_some() {
+                    next.cores.iter_mut().for_each(|core| core.state = MachineState::Halted);
+                }
+                pending.push(next);
+            }
+            for index in 0..core.store_buffer.len() {
+                if drainable(machine.memory_model, core.store_buffer, index) {
+                    moves += 1;
+                    let mut next = machine.fork();
+                    next.switch_to(id);
+                    next.drain_store(index);
+                    pending.push(next);
+                }
+            }
+        }
+
+        if moves == 0 {
+            *outcomes.entry((machine.observe(&observations), None)).or_default() += 1;
+        }
+    }
+
+    let outcomes: Vec<LitmusOutcome> = outcomes.into_iter()
+        .map(|((values, fault), states)| LitmusOutcome { values, fault, states })
+        .collect();
+    let exists = request.exists.as_ref().map(|exists| {
+        outcomes.iter().any(|outcome| outcome.fault.is_none()
+            && exists.iter().all(|(name, value)| outcome.values.get(name) == Some(value)))
+    });
+
+    Ok(LitmusResult {
+        memory_model: request.memory_model,
+        outcomes,
+        states: visited.len(),
+        complete,
+        exists,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::consistency::{MemoryModel};
+    use crate::services::assembler::{assemble};
+
+    fn request(cores: usize, observe: &[&str]) -> LitmusRequest {
+        LitmusRequest {
+            cores,
+            memory_model: MemoryModel::Sequential,
+            observe: observe.iter().map(|name| name.to_string()).collect(),
+            memory: BTreeMap::new(),
+            exists: None,
+            max_states: None,
+            max_steps: None,
+        }
+    }
+
+    fn program(source: &str) -> Vec<Instruction> {
+        assemble(source, &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions
+    }
+
+    #[test]
+    fn every_core_has_a_stack_of_its_own() {
+        let code = program("
+            coreid r10
+            add r1, r10, 100
+            push r1
+            add r1, r0, 0
+            pop r2
+            halt");
+        let cores = 3;
+        let observe: Vec<String> = (0..cores).map(|core| format!("{}:r2", core)).chain(["[0xff8]".to_string()]).collect();
+        let observe: Vec<&str> = observe.iter().map(String::as_str).collect();
+        let result = explore(code, &request(cores, &observe)).unwrap();
+
+        assert!(result.complete);
+        assert_eq!(result.outcomes.len(), 1);
+        let outcome = &result.outcomes[0];
+        assert_eq!(outcome.fault, None);
+        for core in 0..cores {
+            assert_eq!(outcome.values[&format!("{}:r2", core)], 100 + core as u64);
+        }
+        // the data memory is left alone
+        assert_eq!(outcome.values["[0xff8]"], 0);
+    }
+
+    #[test]
+    fn states_are_compared_in_full() {
+        // core 1 spins until core 0 sets the flag, the search still ends
+        let code = program("
+            coreid r10
+            bne r10, r0, wait
+            add r1, r0, 1
+            st r1, [r0 + 0x100]
+            halt
+        wait:
+            ld r2, [r0 + 0x100]
+            beq r2, r0, wait
+            halt");
+        let result = explore(code, &request(2, &["1:r2"])).unwrap();
+        assert!(result.complete);
+        assert_eq!(result.outcomes.len(), 1);
+        assert_eq!(result.outcomes[0].values["1:r2"], 1);
+
+        // two states that only differ in memory are both explored
+        let machine = Machine::new(program("halt"));
+        let initial = machine.memory.clone();
+        let mut other = machine.fork();
+        other.memory[0x100] = 1;
+        assert!(machine.key(&initial) != other.key(&initial));
+        assert!(machine.key(&initial) == machine.fork().key(&initial));
+    }
+
+    #[test]
+    fn bounds_are_limited() {
+        let code = program("halt");
+        assert!(explore(code.clone(), &request(MAX_LITMUS_CORES + 1, &[])).is_err());
+        assert!(explore(code.clone(), &request(0, &[])).is_err());
+        let states = LitmusRequest { max_states: Some(MAX_STATES + 1), ..request(2, &[]) };
+        assert!(explore(code.clone(), &states).is_err());
+        let steps = LitmusRequest { max_steps: Some(MAX_STEPS + 1), ..request(2, &[]) };
+        assert!(explore(code.clone(), &steps).is_err());
+        assert!(explore(code, &request(2, &[])).unwrap().complete);
+    }
+}
@@ -1 +1,27 @@
-pub mod state;
\ No newline at end of file
+pub mod state;
+pub mod executor;
+pub mod cache;
+pub mod branch;
+pub mod analysis;
+pub mod dot;
+pub mod optimizer;
+pub mod decoder;
+pub mod float;
+pub mod vector;
+pub mod interrupt;
+pub mod context;
+pub mod device;
+pub mod syscall;
+pub mod events;
+pub mod core;
+pub mod atomic;
+pub mod consistency;
+pub mod litmus;
+pub mod exception;
+pub mod protection;
+pub mod paging;
+pub mod patch;
+pub mod history;
+pub mod library;
+pub mod persistence;
+pub mod assembler;
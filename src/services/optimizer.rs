@@ -0,0 +1,558 @@
+use crate::models::instruction::{Instruction};
+use crate::models::analysis::{DiagnosticKind};
+use crate::models::optimizer::{Pass, Rewrite, Verification, OptimizeResult};
+use crate::models::run::{RunStatus};
+use crate::services::analysis::{analyze};
+use crate::services::syscall::{SYSCALL_RESULT};
+use crate::services::executor::{Machine, parse_imdval, alu, is_alu, has_target,
+    REGISTER_COUNT, REG_ZERO, REG_LINK};
+
+// the pipeline is repeated until nothing changes, one rewrite often enables another
+const MAX_ROUNDS: usize = 16;
+
+fn immediate(value: u64) -> String {
+    format!("0x{:x}", value)
+}
+
+fn target_of(instruction: &Instruction) -> Option<usize> {
+    match parse_imdval(&instruction.imdval) {
+        Ok(Some(target)) if target >= 0 => Some(target as usize),
+        _ => None,
+    }
+}
+
+fn registers_valid(instruction: &Instruction) -> bool {
+    [instruction.regsrc, instruction.regext, instruction.regdst].iter()
+        .all(|register| (*register as usize) < REGISTER_COUNT)
+}
+
+// an ALU instruction that can not fault, so it may be removed without changing behaviour
+fn is_pure(instruction: &Instruction) -> bool {
+    if !is_alu(&instruction.opcode) || !registers_valid(instruction) {
+        return false;
+    }
+    match (instruction.opcode.as_str(), parse_imdval(&instruction.imdval)) {
+        ("div" | "rem", Ok(Some(divisor))) => divisor != 0,
+        ("div" | "rem", _) => false,
+        (_, imdval) => imdval.is_ok(),
+    }
+}
+
+// an instruction and where it was in the submitted program
+#[derive(Clone)]
+struct Slot {
+    origin: usize,
+    instruction: Instruction,
+}
+
+pub struct Optimizer {
+    slots: Vec<Slot>,
+    rewrites: Vec<Rewrite>,
+}
+
+impl Optimizer {
+
+    pub fn new(program: &[Instruction]) -> Optimizer {
+        Optimizer {
+            slots: program.iter()
+                .enumerate()
+                .map(|(origin, instruction)| Slot { origin, instruction: instruction.clone() })
+                .collect(),
+            rewrites: Vec::new(),
+        }
+    }
+
+    fn replace(&mut self, index: usize, pass: Pass, instruction: Instruction) {
+        let slot = &mut self.slots[index];
+        self.rewrites.push(Rewrite {
+            pass,
+            index: slot.origin,
+            before: slot.instruction.clone(),
+            after: Some(instruction.clone()),
+        });
+        slot.instruction = instruction;
+    }
+
+    // drops the marked instructions and moves every branch target to the new position
+    // of the instruction it pointed at (or the next one kept, if that one is gone)
+    fn remove(&mut self, pass: Pass, remove: &[bool]) -> bool {
+
+        if !remove.contains(&true) {
+            return false;
+        }
+
+        let length = self.slots.len();
+        let mut new_index = vec![0; length + 1];
+        let mut kept = 0;
+        for index in 0..=length {
+            new_index[index] = kept;
+            if index < length && !remove[index] {
+                kept += 1;
+            }
+        }
+
+        let slots = std::mem::take(&mut self.slots);
+        for (index, mut slot) in slots.into_iter().enumerate() {
+            if remove[index] {
+                self.rewrites.push(Rewrite {
+                    pass,
+                    index: slot.origin,
+                    before: slot.instruction,
+                    after: None,
+                });
+                continue;
+            }
+            if has_target(&slot.instruction.opcode) {
+                if let Some(target) = target_of(&slot.instruction).filter(|target| *target <= length) {
+                    slot.instruction.imdval = immediate(new_index[target] as u64);
+                }
+            }
+            self.slots.push(slot);
+        }
+        true
+    }
+
+    // first instruction of every basic block
+    fn leaders(&self) -> Vec<bool> {
+        let length = self.slots.len();
+        let mut leaders = vec![false; length];
+        if length > 0 {
+            leaders[0] = true;
+        }
+        for (index, slot) in self.slots.iter().enumerate() {
+            let opcode = slot.instruction.opcode.as_str();
+            if has_target(opcode) {
+                if let Some(target) = target_of(&slot.instruction).filter(|target| *target < length) {
+                    leaders[target] = true;
+                }
+            }
+            if (has_target(opcode) || matches!(opcode, "ret" | "halt")) && index + 1 < length {
+                leaders[index + 1] = true;
+            }
+        }
+        leaders
+    }
+
+    // evaluates ALU instructions whose operands are known constants within a basic block,
+    // and turns register operands holding a known constant into immediates
+    fn constant_folding(&mut self) -> bool {
+
+        let leaders = self.leaders();
+        let mut known: [Option<u64>; REGISTER_COUNT] = [None; REGISTER_COUNT];
+        let mut changed = false;
+
+        for (index, leader) in leaders.iter().enumerate() {
+            if *leader {
+                known = [None; REGISTER_COUNT];
+                known[REG_ZERO as usize] = Some(0);
+            }
+
+            let instruction = self.slots[index].instruction.clone();
+            if matches!(instruction.opcode.as_str(), "ecall" | "syscall") {
+                known[SYSCALL_RESULT as usize] = None;
+            }
+            // writes to r0 are left to the no-op removal
+            if !registers_valid(&instruction) || instruction.regdst == REG_ZERO {
+                continue;
+            }
+            let dst = instruction.regdst as usize;
+
+            if !is_alu(&instruction.opcode) {
+                // whatever it is, it may have written regdst
+                known[dst] = None;
+                continue;
+            }
+
+            let Ok(imdval) = parse_imdval(&instruction.imdval) else {
+                known[dst] = None;
+                continue;
+            };
+            let src = known[instruction.regsrc as usize];
+            let operand = match imdval {
+                Some(value) => Some(value as u64),
+                None => known[instruction.regext as usize],
+            };
+
+            let mut value = None;
+            let replacement = match (src, operand) {
+                (Some(src), Some(operand)) => match alu(&instruction.opcode, src, operand) {
+                    Ok(result) => {
+                        value = Some(result);
+                        let folded = instruction.opcode == "add" && instruction.regsrc == REG_ZERO
+                            && imdval == Some(result as i64);
+                        (!folded).then(|| Instruction {
+                            opcode: "add".to_string(),
+                            imdval: immediate(result),
+                            regsrc: REG_ZERO,
+                            regext: REG_ZERO,
+                            regdst: instruction.regdst,
+                        })
+                    }
+                    // leave the fault where it is
+                    Err(_) => None,
+                },
+                (None, Some(operand)) if imdval.is_none() => Some(Instruction {
+                    imdval: immediate(operand),
+                    regext: REG_ZERO,
+                    ..instruction.clone()
+                }),
+                _ => None,
+            };
+
+            known[dst] = value;
+            if let Some(replacement) = replacement {
+                self.replace(index, Pass::ConstantFolding, replacement);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // multiplication by a power of two becomes a left shift
+    fn strength_reduction(&mut self) -> bool {
+        let mut changed = false;
+        for index in 0..self.slots.len() {
+            let instruction = self.slots[index].instruction.clone();
+            if instruction.opcode != "mul" {
+                continue;
+            }
+            if let Ok(Some(factor)) = parse_imdval(&instruction.imdval) {
+                if factor > 1 && (factor as u64).is_power_of_two() {
+                    self.replace(index, Pass::StrengthReduction, Instruction {
+                        opcode: "shl".to_string(),
+                        imdval: immediate(factor.trailing_zeros() as u64),
+                        ..instruction
+                    });
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    fn is_no_op(&self, index: usize) -> bool {
+        let instruction = &self.slots[index].instruction;
+        let opcode = instruction.opcode.as_str();
+
+        if opcode == "nop" {
+            return true;
+        }
+        // a jump or branch to the next instruction goes there either way
+        if has_target(opcode) && !matches!(opcode, "call" | "ivec" | "xvec" | "ujmp") {
+            return registers_valid(instruction) && target_of(instruction) == Some(index + 1);
+        }
+        if !is_pure(instruction) {
+            return false;
+        }
+        // writes to r0 are discarded
+        if instruction.regdst == REG_ZERO {
+            return true;
+        }
+        if instruction.regdst != instruction.regsrc {
+            return false;
+        }
+        let operand = match parse_imdval(&instruction.imdval) {
+            Ok(Some(value)) => Some(value),
+            Ok(None) if instruction.regext == REG_ZERO => Some(0),
+            _ => None,
+        };
+        matches!((opcode, operand),
+            ("add" | "sub" | "or" | "xor" | "shl" | "shr" | "sar", Some(0))
+            | ("mul" | "div", Some(1))
+            | ("and", Some(-1)))
+    }
+
+    fn remove_no_ops(&mut self) -> bool {
+        let remove: Vec<bool> = (0..self.slots.len()).map(|index| self.is_no_op(index)).collect();
+        self.remove(Pass::NoOpRemoval, &remove)
+    }
+
+    // unreachable instructions and ALU results that are overwritten before being read
+    fn remove_dead_code(&mut self) -> bool {
+        let program: Vec<Instruction> = self.slots.iter().map(|slot| slot.instruction.clone()).collect();
+        let mut remove = vec![false; program.len()];
+        for diagnostic in analyze(&program).diagnostics {
+            match diagnostic.kind {
+                DiagnosticKind::UnreachableInstruction => remove[diagnostic.index] = true,
+                DiagnosticKind::DeadStore if is_pure(&program[diagnostic.index]) => remove[diagnostic.index] = true,
+                _ => {}
+            }
+        }
+        self.remove(Pass::DeadCodeRemoval, &remove)
+    }
+
+    // a branch to an unconditional jump goes straight to the jump's target
+    fn branch_collapsing(&mut self) -> bool {
+        let length = self.slots.len();
+        let mut changed = false;
+        for index in 0..length {
+            let instruction = self.slots[index].instruction.clone();
+            if !has_target(&instruction.opcode) {
+                continue;
+            }
+            let Some(first) = target_of(&instruction) else { continue; };
+
+            let mut target = first;
+            let mut hops = 0;
+            while target < length && self.slots[target].instruction.opcode == "jmp" && hops < length {
+                match target_of(&self.slots[target].instruction) {
+                    Some(next) if next <= length && next != target => target = next,
+                    _ => break,
+                }
+                hops += 1;
+            }
+
+            if target != first {
+                self.replace(index, Pass::BranchCollapsing, Instruction {
+                    imdval: immediate(target as u64),
+                    ..instruction
+                });
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn optimize(&mut self) {
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = self.branch_collapsing();
+            changed |= self.constant_folding();
+            changed |= self.strength_reduction();
+            changed |= self.remove_no_ops();
+            changed |= self.remove_dead_code();
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    pub fn program(&self) -> Vec<Instruction> {
+        self.slots.iter().map(|slot| slot.instruction.clone()).collect()
+    }
+}
+
+// fault messages end with the pc, which moves when instructions are removed
+fn fault_kind(machine: &Machine) -> &str {
+    let fault = machine.fault.as_deref().unwrap_or("");
+    fault.split(" at pc ").next().unwrap_or(fault)
+}
+
+// runs both programs from the same initial state and compares what they leave behind.
+// the link register holds a code address, which legitimately changes.
+pub fn verify(original: &[Instruction], optimized: &[Instruction], budget: u64) -> Verification {
+
+    let mut before = Machine::new(original.to_vec());
+    let mut after = Machine::new(optimized.to_vec());
+    let original_status = before.run(budget);
+    let optimized_status = after.run(budget);
+
+    let registers_match = (0..REGISTER_COUNT)
+        .filter(|register| *register != REG_LINK as usize)
+        .all(|register| before.registers[register] == after.registers[register]);
+
+    // compared bit for bit, so NaN == NaN
+    let fregisters_match = (0..REGISTER_COUNT)
+        .all(|register| before.fregisters[register].to_bits() == after.fregisters[register].to_bits());
+    let vregisters_match = before.vregisters == after.vregisters;
+
+    let (equivalent, message) = match (original_status, optimized_status) {
+        (RunStatus::BudgetExhausted, _) => (false, "original program did not halt within the budget".to_string()),
+        (RunStatus::Halted, RunStatus::Halted) if !registers_match => (false, "registers differ".to_string()),
+        (RunStatus::Halted, RunStatus::Halted) if !fregisters_match => (false, "fp registers differ".to_string()),
+        (RunStatus::Halted, RunStatus::Halted) if !vregisters_match => (false, "vector registers differ".to_string()),
+        (RunStatus::Halted, RunStatus::Halted) if before.memory != after.memory => (false, "memory differs".to_string()),
+        (RunStatus::Halted, RunStatus::Halted) => (true, "registers and memory match".to_string()),
+        (RunStatus::Faulted, RunStatus::Faulted) if fault_kind(&before) == fault_kind(&after) =>
+            (true, format!("both runs fault with '{}'", fault_kind(&before))),
+        _ => (false, "run status differs".to_string()),
+    };
+
+    Verification {
+        equivalent,
+        original_status,
+        optimized_status,
+        original_steps: before.steps,
+        optimized_steps: after.steps,
+        message,
+    }
+}
+
+// optimizes a program and checks the result against the unoptimized program.
+// when the two can not be shown to behave the same, the original program is returned.
+pub fn optimize(program: &[Instruction], budget: u64) -> OptimizeResult {
+
+    let mut optimizer = Optimizer::new(program);
+    optimizer.optimize();
+    let optimized = optimizer.program();
+    let verification = verify(program, &optimized, budget);
+
+    OptimizeResult {
+        instructions: if verification.equivalent { optimized } else { program.to_vec() },
+        rewrites: optimizer.rewrites,
+        verification,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: u64 = 10_000;
+
+    fn instruction(opcode: &str, imdval: &str, regsrc: u8, regext: u8, regdst: u8) -> Instruction {
+        Instruction { opcode: opcode.to_string(), imdval: imdval.to_string(), regsrc, regext, regdst }
+    }
+
+    // runs a program with some registers set beforehand
+    fn run(program: &[Instruction], inputs: &[(usize, u64)]) -> Machine {
+        let mut machine = Machine::new(program.to_vec());
+        for (register, value) in inputs {
+            machine.registers[*register] = *value;
+        }
+        assert_eq!(machine.run(BUDGET), RunStatus::Halted, "{:?}", machine.fault);
+        machine
+    }
+
+    // applies a single pass until it stops changing the program, then runs the program before
+    // and after it on every input and compares the registers and memory they leave behind
+    fn differential(program: &[Instruction], pass: fn(&mut Optimizer) -> bool, expected: Pass, inputs: &[&[(usize, u64)]]) -> Vec<Instruction> {
+        let mut optimizer = Optimizer::new(program);
+        let mut rounds = 0;
+        while pass(&mut optimizer) && rounds < MAX_ROUNDS {
+            rounds += 1;
+        }
+        assert!(rounds > 0, "the pass did not change the program");
+        assert!(optimizer.rewrites.iter().all(|rewrite| rewrite.pass == expected));
+        let optimized = optimizer.program();
+
+        for inputs in inputs {
+            let before = run(program, inputs);
+            let after = run(&optimized, inputs);
+            for register in (0..REGISTER_COUNT).filter(|register| *register != REG_LINK as usize) {
+                assert_eq!(before.registers[register], after.registers[register], "r{} with inputs {:?}", register, inputs);
+            }
+            assert!(before.memory == after.memory, "memory differs with inputs {:?}", inputs);
+            assert!(after.steps <= before.steps);
+        }
+        optimized
+    }
+
+    #[test]
+    fn constant_folding_keeps_results() {
+        let program = [
+            instruction("add", "6", 0, 0, 1),
+            instruction("add", "7", 1, 0, 2),
+            instruction("mul", "", 2, 1, 3),
+            // r5 is an input, r3 becomes an immediate
+            instruction("sub", "", 5, 3, 4),
+            instruction("beq", "7", 5, 0, 0),
+            instruction("add", "", 3, 1, 6),
+            instruction("st", "0x100", 0, 4, 0),
+            // a new block, nothing is known any more
+            instruction("add", "1", 1, 0, 7),
+            // a division by zero is not folded, it faults if it runs (it is jumped over)
+            instruction("beq", "10", 0, 0, 0),
+            instruction("div", "", 1, 0, 8),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        let optimized = differential(&program, Optimizer::constant_folding, Pass::ConstantFolding,
+            &[&[(5, 0)], &[(5, 7)], &[(5, u64::MAX)]]);
+        assert_eq!(optimized[2].imdval, "0x4e");
+        assert_eq!((optimized[3].regext, optimized[3].imdval.as_str()), (0, "0x4e"));
+        assert_eq!(optimized[9].opcode, "div");
+    }
+
+    #[test]
+    fn strength_reduction_keeps_results() {
+        let program = [
+            instruction("mul", "8", 1, 0, 2),
+            instruction("mul", "0x40", 1, 0, 3),
+            instruction("mul", "3", 1, 0, 4),
+            instruction("blt", "6", 1, 0, 0),
+            instruction("mul", "2", 2, 0, 2),
+            instruction("st", "0x200", 0, 2, 0),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        let optimized = differential(&program, Optimizer::strength_reduction, Pass::StrengthReduction,
+            &[&[(1, 3)], &[(1, (-5i64) as u64)], &[(1, 1 << 60)]]);
+        let opcodes: Vec<&str> = optimized.iter().map(|instruction| instruction.opcode.as_str()).collect();
+        assert_eq!(opcodes, vec!["shl", "shl", "mul", "blt", "shl", "st", "halt"]);
+    }
+
+    #[test]
+    fn dead_code_removal_keeps_results() {
+        let program = [
+            // overwritten before it is read
+            instruction("add", "1", 0, 0, 1),
+            instruction("add", "2", 0, 0, 1),
+            instruction("beq", "5", 3, 0, 0),
+            instruction("add", "", 1, 1, 2),
+            instruction("jmp", "7", 0, 0, 0),
+            instruction("add", "", 1, 3, 2),
+            instruction("halt", "", 0, 0, 0),
+            instruction("st", "0x300", 0, 2, 0),
+            instruction("halt", "", 0, 0, 0),
+            // never reached
+            instruction("add", "9", 0, 0, 4),
+        ];
+        let optimized = differential(&program, Optimizer::remove_dead_code, Pass::DeadCodeRemoval,
+            &[&[(3, 0)], &[(3, 5)]]);
+        assert_eq!(optimized.len(), program.len() - 2);
+        // the branch follows the instructions it pointed at
+        assert_eq!((optimized[1].opcode.as_str(), optimized[1].imdval.as_str()), ("beq", "0x4"));
+        assert_eq!(optimized[3].imdval, "0x6");
+    }
+
+    #[test]
+    fn branch_collapsing_keeps_results() {
+        let program = [
+            instruction("beq", "3", 1, 0, 0),
+            instruction("add", "1", 0, 0, 2),
+            instruction("halt", "", 0, 0, 0),
+            instruction("jmp", "4", 0, 0, 0),
+            instruction("jmp", "6", 0, 0, 0),
+            instruction("halt", "", 0, 0, 0),
+            instruction("add", "2", 0, 0, 2),
+            instruction("st", "0x80", 0, 2, 0),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        let optimized = differential(&program, Optimizer::branch_collapsing, Pass::BranchCollapsing,
+            &[&[(1, 0)], &[(1, 1)]]);
+        assert_eq!(optimized[0].imdval, "0x6");
+        assert_eq!(optimized[3].imdval, "0x6");
+    }
+
+    #[test]
+    fn optimized_program_is_verified() {
+        let program = [
+            instruction("add", "4", 0, 0, 1),
+            instruction("mul", "", 2, 1, 3),
+            instruction("add", "", 3, 0, 3),
+            instruction("bne", "5", 2, 0, 0),
+            instruction("add", "1", 3, 0, 3),
+            instruction("halt", "", 0, 0, 0),
+        ];
+        let result = optimize(&program, BUDGET);
+        assert!(result.verification.equivalent, "{}", result.verification.message);
+        assert!(result.instructions.len() < program.len());
+        for input in [0, 3] {
+            let before = run(&program, &[(2, input)]);
+            let after = run(&result.instructions, &[(2, input)]);
+            assert_eq!(before.registers[3], after.registers[3]);
+        }
+    }
+
+    #[test]
+    fn verify_notices_a_difference() {
+        let program = [instruction("add", "1", 0, 0, 1), instruction("halt", "", 0, 0, 0)];
+        let changed = [instruction("add", "2", 0, 0, 1), instruction("halt", "", 0, 0, 0)];
+        let verification = verify(&program, &changed, BUDGET);
+        assert!(!verification.equivalent);
+        assert_eq!(verification.message, "registers differ");
+
+        // r1 holds 1 in both, only the stored register differs
+        let program = [instruction("add", "1", 0, 0, 1), instruction("st", "0x10", 0, 0, 0), instruction("halt", "", 0, 0, 0)];
+        let changed = [instruction("add", "1", 0, 0, 1), instruction("st", "0x10", 0, 1, 0), instruction("halt", "", 0, 0, 0)];
+        assert_eq!(verify(&program, &changed, BUDGET).message, "memory differs");
+    }
+}
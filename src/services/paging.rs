@@ -0,0 +1,396 @@
+use crate::models::exception::{ExceptionKind};
+use crate::models::paging::{PagingConfig, TlbReport};
+use crate::models::protection::{Privilege};
+use crate::services::executor::{Machine};
+use crate::services::exception::{Exception};
+use crate::services::decoder::{Op};
+use crate::services::protection::{PERMISSION_WRITE};
+
+// virtual memory. once a core sets a page table root, the data addresses of its loads, stores
+// and system call buffers are virtual and translated through a two level page table in data
+// memory. code addresses (instruction indexes) and the devices are never translated.
+//
+//  ptroot      root page table = rs, 0 switches translation off   (flushes the tlb)
+//  tlbflush    drops every tlb entry
+//
+// a page table is one page of 8 byte entries, so a virtual address is
+//
+//   | first level index | second level index | offset in the page |
+//
+// with log2(page_size / 8) bits for each index. an entry holds the physical address of the
+// next table (first level) or of the page (second level), page aligned, or'ed with PTE_*
+// flags. first level entries only need PTE_VALID. the walker sets PTE_ACCESSED on every page
+// it translates and PTE_DIRTY on pages that are written, in memory.
+//
+// a missing entry, a page without the permission, a supervisor page accessed from user mode
+// or an address beyond the virtual address space raises PageFault with the virtual address.
+// a handler that maps the page and returns with xret retries the access. an access can not
+// cross a page boundary (MisalignedAccess). the mpu (services/protection.rs) checks the
+// physical address. every core has its own root and tlb, ptroot and tlbflush are privileged.
+
+pub const DEFAULT_PAGE_SIZE: u64 = 4096;
+pub const DEFAULT_TLB_ENTRIES: usize = 16;
+pub const MAX_TLB_ENTRIES: usize = 256;
+
+pub const PTE_VALID: u64 = 1;
+pub const PTE_READ: u64 = 2;
+pub const PTE_WRITE: u64 = 4;
+pub const PTE_USER: u64 = 8;
+pub const PTE_ACCESSED: u64 = 16;
+pub const PTE_DIRTY: u64 = 32;
+
+#[derive(Debug, Clone, Copy)]
+struct TlbEntry {
+    page: u64,
+    frame: u64,
+    flags: u64,
+    used: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mmu {
+    pub page_size: u64,
+    pub root: Option<u64>,
+    capacity: usize,
+    tlb: Vec<TlbEntry>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    walks: u64,
+    page_faults: u64,
+    flushes: u64,
+}
+
+impl Default for Mmu {
+    fn default() -> Mmu {
+        Mmu {
+            page_size: DEFAULT_PAGE_SIZE,
+            root: None,
+            capacity: DEFAULT_TLB_ENTRIES,
+            tlb: Vec::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            walks: 0,
+            page_faults: 0,
+            flushes: 0,
+        }
+    }
+}
+
+impl Mmu {
+
+    pub fn new(config: &PagingConfig) -> Result<Mmu, String> {
+        let page_size = config.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !page_size.is_power_of_two() || !(256..=4096).contains(&page_size) {
+            return Err(format!("page_size must be a power of two from 256 to 4096, not {}", page_size));
+        }
+        let capacity = config.tlb_entries.unwrap_or(DEFAULT_TLB_ENTRIES);
+        if capacity == 0 || capacity > MAX_TLB_ENTRIES {
+            return Err(format!("a tlb has 1 to {} entries, not {}", MAX_TLB_ENTRIES, capacity));
+        }
+        Ok(Mmu { page_size, capacity, ..Mmu::default() })
+    }
+
+    // entries in one page table
+    fn table_entries(&self) -> u64 {
+        self.page_size / 8
+    }
+
+    fn lookup(&mut self, page: u64) -> Option<TlbEntry> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.tlb.iter_mut().find(|entry| entry.page == page)?;
+        entry.used = clock;
+        Some(*entry)
+    }
+
+    fn insert(&mut self, entry: TlbEntry) {
+        self.tlb.retain(|cached| cached.page != entry.page);
+        if self.tlb.len() >= self.capacity {
+            let oldest = self.tlb.iter().enumerate().min_by_key(|(_, cached)| cached.used).map(|(index, _)| index);
+            if let Some(index) = oldest {
+                self.tlb.swap_remove(index);
+            }
+        }
+        self.tlb.push(TlbEntry { used: self.clock, ..entry });
+    }
+
+    pub fn flush(&mut self) {
+        self.tlb.clear();
+        self.flushes += 1;
+    }
+
+    pub fn report(&self) -> TlbReport {
+        let accesses = self.hits + self.misses;
+        TlbReport {
+            page_size: self.page_size,
+            entries: self.capacity,
+            root: self.root,
+            hits: self.hits,
+            misses: self.misses,
+            hit_rate: if accesses == 0 { 0.0 } else { self.hits as f64 / accesses as f64 },
+            walks: self.walks,
+            page_faults: self.page_faults,
+            flushes: self.flushes,
+        }
+    }
+}
+
+fn access_name(write: bool) -> &'static str {
+    if write { "write" } else { "read" }
+}
+
+impl Machine {
+
+    fn page_fault(&mut self, address: u64, write: bool, reason: &str) -> Exception {
+        self.mmu.page_faults += 1;
+        let message = format!("page fault: {} at 0x{:x} ({})", access_name(write), address, reason);
+        Exception::at(ExceptionKind::PageFault, address, message)
+    }
+
+    // the page table entry at physical 'address', as the walker sees it
+    fn table_entry(&mut self, address: u64, virtual_address: u64, write: bool) -> Result<u64, Exception> {
+        if address.checked_add(8).is_none_or(|end| end > self.memory.len() as u64) {
+            return Err(self.page_fault(virtual_address, write, "page table outside of memory"));
+        }
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.memory[address as usize..address as usize + 8]);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn walk(&mut self, root: u64, address: u64, write: bool) -> Result<TlbEntry, Exception> {
+        // the walker reads memory, the stores of the core have to be there first
+        self.drain_stores();
+        self.mmu.walks += 1;
+        let (page_size, entries) = (self.mmu.page_size, self.mmu.table_entries());
+        let page = address / page_size;
+        if page >= entries * entries {
+            return Err(self.page_fault(address, write, "beyond the virtual address space"));
+        }
+
+        let first = self.table_entry(root + (page / entries) * 8, address, write)?;
+        if first & PTE_VALID == 0 {
+            return Err(self.page_fault(address, write, "no second level table"));
+        }
+        let at = (first & !(page_size - 1)) + (page % entries) * 8;
+        let second = self.table_entry(at, address, write)?;
+        if second & PTE_VALID == 0 {
+            return Err(self.page_fault(address, write, "page not mapped"));
+        }
+
+        let flags = second | PTE_ACCESSED | if write && second & PTE_WRITE != 0 { PTE_DIRTY } else { 0 };
+        if flags != second {
+            self.memory[at as usize..at as usize + 8].copy_from_slice(&flags.to_le_bytes());
+            self.invalidate_reservations(at as usize, 8);
+        }
+        let entry = TlbEntry { page, frame: second & !(page_size - 1), flags: flags & (page_size - 1), used: 0 };
+        self.mmu.insert(entry);
+        Ok(entry)
+    }
+
+    // the physical address of a data access, the access must not cross a page boundary
+    pub(crate) fn translate(&mut self, address: u64, size: usize, access: u8) -> Result<u64, Exception> {
+        let Some(root) = self.mmu.root else {
+            return Ok(address);
+        };
+        let write = access & PERMISSION_WRITE != 0;
+        let offset = address % self.mmu.page_size;
+        if offset + size as u64 > self.mmu.page_size {
+            let message = format!("access at 0x{:x} crosses a page boundary", address);
+            return Err(Exception::at(ExceptionKind::MisalignedAccess, address, message));
+        }
+
+        let page = address / self.mmu.page_size;
+        let entry = match self.mmu.lookup(page) {
+            // a clean page walks again to become dirty
+            Some(entry) if !write || entry.flags & PTE_DIRTY != 0 => {
+                self.mmu.hits += 1;
+                entry
+            },
+            _ => {
+                self.mmu.misses += 1;
+                self.walk(root, address, write)?
+            },
+        };
+
+        let needed = if write { PTE_WRITE } else { PTE_READ };
+        if entry.flags & needed == 0 {
+            return Err(self.page_fault(address, write, "no permission"));
+        }
+        if self.privilege == Privilege::User && entry.flags & PTE_USER == 0 {
+            return Err(self.page_fault(address, write, "supervisor page"));
+        }
+        Ok(entry.frame + offset)
+    }
+
+    // the physical ranges of a system call buffer, split at the page boundaries
+    pub(crate) fn translate_range(&mut self, address: u64, length: u64, access: u8) -> Result<Vec<(u64, u64)>, Exception> {
+        if self.mmu.root.is_none() {
+            return Ok(vec![(address, length)]);
+        }
+        let mut ranges = Vec::new();
+        let mut at = address;
+        let end = address.checked_add(length).ok_or_else(|| format!("buffer 0x{:x}+{} wraps around", address, length))?;
+        while at < end {
+            let chunk = (self.mmu.page_size - at % self.mmu.page_size).min(end - at);
+            ranges.push((self.translate(at, chunk as usize, access)?, chunk));
+            at += chunk;
+        }
+        Ok(ranges)
+    }
+
+    pub(crate) fn step_paging(&mut self, op: Op) -> Result<(), Exception> {
+        match op {
+            Op::PageTableRoot { src } => {
+                let root = self.registers[src];
+                if !root.is_multiple_of(self.mmu.page_size) {
+                    return Err(format!("page table root 0x{:x} is not page aligned", root).into());
+                }
+                self.drain_stores();
+                self.mmu.root = (root != 0).then_some(root);
+                self.mmu.flush();
+            },
+            Op::TlbFlush => self.mmu.flush(),
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::run::{RunRequest, RunStatus};
+    use crate::services::assembler::{assemble};
+
+    // 256 byte pages. the root table at 0x1000 points at one second level table at 0x1100,
+    // which maps virtual page 1 to 0x2000 (read/write), page 2 to 0x2100 (read only) and
+    // pages 0x10/0x11 onto the two tables so a program can edit them
+    const ROOT: u64 = 0x1000;
+    const TABLE: u64 = 0x1100;
+
+    fn write_word(machine: &mut Machine, address: u64, value: u64) {
+        machine.memory[address as usize..address as usize + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn read_word(machine: &Machine, address: u64) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&machine.memory[address as usize..address as usize + 8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn run(source: &str) -> (Machine, RunStatus) {
+        let program = assemble(source, &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions;
+        let mut machine = Machine::new(program);
+        let paging = PagingConfig { page_size: Some(256), tlb_entries: Some(4) };
+        machine.attach(&RunRequest { paging: Some(paging), ..Default::default() }).unwrap();
+        write_word(&mut machine, ROOT, TABLE | PTE_VALID);
+        write_word(&mut machine, TABLE + 8, 0x2000 | PTE_VALID | PTE_READ | PTE_WRITE);
+        write_word(&mut machine, TABLE + 2 * 8, 0x2100 | PTE_VALID | PTE_READ);
+        write_word(&mut machine, TABLE + 0x10 * 8, ROOT | PTE_VALID | PTE_READ | PTE_WRITE);
+        write_word(&mut machine, TABLE + 0x11 * 8, TABLE | PTE_VALID | PTE_READ | PTE_WRITE);
+        write_word(&mut machine, 0x2000, 111);
+        write_word(&mut machine, 0x2100, 222);
+        let status = machine.run(1_000);
+        (machine, status)
+    }
+
+    #[test]
+    fn walks_a_two_level_table_and_sets_accessed_and_dirty() {
+        let (machine, status) = run("
+            li r1, 0x1000
+            ptroot r1
+            ld r2, [r0 + 0x100]
+            ld r3, [r0 + 0x200]
+            add r4, r0, 5
+            st r4, [r0 + 0x108]
+            ld r5, [r0 + 0x108]
+            halt");
+        assert_eq!(status, RunStatus::Halted);
+        assert_eq!(&machine.registers[2..6], &[111, 222, 5, 5]);
+        assert_eq!(read_word(&machine, 0x2008), 5);
+
+        // both pages were accessed, only the written one is dirty
+        assert_eq!(read_word(&machine, TABLE + 8), 0x2000 | PTE_VALID | PTE_READ | PTE_WRITE | PTE_ACCESSED | PTE_DIRTY);
+        assert_eq!(read_word(&machine, TABLE + 2 * 8), 0x2100 | PTE_VALID | PTE_READ | PTE_ACCESSED);
+
+        // the store to the clean page walked again, the last load hit
+        let report = machine.mmu.report();
+        assert_eq!(report.root, Some(ROOT));
+        assert_eq!((report.hits, report.misses, report.walks, report.page_faults), (1, 3, 3, 0));
+    }
+
+    #[test]
+    fn tlbflush_and_ptroot_drop_stale_entries() {
+        // remaps page 1 to 0x2100 through the table's own mapping at 0x1100 + 0x1000
+        let (machine, status) = run("
+            li r1, 0x1000
+            ptroot r1
+            ld r2, [r0 + 0x100]
+            li r6, 0x2103
+            st r6, [r0 + 0x1108]
+            ld r3, [r0 + 0x100]
+            tlbflush
+            ld r4, [r0 + 0x100]
+            li r6, 0x2007
+            st r6, [r0 + 0x1108]
+            ld r5, [r0 + 0x100]
+            ptroot r1
+            ld r7, [r0 + 0x100]
+            ptroot r0
+            ld r8, [r0 + 0x2100]
+            halt");
+        assert_eq!(status, RunStatus::Halted);
+        // the tlb keeps the old frame until it is flushed
+        assert_eq!(&machine.registers[2..6], &[111, 111, 222, 222]);
+        assert_eq!(machine.registers[7], 111);
+        // ptroot 0 switches translation off, 0x2100 is physical again
+        assert_eq!(machine.registers[8], 222);
+
+        let report = machine.mmu.report();
+        assert_eq!(report.root, None);
+        // the first ptroot flushes too
+        assert_eq!(report.flushes, 4);
+    }
+
+    #[test]
+    fn a_page_fault_reports_the_virtual_address() {
+        for (access, address, reason) in [
+            ("ld r2, [r0 + 0x308]", 0x308, "page not mapped"),
+            ("st r2, [r0 + 0x210]", 0x210, "no permission"),
+            ("ld r2, [r0 + 0x2000]", 0x2000, "no second level table"),
+        ] {
+            let (machine, status) = run(&format!("li r1, 0x1000\nptroot r1\n{}\nhalt", access));
+            assert_eq!(status, RunStatus::Faulted, "{}", access);
+            let exception = machine.exception.as_ref().unwrap();
+            assert_eq!(exception.kind, ExceptionKind::PageFault);
+            assert_eq!(exception.address, Some(address));
+            assert_eq!(exception.pc, 2);
+            assert!(exception.message.contains(reason), "{}", exception.message);
+            assert_eq!(machine.mmu.report().page_faults, 1);
+        }
+    }
+
+    #[test]
+    fn a_handler_maps_the_page_and_the_access_is_retried() {
+        // page 3 is missing, the handler maps it onto 0x2000
+        let (machine, status) = run("
+            add r1, r0, 9
+            xvec r1, handler
+            li r1, 0x1000
+            ptroot r1
+            ld r2, [r0 + 0x300]
+            halt
+        handler:
+            xaddr r5
+            li r6, 0x2003
+            st r6, [r0 + 0x1118]
+            xret");
+        assert_eq!(status, RunStatus::Halted);
+        assert_eq!(machine.registers[2], 111);
+        assert_eq!(machine.registers[5], 0x300);
+        assert_eq!(machine.exceptions.cause, 9);
+        assert_eq!(machine.mmu.report().page_faults, 1);
+    }
+}
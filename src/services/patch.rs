@@ -0,0 +1,201 @@
+use crate::models::instruction::{Instruction};
+use crate::models::patch::{PatchResult};
+use crate::services::executor::{has_target, parse_imdval};
+use crate::services::decoder::{decode};
+
+// edits of a program in place. targets are instruction indexes, so an edit that moves
+// instructions moves the targets that point at them along:
+//
+// - inserting n instructions at 'index' adds n to every target >= index, the inserted
+//   instructions go in front of the one that was at 'index'. their own targets are taken
+//   as indexes into the edited program.
+// - deleting the instruction at 'index' subtracts 1 from every target > index, targets of
+//   the deleted instruction continue with the one that follows it.
+// - replacing an instruction moves nothing.
+//
+// the end of the program (target == length) stays the end. an edit is rejected when it
+// leaves an instruction the decoder refuses, unless that instruction was already invalid
+// before the edit (a program loaded with /load is not checked).
+
+pub enum Edit {
+    Replace(Instruction),
+    Insert(Vec<Instruction>),
+    Delete,
+}
+
+pub fn target_of(instruction: &Instruction) -> Option<usize> {
+    match parse_imdval(&instruction.imdval) {
+        Ok(Some(target)) if target >= 0 => Some(target as usize),
+        _ => None,
+    }
+}
+
+// keeps the notation the target was written in
+pub fn with_target(imdval: &str, target: usize) -> String {
+    if imdval.trim().starts_with("0x") {
+        format!("0x{:x}", target)
+    } else {
+        target.to_string()
+    }
+}
+
+pub fn apply(code: &[Instruction], index: usize, edit: Edit) -> Result<(Vec<Instruction>, PatchResult), String> {
+
+    let length = code.len();
+    let last = match edit {
+        Edit::Insert(_) => length,
+        _ => length.checked_sub(1).ok_or("the program is empty")?,
+    };
+    if index > last {
+        return Err(format!("no instruction {} in a program of {}", index, length));
+    }
+    if matches!(&edit, Edit::Insert(inserted) if inserted.is_empty()) {
+        return Err("nothing to insert".to_string());
+    }
+
+    let moved = |target: usize| -> usize {
+        match &edit {
+            Edit::Insert(inserted) if target >= index => target + inserted.len(),
+            Edit::Delete if target > index => target - 1,
+            _ => target,
+        }
+    };
+
+    // (instruction, its index before the edit)
+    let mut patched: Vec<(Instruction, Option<usize>)> = Vec::with_capacity(length + 1);
+    let mut retargeted = Vec::new();
+    for (at, instruction) in code.iter().enumerate() {
+        if at == index {
+            match &edit {
+                Edit::Replace(replacement) => {
+                    patched.push((replacement.clone(), None));
+                    continue;
+                },
+                Edit::Insert(inserted) => patched.extend(inserted.iter().map(|instruction| (instruction.clone(), None))),
+                Edit::Delete => continue,
+            }
+        }
+
+        let mut instruction = instruction.clone();
+        if has_target(&instruction.opcode) {
+            if let Some(target) = target_of(&instruction).filter(|target| *target <= length) {
+                if moved(target) != target {
+                    instruction.imdval = with_target(&instruction.imdval, moved(target));
+                    retargeted.push(patched.len());
+                }
+            }
+        }
+        patched.push((instruction, Some(at)));
+    }
+    if let Edit::Insert(inserted) = &edit {
+        if index == length {
+            patched.extend(inserted.iter().map(|instruction| (instruction.clone(), None)));
+        }
+    }
+
+    let new_length = patched.len();
+    for (at, (instruction, origin)) in patched.iter().enumerate() {
+        if let Err(e) = decode(instruction, new_length) {
+            let was_valid = origin.is_none_or(|origin| decode(&code[origin], length).is_ok());
+            if was_valid {
+                return Err(format!("instruction {} would be invalid: {}", at, e));
+            }
+        }
+    }
+
+    let message = match &edit {
+        Edit::Replace(_) => format!("instruction {} replaced", index),
+        Edit::Insert(inserted) if inserted.len() == 1 => format!("1 instruction inserted at {}", index),
+        Edit::Insert(inserted) => format!("{} instructions inserted at {}", inserted.len(), index),
+        Edit::Delete => format!("instruction {} deleted", index),
+    };
+    let result = PatchResult {
+        message,
+        length: new_length,
+        retargeted,
+    };
+    Ok((patched.into_iter().map(|(instruction, _)| instruction).collect(), result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(opcode: &str, imdval: &str) -> Instruction {
+        Instruction { opcode: opcode.to_string(), imdval: imdval.to_string(), regsrc: 1, regext: 0, regdst: 1 }
+    }
+
+    fn imdvals(code: &[Instruction]) -> Vec<&str> {
+        code.iter().map(|instruction| instruction.imdval.as_str()).collect()
+    }
+
+    //  0: add   1
+    //  1: beq   0x4     forward, past the edits at 2
+    //  2: add   2
+    //  3: jmp   1       backward, before the edits at 2
+    //  4: bne   2       at the edit point
+    //  5: jmp   6       the end of the program
+    fn program() -> Vec<Instruction> {
+        vec![
+            instruction("add", "1"),
+            instruction("beq", "0x4"),
+            instruction("add", "2"),
+            instruction("jmp", "1"),
+            instruction("bne", "2"),
+            instruction("jmp", "6"),
+        ]
+    }
+
+    #[test]
+    fn insert_moves_targets_at_and_after_the_edit_point() {
+        let (code, result) = apply(&program(), 2, Edit::Insert(vec![instruction("nop", ""), instruction("jmp", "7")])).unwrap();
+        assert_eq!(imdvals(&code), vec!["1", "0x6", "", "7", "2", "1", "4", "8"]);
+        assert_eq!(result.retargeted, vec![1, 6, 7]);
+        assert_eq!(result.length, 8);
+
+        // at the end, nothing moves but the end itself
+        let (code, result) = apply(&program(), 6, Edit::Insert(vec![instruction("halt", "")])).unwrap();
+        assert_eq!(imdvals(&code), vec!["1", "0x4", "2", "1", "2", "7", ""]);
+        assert_eq!(result.retargeted, vec![5]);
+    }
+
+    #[test]
+    fn delete_moves_targets_after_the_edit_point() {
+        let (code, result) = apply(&program(), 2, Edit::Delete).unwrap();
+        // bne pointed at the deleted instruction and continues with the one after it
+        assert_eq!(imdvals(&code), vec!["1", "0x3", "1", "2", "5"]);
+        assert_eq!(result.retargeted, vec![1, 4]);
+
+        // deleting a branch target at the start
+        let (code, _) = apply(&program(), 0, Edit::Delete).unwrap();
+        assert_eq!(imdvals(&code), vec!["0x3", "2", "0", "1", "5"]);
+    }
+
+    #[test]
+    fn replace_moves_nothing() {
+        let (code, result) = apply(&program(), 2, Edit::Replace(instruction("sub", "3"))).unwrap();
+        assert_eq!(imdvals(&code), vec!["1", "0x4", "3", "1", "2", "6"]);
+        assert!(result.retargeted.is_empty());
+    }
+
+    #[test]
+    fn targets_keep_their_notation() {
+        assert_eq!(with_target("0x4", 10), "0xa");
+        assert_eq!(with_target(" 0x0", 3), "0x3");
+        assert_eq!(with_target("4", 10), "10");
+    }
+
+    #[test]
+    fn rejects_edits_that_leave_invalid_instructions() {
+        assert!(apply(&program(), 2, Edit::Replace(instruction("jmp", "9"))).is_err());
+        assert!(apply(&program(), 2, Edit::Insert(vec![instruction("frobnicate", "")])).is_err());
+        assert!(apply(&program(), 7, Edit::Insert(vec![instruction("nop", "")])).is_err());
+        assert!(apply(&program(), 6, Edit::Delete).is_err());
+        assert!(apply(&[], 0, Edit::Delete).is_err());
+
+        // an instruction that was invalid before the edit does not stand in the way
+        let mut code = program();
+        code[2] = instruction("frobnicate", "");
+        assert!(apply(&code, 0, Edit::Replace(instruction("add", "5"))).is_ok());
+    }
+}
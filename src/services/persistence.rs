@@ -0,0 +1,286 @@
+use std::fs;
+use std::path::{PathBuf};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::{web};
+
+use crate::services::state::{InstreamState};
+use crate::services::library::{write_private};
+use crate::routes::worker::{dispatch_work};
+use crate::models::command::{CommandEnum, RequestMessage};
+use crate::models::history::{VersionOrigin};
+use crate::models::persistence::{ServerSnapshot};
+
+// the session key, the loaded program, the running workers and the commands waiting in the
+// worker channels are kept in a snapshot file. a server stopped at any point (killed
+// included) starts again from the last snapshot:
+//
+// - the session key stays valid, clients keep working without a new GET /session_key
+// - the program is loaded again, as version 1 of a new history
+// - pending commands are queued again, in their order
+// - workers that were running are started again, after that, so they receive the commands
+//
+// whatever changes one of them calls StateStore::changed, which only wakes the saver thread.
+// the saver waits SAVE_DELAY_MS for more changes, then writes the file if the state differs
+// from the last one written. a command queued and received in that time writes nothing,
+// the workers never wait for the disk. a server killed less than SAVE_DELAY_MS after a
+// change starts from the state before it.
+//
+// the file holds the session key, it is created readable by its owner only. it is named by
+// INSTREAMS_STATE, STATE_FILE when it is not set.
+
+pub const STATE_FILE: &str = "instreams-state.json";
+pub const STATE_VARIABLE: &str = "INSTREAMS_STATE";
+pub const SAVE_DELAY_MS: u64 = 200;
+
+pub struct StateStore {
+    path: PathBuf,
+    // set by changed(), taken by the saver thread
+    dirty: Mutex<bool>,
+    wake: Condvar,
+    // the snapshot last written, one write at a time
+    written: Mutex<Option<ServerSnapshot>>,
+}
+
+// the channels carry &'static str, commands read back from a snapshot are mapped onto them
+fn command_name(command: &str) -> &'static str {
+    match CommandEnum::from_str(command) {
+        Ok(CommandEnum::Stop) => "Stop",
+        Ok(CommandEnum::Start) => "Start",
+        Ok(CommandEnum::Restart) => "Restart",
+        Ok(CommandEnum::Terminate) => "Terminate",
+        Ok(CommandEnum::UpdateStatus) => "UpdateStatus",
+        Err(_) => "_",
+    }
+}
+
+// sends a command to a worker and keeps it as pending until the worker receives it
+pub fn queue_command(state: &InstreamState, worker: &str, sender: &Mutex<mpsc::Sender<&'static str>>, command: &'static str) {
+    {
+        let sender = sender.lock().unwrap();
+        // pending before it is sent, the worker takes it off again when it receives it
+        state.pending_commands.lock().unwrap().entry(worker.to_string()).or_default().push_back(command);
+        sender.send(command).expect("Send failed");
+    }
+    state.store.changed();
+}
+
+// called by a worker for every command it receives
+pub fn command_received(state: &InstreamState, worker: &str) {
+    if let Some(pending) = state.pending_commands.lock().unwrap().get_mut(worker) {
+        pending.pop_front();
+    }
+    state.store.changed();
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+impl StateStore {
+
+    pub fn from_environment() -> StateStore {
+        let path = std::env::var(STATE_VARIABLE).unwrap_or_else(|_| STATE_FILE.to_string());
+        StateStore { path: PathBuf::from(path), dirty: Mutex::new(false), wake: Condvar::new(), written: Mutex::new(None) }
+    }
+
+    pub fn snapshot(state: &InstreamState) -> ServerSnapshot {
+        let running = [
+            ("StartWorker10ms", &state.worker10running),
+            ("StartWorker25ms", &state.worker25running),
+            ("StartWorker50ms", &state.worker50running),
+            ("StartWorker100ms", &state.worker100running),
+            ("StartWorker250ms", &state.worker250running),
+        ];
+        ServerSnapshot {
+            master_key: state.master_key.lock().unwrap().to_string(),
+            code: state.code_segment.lock().unwrap().to_vec(),
+            workers: running.iter()
+                .filter(|(_, running)| *running.lock().unwrap())
+                .map(|(work, _)| work.to_string())
+                .collect(),
+            pending: state.pending_commands.lock().unwrap().iter()
+                .filter(|(_, commands)| !commands.is_empty())
+                .map(|(worker, commands)| (worker.clone(), commands.iter().map(|command| command.to_string()).collect()))
+                .collect(),
+            saved_at: now(),
+        }
+    }
+
+    // the state changed, the saver writes it soon. cheap, callers may hold their own locks
+    // but not one snapshot() takes
+    pub fn changed(&self) {
+        *self.dirty.lock().unwrap() = true;
+        self.wake.notify_one();
+    }
+
+    // writes the snapshot now, unless it is the one written last. a failed write is reported
+    // and otherwise ignored, the server keeps running on the state in memory
+    pub fn save(&self, state: &InstreamState) {
+        self.write(StateStore::snapshot(state));
+    }
+
+    fn write(&self, mut snapshot: ServerSnapshot) {
+        let mut written = self.written.lock().unwrap();
+        if let Some(last) = written.as_ref() {
+            snapshot.saved_at = last.saved_at;
+            if snapshot == *last {
+                return;
+            }
+            snapshot.saved_at = now();
+        }
+        let saved = serde_json::to_string_pretty(&snapshot)
+            .map_err(|e| e.to_string())
+            .and_then(|text| write_private(&self.path, text.as_bytes()));
+        match saved {
+            Ok(()) => *written = Some(snapshot),
+            Err(e) => eprintln!("::: state not saved: {}", e),
+        }
+    }
+
+    // None when there is no snapshot yet. an unreadable one is reported and not used.
+    pub fn load(&self) -> Option<ServerSnapshot> {
+        let text = fs::read_to_string(&self.path).ok()?;
+        match serde_json::from_str(&text) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                eprintln!("::: {} not restored: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+// the thread writing the snapshots, started once the state is restored
+pub fn start_saver(state: Arc<InstreamState>) {
+    thread::spawn(move || loop {
+        {
+            let mut dirty = state.store.dirty.lock().unwrap();
+            while !*dirty {
+                dirty = state.store.wake.wait(dirty).unwrap();
+            }
+            *dirty = false;
+        }
+        // changes made meanwhile go into the same snapshot
+        thread::sleep(Duration::from_millis(SAVE_DELAY_MS));
+        state.store.save(&state);
+    });
+}
+
+// brings a freshly started server back to the last snapshot
+pub fn restore(data: web::Data<Arc<InstreamState>>) {
+
+    let snapshot = match data.store.load() {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+    println!(":: restoring state saved at {}", snapshot.saved_at);
+
+    *data.master_key.lock().unwrap() = snapshot.master_key.clone();
+
+    if !snapshot.code.is_empty() {
+        let mut code = data.code_segment.lock().unwrap();
+        *code = snapshot.code.clone();
+        data.history.lock().unwrap().record(&code, VersionOrigin::Restore, None);
+    }
+
+    for (worker, commands) in &snapshot.pending {
+        let sender = match worker.as_str() {
+            "Worker10" => &data.sender10,
+            "Worker25" => &data.sender25,
+            "Worker50" => &data.sender50,
+            "Worker100" => &data.sender100,
+            "Worker250" => &data.sender250,
+            _ => {
+                eprintln!("::: pending commands of unknown worker {} dropped", worker);
+                continue;
+            }
+        };
+        for command in commands {
+            queue_command(&data, worker, sender, command_name(command));
+        }
+    }
+
+    for work in &snapshot.workers {
+        let request = RequestMessage {
+            key: snapshot.master_key.clone(),
+            message: work.clone(),
+        };
+        if let Err(e) = dispatch_work(&request, data.clone()) {
+            eprintln!("::: {} not restarted: {}", work, e);
+        }
+    }
+
+    // the saver is not running yet, nothing was written while the state was put back
+    // together. dispatch_work has set the running flags, the snapshot names the same
+    // workers again
+    data.store.save(&data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::instruction::{Instruction};
+
+    fn store(name: &str) -> StateStore {
+        let path = std::env::temp_dir().join(format!("instreams-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        StateStore { path, dirty: Mutex::new(false), wake: Condvar::new(), written: Mutex::new(None) }
+    }
+
+    fn snapshot() -> ServerSnapshot {
+        ServerSnapshot {
+            master_key: "3f2a9c1e".to_string(),
+            code: vec![Instruction { opcode: "add".to_string(), imdval: "0x1".to_string(), regsrc: 1, regext: 0, regdst: 1 }],
+            workers: vec!["StartWorker100ms".to_string()],
+            saved_at: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let store = store("round-trip");
+        store.write(snapshot());
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.code, snapshot().code);
+        assert_eq!(loaded.master_key, "3f2a9c1e");
+        let _ = fs::remove_file(&store.path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn snapshot_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let store = store("private");
+        store.write(snapshot());
+        let mode = fs::metadata(&store.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let _ = fs::remove_file(&store.path);
+    }
+
+    #[test]
+    fn unchanged_state_is_not_written() {
+        let store = store("unchanged");
+        store.write(snapshot());
+        fs::remove_file(&store.path).unwrap();
+
+        // only saved_at differs
+        store.write(ServerSnapshot { saved_at: 2, ..snapshot() });
+        assert!(store.load().is_none());
+
+        store.write(ServerSnapshot { master_key: "0".to_string(), ..snapshot() });
+        assert_eq!(store.load().unwrap().master_key, "0");
+        let _ = fs::remove_file(&store.path);
+    }
+
+    #[test]
+    fn snapshot_without_code_loads() {
+        let store = store("no-code");
+        fs::write(&store.path, r#"{"master_key":"0","workers":[],"pending":{},"saved_at":1}"#).unwrap();
+        assert!(store.load().unwrap().code.is_empty());
+        let _ = fs::remove_file(&store.path);
+    }
+}
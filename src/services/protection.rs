@@ -0,0 +1,242 @@
+use crate::models::exception::{ExceptionKind};
+use crate::models::protection::{Privilege, RegionReport};
+use crate::services::executor::{Machine};
+use crate::services::exception::{Exception};
+use crate::services::decoder::{Op};
+
+// privilege levels and the memory protection unit. a core starts in supervisor mode, where
+// it may do anything. in user mode every instruction fetch and data access has to fall into
+// an MPU region that permits it, and the privileged opcodes (halt, wfi, the interrupt and
+// exception controller setup, iret / xret, the MPU and paging opcodes and ujmp) raise
+// PrivilegedInstruction.
+//
+//  mpubase     region[imdval] covers rs .. rs + rx
+//  mpuperm     region[imdval] permissions = rs      (PERMISSION_*, 0 switches it off)
+//  ujmp        pc = imdval, continue in user mode
+//  priv        rd = 0 in supervisor mode, 1 in user mode
+//
+// code and data have address spaces of their own: the read and write permissions of a region
+// apply to the data addresses rs .. rs + rx, execute to the instruction indexes in that
+// range. the lowest region that contains the first byte of an access decides, and it has to
+// contain the whole access. addresses above the data memory reach the devices, a region can
+// hand those to user mode as well.
+//
+// entering an interrupt or exception handler switches to supervisor mode, iret and xret
+// return to the mode that was interrupted. an ecall in user mode raises the Syscall
+// exception when a handler is installed for it (xepc is the ecall, skip it before xret),
+// otherwise the system call is served directly and its buffers are checked against the
+// regions. a violation raises ProtectionFault with the region it violated, none when no
+// region contains the address. every core has its own privilege level and regions.
+
+pub const MPU_REGIONS: usize = 8;
+
+pub const PERMISSION_READ: u8 = 1;
+pub const PERMISSION_WRITE: u8 = 2;
+pub const PERMISSION_EXECUTE: u8 = 4;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Region {
+    pub base: u64,
+    pub size: u64,
+    pub permissions: u8,
+}
+
+impl Region {
+    fn contains(&self, address: u64) -> bool {
+        self.permissions != 0 && address >= self.base && address - self.base < self.size
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mpu {
+    pub regions: [Region; MPU_REGIONS],
+}
+
+impl Mpu {
+
+    pub fn new() -> Mpu {
+        Mpu::default()
+    }
+
+    // Err holds the region that was violated, None when no region contains the address
+    pub fn check(&self, address: u64, size: u64, access: u8) -> Result<(), Option<usize>> {
+        let Some(index) = self.regions.iter().position(|region| region.contains(address)) else {
+            return Err(None);
+        };
+        let region = &self.regions[index];
+        let inside = (address - region.base).checked_add(size).is_some_and(|end| end <= region.size);
+        if !inside || region.permissions & access != access {
+            return Err(Some(index));
+        }
+        Ok(())
+    }
+
+    pub fn report(&self) -> Vec<RegionReport> {
+        self.regions.iter().enumerate()
+            .filter(|(_, region)| region.permissions != 0)
+            .map(|(index, region)| RegionReport {
+                index,
+                base: region.base,
+                size: region.size,
+                read: region.permissions & PERMISSION_READ != 0,
+                write: region.permissions & PERMISSION_WRITE != 0,
+                execute: region.permissions & PERMISSION_EXECUTE != 0,
+            })
+            .collect()
+    }
+}
+
+pub fn check_region(region: i64) -> Result<usize, String> {
+    if region < 0 || region as usize >= MPU_REGIONS {
+        return Err(format!("invalid mpu region {}", region));
+    }
+    Ok(region as usize)
+}
+
+fn access_name(access: u8) -> &'static str {
+    match access {
+        PERMISSION_READ => "read",
+        PERMISSION_WRITE => "write",
+        PERMISSION_EXECUTE => "fetch",
+        _ => "atomic access",
+    }
+}
+
+impl Machine {
+
+    // whether the executing core may access 'size' bytes at 'address'
+    #[inline(always)]
+    pub(crate) fn protect(&self, address: u64, size: usize, access: u8) -> Result<(), Exception> {
+        if self.privilege == Privilege::Supervisor {
+            return Ok(());
+        }
+        self.mpu.check(address, size as u64, access).map_err(|region| {
+            let message = match region {
+                Some(region) => format!("{} at 0x{:x} violates mpu region {}", access_name(access), address, region),
+                None => format!("{} at 0x{:x} outside of the mpu regions", access_name(access), address),
+            };
+            Exception { region, ..Exception::at(ExceptionKind::ProtectionFault, address, message) }
+        })
+    }
+
+    // the checks of user mode before an instruction executes
+    pub(crate) fn check_user(&self, pc: usize, op: Op) -> Result<(), Exception> {
+        self.protect(pc as u64, 1, PERMISSION_EXECUTE)?;
+        if op.is_privileged() {
+            let message = format!("'{}' is privileged", self.code[pc].opcode);
+            return Err(Exception::new(ExceptionKind::PrivilegedInstruction, message));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn step_protection(&mut self, op: Op, next_pc: &mut usize) {
+        match op {
+            Op::RegionBounds { region, base, size } => {
+                self.mpu.regions[region].base = self.registers[base];
+                self.mpu.regions[region].size = self.registers[size];
+            },
+            Op::RegionPermissions { region, src } => {
+                let permissions = self.registers[src] as u8 & (PERMISSION_READ | PERMISSION_WRITE | PERMISSION_EXECUTE);
+                self.mpu.regions[region].permissions = permissions;
+            },
+            Op::UserJump { target } => {
+                self.privilege = Privilege::User;
+                *next_pc = target;
+            },
+            Op::ReadPrivilege { dst } => self.set(dst, self.privilege as u64),
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::run::{RunStatus};
+    use crate::services::assembler::{assemble};
+
+    // region 0 lets user mode execute the first 100 instructions, region 1 read 0x1000..0x10fc
+    const SETUP: &str = "
+            li r2, 100
+            mpubase r0, r2, 0
+            li r3, 4
+            mpuperm r3, 0
+            li r1, 0x1000
+            li r2, 0xfc
+            mpubase r1, r2, 1
+            li r3, 1
+            mpuperm r3, 1";
+
+    fn run(source: &str) -> (Machine, RunStatus) {
+        let source = format!("{}\n{}", SETUP, source);
+        let program = assemble(&source, &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions;
+        let mut machine = Machine::new(program);
+        let status = machine.run(1_000);
+        (machine, status)
+    }
+
+    #[test]
+    fn a_denied_access_reports_its_region() {
+        for (access, address, region) in [
+            ("st r5, [r1 + 8]", 0x1008, Some(1)),
+            // runs past the end of the region
+            ("ld r5, [r1 + 0xf8]", 0x10f8, Some(1)),
+            ("ld r5, [r1 + 0x2000]", 0x3000, None),
+        ] {
+            let (machine, status) = run(&format!("
+                ujmp user
+            user:
+                ld r5, [r1 + 0]
+                {}
+                ecall 0", access));
+            assert_eq!(status, RunStatus::Faulted, "{}", access);
+            assert_eq!(machine.privilege, Privilege::User);
+            let exception = machine.exception.as_ref().unwrap();
+            assert_eq!(exception.kind, ExceptionKind::ProtectionFault);
+            assert_eq!(exception.address, Some(address));
+            assert_eq!(exception.region, region, "{}", access);
+        }
+
+        // the same accesses are fine in supervisor mode
+        let (machine, status) = run("st r3, [r1 + 8]\nld r5, [r1 + 0x2000]\nhalt");
+        assert_eq!(status, RunStatus::Halted);
+        assert!(machine.exception.is_none());
+        assert_eq!(machine.mpu.report().len(), 2);
+    }
+
+    #[test]
+    fn privileged_opcodes_fault_in_user_mode() {
+        for opcode in ["di", "halt", "mpuperm r0, 1", "tlbflush"] {
+            let (machine, status) = run(&format!("ujmp user\nuser:\n{}\necall 0", opcode));
+            assert_eq!(status, RunStatus::Faulted, "{}", opcode);
+            let exception = machine.exception.as_ref().unwrap();
+            assert_eq!(exception.kind, ExceptionKind::PrivilegedInstruction, "{}", opcode);
+            assert_eq!(exception.pc, 10);
+        }
+    }
+
+    #[test]
+    fn ujmp_and_xret_switch_the_privilege_level() {
+        let (machine, status) = run("
+            li r1, 7
+            xvec r1, handler
+            priv r4
+            ujmp user
+        user:
+            priv r5
+            di
+            priv r7
+            ecall 0
+        handler:
+            priv r6
+            xepc r8
+            add r8, r8, 1
+            xsetepc r8
+            xret");
+        assert_eq!(status, RunStatus::Halted);
+        // supervisor, user, the handler in supervisor mode, back in user mode
+        assert_eq!(&machine.registers[4..8], &[0, 1, 0, 1]);
+        assert_eq!(machine.privilege, Privilege::User);
+        assert_eq!(machine.exceptions.previous, Privilege::User);
+    }
+}
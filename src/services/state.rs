@@ -1,13 +1,34 @@
 use std::sync::{Mutex};
+use std::collections::{HashMap, VecDeque};
 use std::sync::mpsc;
 
 use crate::models::instruction::{Instruction};
+use crate::models::run::{RunResult};
+use crate::services::context::{SharedContext};
+use crate::services::events::{EventBus};
+use crate::services::history::{ProgramHistory};
+use crate::services::library::{ProgramLibrary};
+use crate::services::persistence::{StateStore};
 
 // #[derive(Default)]
 pub struct InstreamState {
 
     pub master_key: Mutex<String>,
     pub code_segment: Mutex<Vec<Instruction>>, 
+    // result of the most recent run of code_segment, cleared when a new program is loaded
+    pub last_run: Mutex<Option<RunResult>>,
+    // live runs advanced by the worker ticks, by run id
+    pub runs: Mutex<HashMap<String, SharedContext>>,
+    // worker and run events for GET /events
+    pub events: EventBus,
+    // versions of code_segment for GET /programs/history, lock it after code_segment
+    pub history: Mutex<ProgramHistory>,
+    // named programs on disk, lock it after code_segment
+    pub library: Mutex<ProgramLibrary>,
+    // commands sent to a worker and not received yet, by worker ("Worker100")
+    pub pending_commands: Mutex<HashMap<String, VecDeque<&'static str>>>,
+    // snapshot of the key, program, workers and pending commands, see services/persistence.rs
+    pub store: StateStore,
 
     pub worker10running: Mutex<bool>,
     pub worker25running: Mutex<bool>,
@@ -25,4 +46,13 @@ pub struct InstreamState {
     pub receiver50: Mutex<mpsc::Receiver<&'static str>>,
     pub receiver100: Mutex<mpsc::Receiver<&'static str>>,
     pub receiver250: Mutex<mpsc::Receiver<&'static str>>,
-}
\ No newline at end of file
+}
+
+impl InstreamState {
+
+    // the key is "0" until GET /session_key makes one, nobody can subscribe with it before that
+    pub fn key_matches(&self, key: &str) -> bool {
+        let master_key = self.master_key.lock().unwrap();
+        *master_key != "0" && key == *master_key
+    }
+}
@@ -0,0 +1,187 @@
+use crate::services::executor::{Machine, MachineState};
+use crate::services::protection::{PERMISSION_READ, PERMISSION_WRITE};
+use std::ops::Range;
+
+// the system call interface. 'ecall' (or 'syscall') takes the service number from its imdval,
+// or from r1 when there is none. arguments are passed in r2, r3 and r4, the result is
+// returned in r1. a negative result is an error code, the error is also recorded in the
+// syscall_errors of the run.
+//
+//  0  exit     r2 = exit code                         halts the machine
+//  1  write    r2 = stream, r3 = address, r4 = length  r1 = bytes written
+//  2  read     r2 = stream, r3 = address, r4 = length  r1 = bytes read, 0 at the end of input
+//  3  time                                             r1 = worker ticks seen by the run
+//  4  yield                                            a live run gives up the rest of its slice
+//  5  tryread  r2 = stream, r3 = address, r4 = length  like read, but never blocks
+//
+// stream 0 is the input of the run, 1 the console and 2 the error console. read blocks until
+// input is available or the input is closed, a blocked run continues when input arrives
+// (POST /runs/{id}/input). tryread returns -4 instead of blocking. the input of /run is
+// closed once the run starts, so reads there never block. in user mode the buffers have to
+// lie in mpu regions that permit the access (services/protection.rs). with virtual memory
+// (services/paging.rs) the buffers are virtual, an unmapped buffer fails with -3.
+
+pub const SYSCALL_RESULT: u8 = 1;
+pub const SYSCALL_ARGUMENTS: [u8; 3] = [2, 3, 4];
+
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_READ: u64 = 2;
+pub const SYS_TIME: u64 = 3;
+pub const SYS_YIELD: u64 = 4;
+pub const SYS_TRY_READ: u64 = 5;
+
+pub const ERROR_SERVICE: i64 = -1;
+pub const ERROR_STREAM: i64 = -2;
+pub const ERROR_BUFFER: i64 = -3;
+pub const ERROR_AGAIN: i64 = -4;
+
+pub const STREAM_INPUT: u64 = 0;
+pub const STREAM_CONSOLE: u64 = 1;
+pub const STREAM_ERROR: u64 = 2;
+
+// the argument registers a service reads, None for an unknown service
+pub fn syscall_arguments(number: u64) -> Option<&'static [u8]> {
+    match number {
+        SYS_EXIT => Some(&SYSCALL_ARGUMENTS[..1]),
+        SYS_WRITE | SYS_READ | SYS_TRY_READ => Some(&SYSCALL_ARGUMENTS),
+        SYS_TIME | SYS_YIELD => Some(&[]),
+        _ => None,
+    }
+}
+
+impl Machine {
+
+    fn argument(&self, index: usize) -> u64 {
+        self.registers[SYSCALL_ARGUMENTS[index] as usize]
+    }
+
+    // the data memory ranges of a syscall buffer, one for every page it touches
+    fn buffer(&mut self, name: &str, address: u64, length: u64, access: u8) -> Result<Vec<Range<usize>>, (i64, String)> {
+        let pages = self.translate_range(address, length, access)
+            .map_err(|exception| (ERROR_BUFFER, format!("{}: buffer 0x{:x}+{}: {}", name, address, length, exception.message)))?;
+        pages.into_iter().map(|(start, size)| {
+            let Some(end) = start.checked_add(size).filter(|end| *end <= self.memory.len() as u64) else {
+                return Err((ERROR_BUFFER, format!("{}: buffer 0x{:x}+{} out of bounds", name, address, length)));
+            };
+            if size > 0 && self.protect(start, size as usize, access).is_err() {
+                return Err((ERROR_BUFFER, format!("{}: buffer 0x{:x}+{} is protected", name, address, length)));
+            }
+            Ok(start as usize..end as usize)
+        }).collect()
+    }
+
+    pub(crate) fn syscall(&mut self, pc: usize, number: u64) {
+        self.drain_stores();
+        let result = match number {
+            SYS_EXIT => {
+                self.exit_code = Some(self.argument(0) as i64);
+                self.state = MachineState::Halted;
+                Ok(0)
+            },
+            SYS_WRITE => self.sys_write(),
+            SYS_READ | SYS_TRY_READ => {
+                if self.argument(0) == STREAM_INPUT && !self.devices.input_ready() {
+                    if number == SYS_READ {
+                        // executed again once there is input
+                        self.state = MachineState::Blocked;
+                        return;
+                    }
+                    Err((ERROR_AGAIN, String::new()))
+                } else {
+                    self.sys_read()
+                }
+            },
+            SYS_TIME => Ok(self.ticks as i64),
+            SYS_YIELD => {
+                self.yielded = true;
+                Ok(0)
+            },
+            _ => Err((ERROR_SERVICE, format!("unknown syscall {}", number))),
+        };
+        let value = match result {
+            Ok(value) => value,
+            // 'try again' is not an error worth recording
+            Err((ERROR_AGAIN, _)) => ERROR_AGAIN,
+            Err((code, message)) => {
+                self.syscall_errors.push(format!("{} at pc {}", message, pc));
+                code
+            }
+        };
+        self.set(SYSCALL_RESULT as usize, value as u64);
+    }
+
+    fn sys_write(&mut self) -> Result<i64, (i64, String)> {
+        let (stream, address, length) = (self.argument(0), self.argument(1), self.argument(2));
+        let ranges = self.buffer("write", address, length, PERMISSION_READ)?;
+        let bytes: Vec<u8> = ranges.into_iter().flat_map(|range| self.memory[range].to_vec()).collect();
+        match stream {
+            STREAM_CONSOLE => self.devices.console_write(&bytes),
+            STREAM_ERROR => self.devices.error_write(&bytes),
+            _ => return Err((ERROR_STREAM, format!("write: invalid stream {}", stream))),
+        }
+        Ok(length as i64)
+    }
+
+    fn sys_read(&mut self) -> Result<i64, (i64, String)> {
+        let (stream, address, length) = (self.argument(0), self.argument(1), self.argument(2));
+        if stream != STREAM_INPUT {
+            return Err((ERROR_STREAM, format!("read: invalid stream {}", stream)));
+        }
+        let ranges = self.buffer("read", address, length, PERMISSION_WRITE)?;
+        let mut total = 0;
+        for range in ranges {
+            let count = range.len().min(self.devices.input.len());
+            self.invalidate_reservations(range.start, count);
+            for (slot, byte) in self.memory[range].iter_mut().zip(self.devices.input.drain(..count)) {
+                *slot = byte;
+            }
+            total += count;
+        }
+        Ok(total as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::run::{RunStatus};
+    use crate::services::assembler::{assemble};
+
+    #[test]
+    fn dispatch_and_unknown_services() {
+        let program = assemble("
+            add r2, r0, 0
+            li r3, 0x100
+            add r4, r0, 4
+            ecall 2
+            add r10, r1, 0
+            add r2, r0, 1
+            ecall 1
+            add r11, r1, 0
+            add r2, r0, 7
+            ecall 1
+            add r12, r1, 0
+            add r1, r0, 42
+            ecall
+            add r13, r1, 0
+            add r2, r0, 3
+            ecall 0
+            halt", &|name: &str| Err(format!("no program '{}'", name))).unwrap().instructions;
+        let mut machine = Machine::new(program);
+        machine.devices.input.extend(b"ping");
+        assert_eq!(machine.run(1_000), RunStatus::Halted);
+
+        // read, then write the same buffer back, number 42 comes from r1
+        assert_eq!(&machine.memory[0x100..0x104], b"ping");
+        assert_eq!(machine.devices.console_text(), "ping");
+        assert_eq!(&machine.registers[10..14], &[4, 4, ERROR_STREAM as u64, ERROR_SERVICE as u64]);
+        assert_eq!(machine.exit_code, Some(3));
+        // exit halts at the ecall
+        assert_eq!(machine.pc, 15);
+        assert_eq!(machine.syscall_errors, vec![
+            "write: invalid stream 7 at pc 9".to_string(),
+            "unknown syscall 42 at pc 12".to_string(),
+        ]);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::models::float::{RoundingMode};
+use crate::models::vector::{Lanes};
+use crate::services::float::{self, FloatOp};
+
+// SIMD support. there are 32 vector registers of 128 bits (v0..v31), every opcode names the
+// lane type it works on with a suffix: .i8 (16 lanes), .i16, .i32, .i64, .f32 (4 lanes), .f64.
+//
+//  vadd, vsub, vmul, vmin, vmax         vd = vs <op> operand      lane-wise
+//  vdiv                                 vd = vs / operand         float lanes only
+//  vand, vor, vxor                      vd = vs <op> operand      on the raw bits
+//  vshl, vshr, vsar                     vd = vs <shift> operand   integer lanes only
+//  vsplat                               vd = rs / fs in every lane
+//  vins                                 vd[imdval] = rs / fs
+//  vext                                 rd / fd = vs[imdval]
+//  vshuf                                vd[i] = vs[pattern[i]]
+//  vredsum, vredmin, vredmax            rd / fd = horizontal reduction of vs
+//  vld / vst                            vd = memory[rs + imdval] / memory[rs + imdval] = vx  (16 bytes)
+//
+// the operand is an imdval broadcast to every lane when one is given and vx otherwise.
+// integer lanes wrap and compare signed, float lanes follow the FP rounding mode.
+// the pattern of vshuf is an imdval with one hex digit per lane, lowest lane first
+// ("0x0123" reverses four lanes); without an imdval the lanes of vx are the indexes and
+// out of range indexes select 0. integer reductions are computed in 64 bits.
+
+pub type Vector = u128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VecOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Min,
+    Max,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Sar,
+}
+
+impl VecOp {
+
+    // 'base' is the opcode without its lane suffix
+    pub fn from_base(base: &str) -> Option<VecOp> {
+        match base {
+            "vadd" => Some(VecOp::Add),
+            "vsub" => Some(VecOp::Sub),
+            "vmul" => Some(VecOp::Mul),
+            "vdiv" => Some(VecOp::Div),
+            "vmin" => Some(VecOp::Min),
+            "vmax" => Some(VecOp::Max),
+            "vand" => Some(VecOp::And),
+            "vor" => Some(VecOp::Or),
+            "vxor" => Some(VecOp::Xor),
+            "vshl" => Some(VecOp::Shl),
+            "vshr" => Some(VecOp::Shr),
+            "vsar" => Some(VecOp::Sar),
+            _ => None,
+        }
+    }
+
+    fn float(self) -> Option<FloatOp> {
+        match self {
+            VecOp::Add => Some(FloatOp::Add),
+            VecOp::Sub => Some(FloatOp::Sub),
+            VecOp::Mul => Some(FloatOp::Mul),
+            VecOp::Div => Some(FloatOp::Div),
+            VecOp::Min => Some(FloatOp::Min),
+            VecOp::Max => Some(FloatOp::Max),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reduction {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Reduction {
+    pub fn from_base(base: &str) -> Option<Reduction> {
+        match base {
+            "vredsum" => Some(Reduction::Sum),
+            "vredmin" => Some(Reduction::Min),
+            "vredmax" => Some(Reduction::Max),
+            _ => None,
+        }
+    }
+}
+
+fn mask(lanes: Lanes) -> u64 {
+    u64::MAX >> (64 - lanes.width())
+}
+
+fn sign_extend(value: u64, lanes: Lanes) -> i64 {
+    let shift = 64 - lanes.width();
+    ((value << shift) as i64) >> shift
+}
+
+pub fn lane(vector: Vector, lanes: Lanes, index: usize) -> u64 {
+    (vector >> (index as u32 * lanes.width())) as u64 & mask(lanes)
+}
+
+pub fn with_lane(vector: Vector, lanes: Lanes, index: usize, value: u64) -> Vector {
+    let shift = index as u32 * lanes.width();
+    let mask = (mask(lanes) as Vector) << shift;
+    (vector & !mask) | (((value as Vector) << shift) & mask)
+}
+
+fn from_lanes(lanes: Lanes, value: impl Fn(usize) -> u64) -> Vector {
+    (0..lanes.count()).fold(0, |vector, index| with_lane(vector, lanes, index, value(index)))
+}
+
+fn float_lane(bits: u64, lanes: Lanes) -> f64 {
+    match lanes {
+        Lanes::F32 => f32::from_bits(bits as u32) as f64,
+        _ => f64::from_bits(bits),
+    }
+}
+
+fn float_bits(value: f64, lanes: Lanes) -> u64 {
+    match lanes {
+        Lanes::F32 => (value as f32).to_bits() as u64,
+        _ => value.to_bits(),
+    }
+}
+
+// scalar register contents (an integer, or the bits of an f64 for float lanes) to lane bits
+pub fn to_lane(lanes: Lanes, scalar: u64) -> u64 {
+    if lanes.is_float() {
+        float_bits(f64::from_bits(scalar), lanes)
+    } else {
+        scalar & mask(lanes)
+    }
+}
+
+// the inverse of to_lane, integer lanes are sign extended
+pub fn from_lane(lanes: Lanes, bits: u64) -> u64 {
+    if lanes.is_float() {
+        float_lane(bits, lanes).to_bits()
+    } else {
+        sign_extend(bits, lanes) as u64
+    }
+}
+
+pub fn splat(lanes: Lanes, bits: u64) -> Vector {
+    from_lanes(lanes, |_| bits)
+}
+
+fn integer(op: VecOp, lanes: Lanes, a: u64, b: u64) -> u64 {
+    let shift = (b % lanes.width() as u64) as u32;
+    match op {
+        VecOp::Add => a.wrapping_add(b),
+        VecOp::Sub => a.wrapping_sub(b),
+        VecOp::Mul => a.wrapping_mul(b),
+        VecOp::Min => sign_extend(a, lanes).min(sign_extend(b, lanes)) as u64,
+        VecOp::Max => sign_extend(a, lanes).max(sign_extend(b, lanes)) as u64,
+        VecOp::And => a & b,
+        VecOp::Or => a | b,
+        VecOp::Xor => a ^ b,
+        VecOp::Shl => a << shift,
+        VecOp::Shr => a >> shift,
+        VecOp::Sar => (sign_extend(a, lanes) >> shift) as u64,
+        // rejected by the decoder
+        VecOp::Div => 0,
+    }
+}
+
+// float lanes raise their exceptions in 'flags' (fflags), like the scalar opcodes
+pub fn lanewise(op: VecOp, lanes: Lanes, lhs: Vector, rhs: Vector, mode: RoundingMode, flags: &mut u64) -> Vector {
+    match (op.float(), lanes.is_float()) {
+        (Some(float_op), true) => (0..lanes.count()).fold(0, |vector, index| {
+            let a = float_lane(lane(lhs, lanes, index), lanes);
+            let b = float_lane(lane(rhs, lanes, index), lanes);
+            let value = float::arithmetic(float_op, a, b, lanes == Lanes::F64, mode, flags);
+            with_lane(vector, lanes, index, float_bits(value, lanes))
+        }),
+        _ => from_lanes(lanes, |index| integer(op, lanes, lane(lhs, lanes, index), lane(rhs, lanes, index))),
+    }
+}
+
+pub fn shuffle(lanes: Lanes, vector: Vector, pattern: u64) -> Vector {
+    from_lanes(lanes, |index| {
+        let from = (pattern >> (index * 4)) as usize & 0xf;
+        lane(vector, lanes, from % lanes.count())
+    })
+}
+
+pub fn swizzle(lanes: Lanes, vector: Vector, indexes: Vector) -> Vector {
+    from_lanes(lanes, |index| {
+        let from = lane(indexes, lanes, index);
+        if from < lanes.count() as u64 { lane(vector, lanes, from as usize) } else { 0 }
+    })
+}
+
+// the result uses the from_lane convention
+pub fn reduce(reduction: Reduction, lanes: Lanes, vector: Vector, mode: RoundingMode, flags: &mut u64) -> u64 {
+    if lanes.is_float() {
+        let op = match reduction {
+            Reduction::Sum => FloatOp::Add,
+            Reduction::Min => FloatOp::Min,
+            Reduction::Max => FloatOp::Max,
+        };
+        let double = lanes == Lanes::F64;
+        let value = (1..lanes.count()).fold(float_lane(lane(vector, lanes, 0), lanes), |acc, index| {
+            float::arithmetic(op, acc, float_lane(lane(vector, lanes, index), lanes), double, mode, flags)
+        });
+        return value.to_bits();
+    }
+    let values = (0..lanes.count()).map(|index| sign_extend(lane(vector, lanes, index), lanes));
+    let value = match reduction {
+        Reduction::Sum => values.fold(0i64, |acc, value| acc.wrapping_add(value)),
+        Reduction::Min => values.min().unwrap_or(0),
+        Reduction::Max => values.max().unwrap_or(0),
+    };
+    value as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn i8_lanes(values: [i8; 16]) -> Vector {
+        from_lanes(Lanes::I8, |index| values[index] as u8 as u64)
+    }
+
+    #[test]
+    fn integer_lanes_wrap_without_carrying() {
+        let mut flags = 0;
+        let lhs = i8_lanes([127, -128, -1, 100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let rhs = splat(Lanes::I8, 1);
+        let sum = lanewise(VecOp::Add, Lanes::I8, lhs, rhs, RoundingMode::NearestEven, &mut flags);
+        assert_eq!(sum, i8_lanes([-128, -127, 0, 101, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2]));
+        let difference = lanewise(VecOp::Sub, Lanes::I8, lhs, rhs, RoundingMode::NearestEven, &mut flags);
+        assert_eq!(from_lane(Lanes::I8, lane(difference, Lanes::I8, 1)) as i64, 127);
+        let product = lanewise(VecOp::Mul, Lanes::I8, lhs, splat(Lanes::I8, 3), RoundingMode::NearestEven, &mut flags);
+        assert_eq!(from_lane(Lanes::I8, lane(product, Lanes::I8, 3)) as i64, 300 - 256);
+
+        // min and max compare signed
+        let min = lanewise(VecOp::Min, Lanes::I8, lhs, rhs, RoundingMode::NearestEven, &mut flags);
+        assert_eq!(from_lane(Lanes::I8, lane(min, Lanes::I8, 1)) as i64, -128);
+        assert_eq!(from_lane(Lanes::I8, lane(min, Lanes::I8, 0)) as i64, 1);
+
+        // reductions are computed in 64 bits and do not wrap
+        let sum = reduce(Reduction::Sum, Lanes::I8, splat(Lanes::I8, 127), RoundingMode::NearestEven, &mut flags);
+        assert_eq!(sum as i64, 16 * 127);
+        assert_eq!(reduce(Reduction::Min, Lanes::I8, lhs, RoundingMode::NearestEven, &mut flags) as i64, -128);
+        assert_eq!(flags, 0);
+    }
+}